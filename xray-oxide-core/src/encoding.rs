@@ -0,0 +1,85 @@
+use std::borrow::Cow;
+
+pub use encoding_rs::{Encoding, UTF_8, WINDOWS_1251};
+
+/// The codepage the original games shipped their text and file names in.
+pub const DEFAULT_CODEPAGE: &Encoding = WINDOWS_1251;
+
+const UTF8_BOM: &[u8] = &[0xEF, 0xBB, 0xBF];
+
+/// Looks up a codepage by its label, e.g. `windows-1251`, `cp1251`, `1251` or `utf-8`.
+pub fn codepage_for_label(label: &str) -> Option<&'static Encoding> {
+    let label = label.trim();
+
+    if !label.is_empty() && label.bytes().all(|b| b.is_ascii_digit()) {
+        Encoding::for_label(format!("windows-{label}").as_bytes())
+    } else {
+        Encoding::for_label(label.as_bytes())
+    }
+}
+
+/// Decodes text read from game files.
+///
+/// Text with a UTF-8 byte order mark, or non-ASCII text that is valid UTF-8, is
+/// decoded as UTF-8, as modern mods ship it that way. Everything else is decoded
+/// with `codepage`, which never fails.
+pub fn decode<'a>(bytes: &'a [u8], codepage: &'static Encoding) -> Cow<'a, str> {
+    if let Some(bytes) = bytes.strip_prefix(UTF8_BOM) {
+        return UTF_8.decode_without_bom_handling(bytes).0;
+    }
+
+    if let Ok(text) = std::str::from_utf8(bytes) {
+        return Cow::Borrowed(text);
+    }
+
+    codepage.decode_without_bom_handling(bytes).0
+}
+
+/// Encodes text for game files with `codepage`.
+///
+/// Text the codepage can't represent is written as UTF-8 instead, which
+/// [`decode`] reads back unchanged.
+pub fn encode<'a>(text: &'a str, codepage: &'static Encoding) -> Cow<'a, [u8]> {
+    let (bytes, _, had_errors) = codepage.encode(text);
+
+    if had_errors {
+        Cow::Borrowed(text.as_bytes())
+    } else {
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let cp1251 = [0xEF, 0xF0, 0xE8, 0xE2, 0xE5, 0xF2];
+        assert_eq!(decode(&cp1251, DEFAULT_CODEPAGE), "привет");
+
+        let utf8 = "привет".as_bytes();
+        assert_eq!(decode(utf8, DEFAULT_CODEPAGE), "привет");
+
+        let bom = [UTF8_BOM, utf8].concat();
+        assert_eq!(decode(&bom, DEFAULT_CODEPAGE), "привет");
+    }
+
+    #[test]
+    fn test_encode() {
+        let cp1251 = encode("привет", DEFAULT_CODEPAGE);
+        assert_eq!(*cp1251, [0xEF, 0xF0, 0xE8, 0xE2, 0xE5, 0xF2]);
+        assert_eq!(decode(&cp1251, DEFAULT_CODEPAGE), "привет");
+
+        let utf8 = encode("привет ✓", DEFAULT_CODEPAGE);
+        assert_eq!(decode(&utf8, DEFAULT_CODEPAGE), "привет ✓");
+    }
+
+    #[test]
+    fn test_codepage_for_label() {
+        assert_eq!(codepage_for_label("1251"), Some(WINDOWS_1251));
+        assert_eq!(codepage_for_label("cp1251"), Some(WINDOWS_1251));
+        assert_eq!(codepage_for_label("utf-8"), Some(UTF_8));
+        assert_eq!(codepage_for_label("nope"), None);
+    }
+}
@@ -2,12 +2,29 @@ use std::fs::Metadata;
 
 pub trait StrExt {
     fn is_bool_true(&self) -> bool;
+    fn is_bool_false(&self) -> bool;
+    /// Parses one of XRay's boolean spellings, `None` for anything else.
+    fn parse_bool(&self) -> Option<bool>;
 }
 
 impl StrExt for str {
     fn is_bool_true(&self) -> bool {
         self == "on" || self == "yes" || self == "true" || self == "1"
     }
+
+    fn is_bool_false(&self) -> bool {
+        self == "off" || self == "no" || self == "false" || self == "0"
+    }
+
+    fn parse_bool(&self) -> Option<bool> {
+        if self.is_bool_true() {
+            Some(true)
+        } else if self.is_bool_false() {
+            Some(false)
+        } else {
+            None
+        }
+    }
 }
 
 pub trait MetadataExt {
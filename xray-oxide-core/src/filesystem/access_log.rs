@@ -0,0 +1,115 @@
+use std::{
+    fmt::{Display, Formatter},
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::filesystem::Filesystem;
+
+/// A single file read made through the [`Filesystem`].
+#[derive(Debug, Clone)]
+pub struct FileAccess {
+    pub path: PathBuf,
+    pub source: FileSource,
+    pub size: usize,
+    pub time: SystemTime,
+}
+
+/// Where the bytes of an accessed file came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileSource {
+    /// A loose file, with the most specific alias containing it.
+    Loose { alias: Option<PathBuf> },
+    /// A file packed in the archive at this path.
+    Archive(PathBuf),
+}
+
+impl Display for FileSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FileSource::Loose { alias: Some(alias) } => write!(f, "{}", alias.display()),
+            FileSource::Loose { alias: None } => write!(f, "-"),
+            FileSource::Archive(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct AccessLog {
+    records: Mutex<Vec<FileAccess>>,
+}
+
+impl AccessLog {
+    fn record(&self, access: FileAccess) {
+        self.records.lock().unwrap().push(access);
+    }
+
+    fn records(&self) -> Vec<FileAccess> {
+        self.records.lock().unwrap().clone()
+    }
+}
+
+impl Filesystem {
+    pub(crate) fn log_access(&self, path: &Path, source: FileSource, size: usize) {
+        if let Some(log) = &self.access_log {
+            log.record(FileAccess {
+                path: path.to_path_buf(),
+                source,
+                size,
+                time: SystemTime::now(),
+            });
+        }
+    }
+
+    pub(crate) fn log_loose_access(&self, path: &Path, size: usize) {
+        if self.access_log.is_none() {
+            return;
+        }
+
+        let alias = self
+            .aliases()
+            .filter(|(_, fs_path)| path.starts_with(fs_path.path()))
+            .max_by_key(|(_, fs_path)| fs_path.path().components().count())
+            .map(|(alias, _)| alias.to_path_buf());
+
+        self.log_access(path, FileSource::Loose { alias }, size);
+    }
+
+    /// Returns every file read so far, in order, if the access log is enabled.
+    pub fn accessed_files(&self) -> Option<Vec<FileAccess>> {
+        self.access_log.as_ref().map(AccessLog::records)
+    }
+
+    /// Writes the access log to the file set in [`FilesystemConfig::access_log`](super::FilesystemConfig::access_log).
+    ///
+    /// Every line holds the time in seconds since the Unix epoch, the source,
+    /// the byte count and the path, separated by tabs.
+    pub fn dump_access_log(&self) -> anyhow::Result<()> {
+        let (Some(log), Some(path)) = (&self.access_log, &self.config.access_log) else {
+            return Ok(());
+        };
+
+        log::info!("Writing file access log to {}", path.display());
+
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        for access in log.records() {
+            let time = access.time.duration_since(UNIX_EPOCH)?.as_secs_f64();
+
+            writeln!(
+                writer,
+                "{time:.3}\t{}\t{}\t{}",
+                access.source,
+                access.size,
+                access.path.display()
+            )?;
+        }
+
+        writer.flush()?;
+
+        Ok(())
+    }
+}
@@ -1,33 +1,44 @@
 use std::{
     fs::File,
-    io::{BufReader, Cursor, Read, Seek},
+    io::BufReader,
     mem::size_of,
     path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
 };
 
-use byteorder::{LittleEndian, ReadBytesExt};
-use ini::Ini;
-use local_encoding::{Encoder, Encoding};
 use memmap2::{Mmap, MmapOptions};
 
-use crate::{ext::StrExt, filesystem::Filesystem, lzhuf};
+use crate::{
+    encoding::{self, Encoding},
+    filesystem::{access_log::FileSource, FileTable, Filesystem},
+    ltx::Ltx,
+    stream::Reader,
+};
 
 pub struct Archive {
     path: PathBuf,
     index: usize,
-    header: Ini,
+    header: Option<Ltx>,
     size: usize,
+    modified: SystemTime,
+    /// The file table, kept to register the files again when the file table is rebuilt.
+    files: Vec<ArchiveTableEntry>,
 }
 
 impl Archive {
     pub fn new(path: PathBuf, index: usize) -> anyhow::Result<Archive> {
-        let size = File::open(&path)?.metadata()?.len() as usize;
+        let metadata = File::open(&path)?.metadata()?;
+        let size = metadata.len() as usize;
+        let modified = metadata.modified()?;
 
         Ok(Archive {
             path,
             index,
-            header: Ini::new(),
+            header: None,
             size,
+            modified,
+            files: Vec::new(),
         })
     }
 
@@ -39,18 +50,22 @@ impl Archive {
         self.index
     }
 
-    pub fn header(&self) -> &Ini {
-        &self.header
+    pub fn header(&self) -> Option<&Ltx> {
+        self.header.as_ref()
     }
 
-    pub fn set_header(&mut self, header: Ini) {
-        self.header = header;
+    pub fn set_header(&mut self, header: Ltx) {
+        self.header = Some(header);
     }
 
     pub fn size(&self) -> usize {
         self.size
     }
 
+    pub fn modified(&self) -> SystemTime {
+        self.modified
+    }
+
     pub fn open(&self) -> anyhow::Result<BufReader<File>> {
         Ok(BufReader::new(File::open(&self.path)?))
     }
@@ -68,13 +83,15 @@ impl Archive {
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct VirtualFile {
     name: PathBuf,
     archive: Option<usize>,
     size_real: usize,
     size_compressed: usize,
     ptr: usize,
+    modified: SystemTime,
+    directory: bool,
 }
 
 impl VirtualFile {
@@ -84,6 +101,7 @@ impl VirtualFile {
         size_real: usize,
         size_compressed: usize,
         ptr: usize,
+        modified: SystemTime,
     ) -> VirtualFile {
         VirtualFile {
             name,
@@ -91,11 +109,20 @@ impl VirtualFile {
             size_real,
             size_compressed,
             ptr,
+            modified,
+            directory: false,
+        }
+    }
+
+    pub fn directory(name: PathBuf, archive: Option<usize>, modified: SystemTime) -> VirtualFile {
+        VirtualFile {
+            directory: true,
+            ..VirtualFile::new(name, archive, 0, 0, 0, modified)
         }
     }
 
     pub fn only_name(name: PathBuf) -> VirtualFile {
-        VirtualFile::new(name, None, 0, 0, 0)
+        VirtualFile::new(name, None, 0, 0, 0, SystemTime::UNIX_EPOCH)
     }
 
     pub fn name(&self) -> &PathBuf {
@@ -105,66 +132,162 @@ impl VirtualFile {
     pub fn archive(&self) -> Option<usize> {
         self.archive
     }
+
+    pub fn is_directory(&self) -> bool {
+        self.directory
+    }
+
+    pub fn size_real(&self) -> usize {
+        self.size_real
+    }
+
+    /// Modification time of the loose file, or of the archive the file is packed in.
+    pub fn modified(&self) -> SystemTime {
+        self.modified
+    }
 }
 
-const ARCHIVE_COMPRESS_FLAG: u32 = 1 << 31;
 const ARCHIVE_HEADER_CHUNK_ID: u32 = 666;
+const ARCHIVE_FILES_CHUNK_ID: u32 = 1;
 
-impl Filesystem {
-    pub(crate) fn process_archive<P: AsRef<Path>>(&mut self, path: P) -> anyhow::Result<()> {
-        log::trace!("process_archive: {}", path.as_ref().display().to_string());
+/// The file table of an archive, read ahead of time so that archives can be
+/// decompressed and parsed independently before they are mounted.
+pub(crate) struct ArchiveTable {
+    path: PathBuf,
+    header: Option<Ltx>,
+    files: Vec<ArchiveTableEntry>,
+}
 
-        let path = std::fs::canonicalize(path)?;
+struct ArchiveTableEntry {
+    name: String,
+    size_real: usize,
+    size_compressed: usize,
+    ptr: usize,
+}
 
-        if self.archives.iter().any(|archive| archive.path() == &path) {
-            return Ok(());
+impl ArchiveTable {
+    pub(crate) fn read<P: AsRef<Path>>(
+        path: P,
+        codepage: &'static Encoding,
+    ) -> anyhow::Result<ArchiveTable> {
+        log::trace!(
+            "ArchiveTable::read: {}",
+            path.as_ref().display().to_string()
+        );
+
+        let path = path.as_ref().to_path_buf();
+
+        let map = unsafe { Mmap::map(&File::open(&path)?) }?;
+        let reader = Reader::new(&map).with_codepage(codepage);
+
+        let header = if let Some(header) = reader.open_chunk(ARCHIVE_HEADER_CHUNK_ID)? {
+            let header = encoding::decode(header.data(), codepage);
+            Some(Ltx::parse(&path, &header)?)
+        } else {
+            None
+        };
+
+        let mut table = ArchiveTable {
+            path,
+            header,
+            files: Vec::new(),
+        };
+
+        if table.auto_load() {
+            table.read_files(&reader)?;
         }
 
-        let index = self.archives.len();
+        Ok(table)
+    }
 
-        self.archives.push(Archive::new(path, index)?);
+    fn auto_load(&self) -> bool {
+        self.header
+            .as_ref()
+            .map(|header| header.r_bool("header", "auto_load").unwrap_or(false))
+            .unwrap_or(true)
+    }
 
-        let archive = self.archives.last_mut().unwrap();
+    fn read_files(&mut self, reader: &Reader) -> anyhow::Result<()> {
+        let mut chunk = reader
+            .open_chunk(ARCHIVE_FILES_CHUNK_ID)?
+            .ok_or_else(|| anyhow::anyhow!("{} has no file table", self.path.display()))?;
+        log::trace!("ArchiveTable::read_files: opened chunk");
+
+        while !chunk.eof() {
+            let entry_size = chunk.r_u16()? as usize;
+            let mut entry = Reader::new(chunk.r_bytes(entry_size)?);
+
+            let size_real = entry.r_u32()?;
+            let size_compressed = entry.r_u32()?;
+            let _crc = entry.r_u32()?;
+            let name_length = entry_size.saturating_sub(4 * size_of::<u32>());
+            let name =
+                encoding::decode(entry.r_bytes(name_length)?, reader.codepage()).into_owned();
+            let ptr = entry.r_u32()?;
+
+            self.files.push(ArchiveTableEntry {
+                name,
+                size_real: size_real as usize,
+                size_compressed: size_compressed as usize,
+                ptr: ptr as usize,
+            });
+        }
+        log::trace!("ArchiveTable::read_files: collected buffers");
+
+        Ok(())
+    }
+}
 
-        let mut reader = archive.open()?;
+impl Filesystem {
+    pub(crate) fn process_archive(
+        &self,
+        files: &mut FileTable,
+        mut table: ArchiveTable,
+    ) -> anyhow::Result<()> {
+        log::trace!("process_archive: {}", table.path.display().to_string());
+
+        if files
+            .archives
+            .iter()
+            .any(|archive| archive.path() == &table.path)
+        {
+            return Ok(());
+        }
 
-        let header = open_chunk(&mut reader, ARCHIVE_HEADER_CHUNK_ID)?;
+        let index = files.archives.len();
 
-        let load = if let Some(header) = header {
-            let header = String::from_utf8(header)?;
-            let header = Ini::load_from_str_noescape(&header)?;
+        let mut archive = Archive::new(table.path.clone(), index)?;
 
+        if let Some(header) = table.header.take() {
             archive.set_header(header);
+        }
 
-            archive
-                .header()
-                .get_from(Some("header"), "auto_load")
-                .map(StrExt::is_bool_true)
-                .unwrap_or(false)
-        } else {
-            true
-        };
+        archive.files = table.files;
 
-        if load {
-            self.load_archive(index)
-        } else {
-            Ok(())
-        }
+        let archive = Arc::new(archive);
+        files.archives.push(archive.clone());
+
+        self.load_archive(files, &archive)
     }
 
-    fn load_archive(&mut self, index: usize) -> anyhow::Result<()> {
-        log::trace!("load_archive: {}", index);
+    /// Registers the files of a mounted archive. Archives that aren't loaded
+    /// automatically have no files in their table.
+    pub(crate) fn load_archive(
+        &self,
+        files: &mut FileTable,
+        archive: &Archive,
+    ) -> anyhow::Result<()> {
+        log::trace!("load_archive: {}", archive.index());
 
-        let archive = self.archives.get_mut(index).unwrap();
+        if archive.files.is_empty() {
+            return Ok(());
+        }
 
-        if archive.header().is_empty() {
+        let Some(header) = archive.header() else {
             panic!("unsupported");
-        }
+        };
 
-        let entry_point = archive
-            .header()
-            .get_from(Some("header"), "entry_point")
-            .unwrap();
+        let entry_point = header.r_string("header", "entry_point")?;
 
         let entry_point = if entry_point == "gamedata" {
             todo!();
@@ -183,51 +306,25 @@ impl Filesystem {
             read_path
         };
 
-        let mut reader = archive.open()?;
-
-        let archive_index = archive.index();
-
-        let chunk = open_chunk(&mut reader, 1)?.unwrap();
-        log::trace!("load_archive: opened chunk");
-        let buffers = ChunkBuffersIter::new(chunk).collect::<Vec<_>>();
-        log::trace!("load_archive: collected buffers");
-
-        buffers
-            .into_iter()
-            .map(|mut buffer| {
-                let buffer_size = buffer.get_ref().len();
-
-                let size_real = buffer.read_u32::<LittleEndian>()?;
-                let size_compressed = buffer.read_u32::<LittleEndian>()?;
-                let _crc = buffer.read_u32::<LittleEndian>()?;
-                let name_length = buffer_size - 4 * size_of::<u32>();
-
-                let mut name = vec![0; name_length];
-                buffer.read_exact(name.as_mut_slice())?;
-                let name = Encoding::ANSI.to_string(&name)?;
-
-                let ptr = buffer.read_u32::<LittleEndian>()?;
-
-                let mut path = entry_point.clone();
-                path.push(name);
-
-                self.register(
-                    path,
-                    Some(archive_index),
-                    size_real as usize,
-                    size_compressed as usize,
-                    ptr as usize,
-                )?;
-
-                Ok(())
-            })
-            .collect::<anyhow::Result<Vec<_>>>()?;
+        for file in &archive.files {
+            let mut path = entry_point.clone();
+            path.push(&file.name);
+
+            files.register(
+                path,
+                Some(archive.index()),
+                file.size_real,
+                file.size_compressed,
+                file.ptr,
+                archive.modified(),
+            )?;
+        }
 
         Ok(())
     }
 
     pub fn file_from_archive(&self, archive: usize, file: &VirtualFile) -> anyhow::Result<Vec<u8>> {
-        let archive = &self.archives[archive];
+        let archive = self.snapshot().archives[archive].clone();
 
         let map = archive.map(file.ptr, Some(file.size_compressed))?;
 
@@ -239,6 +336,12 @@ impl Filesystem {
             lzo1x_1::decompress_to_slice(&map, &mut buffer)?;
         }
 
+        self.log_access(
+            file.name(),
+            FileSource::Archive(archive.path().clone()),
+            buffer.len(),
+        );
+
         Ok(buffer)
     }
 
@@ -249,55 +352,6 @@ impl Filesystem {
     ) -> anyhow::Result<String> {
         let data = self.file_from_archive(archive, file)?;
 
-        Ok(Encoding::ANSI.to_string(&data)?)
-    }
-}
-
-struct ChunkBuffersIter<T: AsRef<[u8]>> {
-    inner: Cursor<T>,
-}
-
-impl<T: AsRef<[u8]>> ChunkBuffersIter<T> {
-    fn new(inner: T) -> Self {
-        ChunkBuffersIter {
-            inner: Cursor::new(inner),
-        }
-    }
-}
-
-impl<T: AsRef<[u8]>> Iterator for ChunkBuffersIter<T> {
-    type Item = Cursor<Vec<u8>>;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        let buffer_size = self.inner.read_u16::<LittleEndian>().ok()? as usize;
-        let mut buffer = vec![0; buffer_size];
-
-        self.inner.read_exact(buffer.as_mut_slice()).ok()?;
-
-        Some(Cursor::new(buffer))
-    }
-}
-
-fn open_chunk<R: Read + Seek>(
-    reader: &mut BufReader<R>,
-    id: u32,
-) -> anyhow::Result<Option<Vec<u8>>> {
-    loop {
-        let ty = reader.read_u32::<LittleEndian>()?;
-        let size = reader.read_u32::<LittleEndian>()?;
-
-        if (ty & !ARCHIVE_COMPRESS_FLAG) == id {
-            let mut source_data = vec![0; size as usize];
-
-            reader.read_exact(source_data.as_mut_slice())?;
-
-            return if (ty & ARCHIVE_COMPRESS_FLAG) > 0 {
-                Ok(Some(lzhuf::decompress(Cursor::new(source_data))?))
-            } else {
-                Ok(Some(source_data))
-            };
-        } else {
-            reader.seek_relative(size as i64)?;
-        }
+        Ok(encoding::decode(&data, self.codepage()).into_owned())
     }
 }
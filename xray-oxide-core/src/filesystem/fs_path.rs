@@ -1,5 +1,4 @@
-use crate::filesystem::Filesystem;
-use std::fs::read_to_string;
+use crate::{encoding, filesystem::Filesystem};
 use std::path::{Path, PathBuf};
 use thiserror::Error;
 
@@ -47,10 +46,38 @@ impl FSPath {
         &self.root
     }
 
+    pub fn add(&self) -> Option<&PathBuf> {
+        self.add.as_ref()
+    }
+
+    pub fn def_ext(&self) -> Option<&str> {
+        self.def_ext.as_deref()
+    }
+
+    pub fn filter_caption(&self) -> Option<&str> {
+        self.filter_caption.as_deref()
+    }
+
     pub fn recurse(&self) -> bool {
         self.recurse
     }
 
+    pub fn notify(&self) -> bool {
+        self.notify
+    }
+
+    /// The same alias with its root replaced by a resolved path.
+    pub(crate) fn rooted_at(self, root: PathBuf) -> FSPath {
+        FSPath::new(
+            root,
+            self.add,
+            self.def_ext,
+            self.filter_caption,
+            self.recurse,
+            self.notify,
+        )
+    }
+
     pub fn appended<P: AsRef<Path>>(&self, to_append: P) -> PathBuf {
         let mut path = self.path.clone();
         path.push(to_append);
@@ -72,6 +99,50 @@ impl Filesystem {
         self.paths.get_mut(path.as_ref())
     }
 
+    /// Iterates over all aliases declared in `fsgame.ltx`, sorted by name.
+    pub fn aliases(&self) -> impl Iterator<Item = (&Path, &FSPath)> {
+        let mut aliases = self
+            .paths
+            .iter()
+            .map(|(alias, path)| (alias.as_path(), path))
+            .collect::<Vec<_>>();
+
+        aliases.sort_by_key(|(alias, _)| *alias);
+
+        aliases.into_iter()
+    }
+
+    /// Resolves the full path of an alias.
+    pub fn alias_path(&self, alias: &str) -> anyhow::Result<PathBuf> {
+        let path = self
+            .get_path(alias)
+            .ok_or_else(|| FilesystemFSPathError::UnknownAlias {
+                alias: alias.to_owned(),
+            })?;
+
+        Ok(path.path().clone())
+    }
+
+    /// Resolves a virtual path like `$game_config$\weathers\default.ltx`,
+    /// the same way XRay's `update_path` does.
+    ///
+    /// Both `\` and `/` are accepted as separators. Paths that don't start
+    /// with an alias are returned as they are.
+    pub fn update_path(&self, path: &str) -> anyhow::Result<PathBuf> {
+        let (alias, rest) = match split_alias(path) {
+            Some(split) => split,
+            None => return Ok(PathBuf::from(path)),
+        };
+
+        let mut result = self.alias_path(alias)?;
+
+        for component in rest.split(['\\', '/']).filter(|c| !c.is_empty()) {
+            result.push(component);
+        }
+
+        Ok(result)
+    }
+
     pub fn append_path<P1: AsRef<Path>, P2: AsRef<Path>>(
         &self,
         initial: P1,
@@ -80,23 +151,71 @@ impl Filesystem {
         self.get_path(initial).map(|p| p.appended(append))
     }
 
-    pub fn read_to_string<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<String> {
+    /// Reads a whole file, loose or packed in an archive.
+    pub fn read<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<Vec<u8>> {
         let path = path.as_ref().to_path_buf();
 
         let file = self
-            .files
-            .get(&path)
+            .get_file(&path)
             .ok_or(FilesystemFSPathError::NotFound { path })?;
 
-        Ok(match file.archive() {
-            Some(archive) => self.string_from_archive(archive, file)?,
-            None => read_to_string(file.name())?,
-        })
+        match file.archive() {
+            Some(archive) => self.file_from_archive(archive, &file),
+            None => {
+                let data = std::fs::read(file.name())?;
+                self.log_loose_access(file.name(), data.len());
+                Ok(data)
+            }
+        }
     }
+
+    pub fn read_to_string<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<String> {
+        let data = self.read(path)?;
+
+        Ok(encoding::decode(&data, self.codepage()).into_owned())
+    }
+
+    /// Writes a loose file and registers it, creating missing directories.
+    ///
+    /// The data is written to a temporary file first and then renamed over the
+    /// old file, so a crash never leaves a half written file behind.
+    pub fn write<P: AsRef<Path>>(&self, path: P, data: &[u8]) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        log::debug!("write({})", path.display());
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+        temp_name.push(".tmp");
+        let temp = path.with_file_name(temp_name);
+
+        std::fs::write(&temp, data)?;
+        std::fs::rename(&temp, path)?;
+
+        self.register_file(path)
+    }
+
+    /// Writes text encoded with the filesystem's codepage.
+    pub fn write_string<P: AsRef<Path>>(&self, path: P, text: &str) -> anyhow::Result<()> {
+        self.write(path, &encoding::encode(text, self.codepage()))
+    }
+}
+
+fn split_alias(path: &str) -> Option<(&str, &str)> {
+    let end = path.strip_prefix('$')?.find('$')? + 2;
+
+    Some(path.split_at(end))
 }
 
 #[derive(Debug, Error)]
 pub enum FilesystemFSPathError {
     #[error("File not found {path}")]
     NotFound { path: PathBuf },
+    #[error("Unknown alias {alias}")]
+    UnknownAlias { alias: String },
+    /// Aliases rooted at each other, the first one repeated at the end.
+    #[error("Alias cycle: {}", chain.join(" -> "))]
+    AliasCycle { chain: Vec<String> },
 }
@@ -1,28 +1,175 @@
 use std::{
-    collections::HashMap,
-    fs::DirEntry,
+    collections::{HashMap, HashSet},
     path::{Path, PathBuf},
-    time::Instant,
+    sync::{Arc, Mutex},
+    time::{Instant, SystemTime},
 };
 
+use arc_swap::ArcSwap;
+use rayon::prelude::*;
 use thiserror::Error;
 
-use crate::ext::{MetadataExt, StrExt};
+use crate::{
+    encoding::{self, Encoding, DEFAULT_CODEPAGE},
+    ext::StrExt,
+};
 
-use archive::{Archive, VirtualFile};
-use fs_path::FSPath;
+use access_log::AccessLog;
+use archive::{Archive, ArchiveTable, VirtualFile};
+use fs_path::{FSPath, FilesystemFSPathError};
+use scan::ScanEntry;
+use xrignore::IgnoreRules;
 
+pub mod access_log;
 pub mod archive;
 pub mod fs_path;
+mod scan;
+mod xrignore;
 
-const DEFAULT_FS_LTX: &str = "fsgame.ltx";
+pub const DEFAULT_FS_LTX: &str = "fsgame.ltx";
 const FS_ROOT: &str = "$fs_root$";
+const FS_IGNORE: &str = "$ignore$";
+const FS_CODEPAGE: &str = "$codepage$";
+
+type ArchiveTables = HashMap<PathBuf, ArchiveTable>;
+
+#[derive(Debug, Clone)]
+pub struct FilesystemConfig {
+    /// Number of threads used to walk directories and read archive file tables.
+    /// `1` initializes on a single worker thread, `0`, the default, uses one
+    /// thread per logical CPU.
+    pub threads: usize,
+    /// Gitignore-style patterns skipped in every scanned directory, in addition to
+    /// `.xrignore` files and the `$ignore$` line of `fsgame.ltx`.
+    pub ignore: Vec<String>,
+    /// Records every file read and writes the log to this file on [`Filesystem::dump_access_log`].
+    pub access_log: Option<PathBuf>,
+    /// Codepage of archive file names and of text files that aren't UTF-8.
+    /// Can be overridden by the `$codepage$` line of `fsgame.ltx`.
+    pub codepage: &'static Encoding,
+}
+
+impl Default for FilesystemConfig {
+    fn default() -> Self {
+        FilesystemConfig {
+            threads: 0,
+            ignore: vec!["Thumbs.db".to_owned(), ".svn/".to_owned()],
+            access_log: None,
+            codepage: DEFAULT_CODEPAGE,
+        }
+    }
+}
 
 pub struct Filesystem {
     fs_root: PathBuf,
+    config: FilesystemConfig,
+    rules: IgnoreRules,
+    /// Walks directories and reads archive file tables, for the initial scan and rescans.
+    pool: rayon::ThreadPool,
     paths: HashMap<PathBuf, FSPath>,
+    files: ArcSwap<FileTable>,
+    /// What the file table is built from, in registration order. Also serializes updates.
+    sources: Mutex<Vec<ScanSource>>,
+    access_log: Option<AccessLog>,
+}
+
+/// The registered files and mounted archives.
+///
+/// Readers work on a snapshot of the table, while updates build a new table
+/// and swap it in once it is complete, so a long rescan never blocks a lookup.
+/// Archives are only ever appended, so archive indices stay valid across snapshots.
+#[derive(Clone, Default)]
+pub(crate) struct FileTable {
     files: HashMap<PathBuf, VirtualFile>,
-    archives: Vec<Archive>,
+    archives: Vec<Arc<Archive>>,
+}
+
+/// The entries of a scanned alias directory, or of a file or archive added later.
+struct ScanSource {
+    root: Option<PathBuf>,
+    recurse: bool,
+    entries: Vec<ScanEntry>,
+}
+
+impl ScanSource {
+    /// Whether this is a single file registered after startup.
+    fn is_file(&self, path: &Path) -> bool {
+        match (&self.root, &self.entries[..]) {
+            (None, [ScanEntry::File { path: file, .. }]) => file == path,
+            _ => false,
+        }
+    }
+}
+
+impl FileTable {
+    fn register<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        archive: Option<usize>,
+        size_real: usize,
+        size_compressed: usize,
+        ptr: usize,
+        modified: SystemTime,
+    ) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        log::trace!("register({}, {archive:?})", path.display());
+
+        let description = VirtualFile::new(
+            path.to_path_buf(),
+            archive,
+            size_real,
+            size_compressed,
+            ptr,
+            modified,
+        );
+
+        self.files.insert(path.to_path_buf(), description);
+
+        self.register_ancestors(path, archive)
+    }
+
+    fn archive(&self, path: &Path) -> Option<Arc<Archive>> {
+        self.archives
+            .iter()
+            .find(|archive| archive.path() == path)
+            .cloned()
+    }
+
+    fn register_directory<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        modified: SystemTime,
+    ) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        log::trace!("register_directory({})", path.display());
+
+        let description = VirtualFile::directory(path.to_path_buf(), None, modified);
+
+        self.files.insert(path.to_path_buf(), description);
+
+        self.register_ancestors(path, None)
+    }
+
+    fn register_ancestors(&mut self, path: &Path, archive: Option<usize>) -> anyhow::Result<()> {
+        let mut archive_id = archive;
+
+        for ancestor in path.ancestors().skip(1) {
+            let description =
+                VirtualFile::directory(ancestor.to_path_buf(), archive_id, SystemTime::UNIX_EPOCH);
+
+            if self
+                .files
+                .insert(ancestor.to_path_buf(), description)
+                .is_some()
+            {
+                break;
+            }
+
+            archive_id = None;
+        }
+
+        Ok(())
+    }
 }
 
 impl Filesystem {
@@ -31,15 +178,29 @@ impl Filesystem {
     }
 
     pub fn with_fs_ltx(fs_path: &str) -> anyhow::Result<Filesystem> {
+        Filesystem::with_config(fs_path, FilesystemConfig::default())
+    }
+
+    pub fn with_config(fs_path: &str, config: FilesystemConfig) -> anyhow::Result<Filesystem> {
         let fs_root = Path::new(fs_path);
         let mut fs_root = std::fs::canonicalize(fs_root)?;
         fs_root.pop();
 
+        let rules = IgnoreRules::new(&config.ignore)?;
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(config.threads)
+            .build()?;
+        let access_log = config.access_log.as_ref().map(|_| AccessLog::default());
+
         let mut fs = Filesystem {
             fs_root,
+            config,
+            rules,
+            pool,
             paths: HashMap::new(),
-            files: HashMap::new(),
-            archives: Vec::new(),
+            files: ArcSwap::from_pointee(FileTable::default()),
+            sources: Mutex::new(Vec::new()),
+            access_log,
         };
 
         fs.initialize(fs_path)?;
@@ -53,7 +214,11 @@ impl Filesystem {
 
         let fs_path = fs_path.as_ref();
 
-        let fs_ltx = std::fs::read_to_string(fs_path)?;
+        let fs_ltx = std::fs::read(fs_path)?;
+        let fs_ltx = encoding::decode(&fs_ltx, self.config.codepage);
+
+        let mut declarations = Vec::new();
+        let mut ignore = self.config.ignore.clone();
 
         for (line_idx, line) in fs_ltx
             .lines()
@@ -66,6 +231,27 @@ impl Filesystem {
             }
 
             let (id, values) = line.split_once('=').unwrap();
+
+            if id.trim() == FS_CODEPAGE {
+                self.config.codepage = encoding::codepage_for_label(values).ok_or_else(|| {
+                    FilesystemError::UnknownCodepage {
+                        codepage: values.trim().to_owned(),
+                    }
+                })?;
+                continue;
+            }
+
+            if id.trim() == FS_IGNORE {
+                ignore.extend(
+                    values
+                        .split('|')
+                        .map(str::trim)
+                        .filter(|pattern| !pattern.is_empty())
+                        .map(str::to_owned),
+                );
+                continue;
+            }
+
             let id = PathBuf::from(id.trim());
             let mut values = values.trim().split('|').map(str::trim);
 
@@ -100,25 +286,32 @@ impl Filesystem {
             let def_ext = values.next().map(str::to_owned);
             let filter_caption = values.next().map(str::to_owned);
 
-            let root_path = self.paths.get(&root);
-            let root_path = if root_path.is_none() && root.as_os_str() == FS_ROOT {
-                let p = FSPath::new(self.fs_root.clone(), None, None, None, false, false);
+            declarations.push((
+                id,
+                FSPath::new(root, add, def_ext, filter_caption, recurse, notify),
+            ));
+        }
 
-                self.paths.insert(PathBuf::from(FS_ROOT), p);
+        let scan_paths = self.resolve_aliases(declarations)?;
 
-                self.paths.get(&root)
-            } else {
-                root_path
-            };
+        self.rules = IgnoreRules::new(&ignore)?;
 
-            let root = root_path.map(|path| path.path().clone()).unwrap_or(root);
+        let mut files = FileTable::default();
 
-            let path = FSPath::new(root, add, def_ext, filter_caption, recurse, notify);
+        let (scans, mut tables) = self.scan(scan_paths.clone())?;
 
-            self.recurse(path.path(), path.recurse())?;
+        let sources = scan_paths
+            .into_iter()
+            .zip(scans)
+            .map(|((root, recurse), entries)| ScanSource {
+                root: Some(root),
+                recurse,
+                entries,
+            })
+            .collect::<Vec<_>>();
 
-            self.paths.insert(id, path);
-        }
+        self.merge(&mut files, &sources, &mut tables)?;
+        *self.sources.get_mut().unwrap() = sources;
 
         log::debug!(
             "Initialized filesystem in {} seconds",
@@ -127,118 +320,699 @@ impl Filesystem {
 
         log::debug!(
             "{} files cached {} archives",
-            self.files.len(),
-            self.archives.len()
+            files.files.len(),
+            files.archives.len()
         );
 
+        self.files.store(Arc::new(files));
+
         Ok(())
     }
 
-    fn recurse<P: AsRef<Path>>(&mut self, path: P, recurse: bool) -> anyhow::Result<()> {
-        let path = path.as_ref().to_path_buf();
-        log::trace!("recurse({})", path.display().to_string());
+    /// Registers the aliases declared in `fsgame.ltx`, resolving an alias rooted
+    /// at another one only once that one is resolved, so aliases can be declared
+    /// in any order. Returns the directories to scan, in declaration order.
+    fn resolve_aliases(
+        &mut self,
+        declarations: Vec<(PathBuf, FSPath)>,
+    ) -> anyhow::Result<Vec<(PathBuf, bool)>> {
+        let declared = declarations
+            .iter()
+            .map(|(id, _)| id.clone())
+            .collect::<HashSet<_>>();
 
-        let ignore = {
-            let mut path = path.clone();
-            path.push(".xrignore");
-            path
-        };
+        let mut scan_paths = vec![None; declarations.len()];
+        let mut pending = declarations.into_iter().enumerate().collect::<Vec<_>>();
 
-        if ignore.exists() {
-            return Ok(());
-        }
+        while !pending.is_empty() {
+            let count = pending.len();
+            let mut deferred = Vec::new();
 
-        if let Ok(dir) = path.read_dir() {
-            for entry in dir {
-                let entry = entry?;
+            for (index, (id, path)) in pending {
+                let root = path.root();
 
-                if ignore_name(&entry.file_name().into_string().unwrap()) {
+                if declared.contains(root) && !self.paths.contains_key(root) {
+                    deferred.push((index, (id, path)));
                     continue;
                 }
 
-                self.process_single(entry, recurse)?;
+                if root.as_os_str() == FS_ROOT && !self.paths.contains_key(root) {
+                    let p = FSPath::new(self.fs_root.clone(), None, None, None, false, false);
+
+                    self.paths.insert(PathBuf::from(FS_ROOT), p);
+                }
+
+                let path = match self.paths.get(root) {
+                    Some(root) => path.rooted_at(root.path().clone()),
+                    None => path,
+                };
+
+                scan_paths[index] = Some((path.path().clone(), path.recurse()));
+
+                self.paths.insert(id, path);
             }
-        } else {
-            return Ok(());
+
+            if deferred.len() == count {
+                return Err(FilesystemFSPathError::AliasCycle {
+                    chain: alias_cycle(&deferred),
+                }
+                .into());
+            }
+
+            pending = deferred;
         }
 
-        self.register(path, None, 0, 0, 0)?;
+        Ok(scan_paths.into_iter().flatten().collect())
+    }
 
-        Ok(())
+    /// Walks the given directories and reads the file tables of the new archives found in them.
+    ///
+    /// This only touches the disk and leaves the file table alone, so it can run
+    /// while other threads keep reading.
+    fn scan(
+        &self,
+        scan_paths: Vec<(PathBuf, bool)>,
+    ) -> anyhow::Result<(Vec<Vec<ScanEntry>>, ArchiveTables)> {
+        let mounted = self.snapshot();
+
+        self.pool.install(|| {
+            let scans = scan_paths
+                .into_par_iter()
+                .map(|(path, recurse)| scan::scan(path, recurse, &self.rules, self.config.codepage))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+
+            let mut archive_paths = Vec::new();
+
+            for entry in scans.iter().flatten() {
+                if let ScanEntry::Archive(path) = entry {
+                    if !archive_paths.contains(path) && mounted.archive(path).is_none() {
+                        archive_paths.push(path.clone());
+                    }
+                }
+            }
+
+            let tables = archive_paths
+                .into_par_iter()
+                .map(|path| {
+                    let table = ArchiveTable::read(&path, self.config.codepage)?;
+                    Ok((path, table))
+                })
+                .collect::<anyhow::Result<HashMap<_, _>>>()?;
+
+            Ok((scans, tables))
+        })
     }
 
-    fn register<P: AsRef<Path>>(
-        &mut self,
-        path: P,
-        archive: Option<usize>,
-        size_real: usize,
-        size_compressed: usize,
-        ptr: usize,
+    /// Registers the entries of every source in order, so the winning file never
+    /// depends on how many threads did the scan. Like in XRay, a file registered
+    /// later replaces an earlier one, and an archive is only registered where it
+    /// was found first.
+    fn merge(
+        &self,
+        files: &mut FileTable,
+        sources: &[ScanSource],
+        tables: &mut ArchiveTables,
     ) -> anyhow::Result<()> {
-        let path = path.as_ref();
-        log::trace!("register({}, {archive:?})", path.display());
+        let mut registered = HashSet::new();
 
-        let description =
-            VirtualFile::new(path.to_path_buf(), archive, size_real, size_compressed, ptr);
+        for entry in sources.iter().flat_map(|source| &source.entries) {
+            match entry {
+                ScanEntry::Directory { path, modified } => {
+                    files.register_directory(path, *modified)?
+                }
+                ScanEntry::File {
+                    path,
+                    size,
+                    modified,
+                } => files.register(path, None, *size, *size, 0, *modified)?,
+                ScanEntry::Archive(path) => {
+                    if !registered.insert(path) {
+                        continue;
+                    }
 
-        self.files.insert(path.to_path_buf(), description);
+                    if let Some(archive) = files.archive(path) {
+                        self.load_archive(files, &archive)?;
+                    } else if let Some(table) = tables.remove(path) {
+                        self.process_archive(files, table)?;
+                    }
+                }
+            }
+        }
 
-        let mut archive_id = archive;
+        Ok(())
+    }
 
-        for ancestor in path.ancestors().skip(1) {
-            let description = VirtualFile::new(ancestor.to_path_buf(), archive_id, 0, 0, 0);
+    /// Applies a small update to a copy of the current file table and swaps it in.
+    ///
+    /// Updates are serialized, readers keep using the previous snapshot until the swap.
+    fn update<F>(&self, f: F) -> anyhow::Result<()>
+    where
+        F: FnOnce(&mut FileTable, &mut Vec<ScanSource>) -> anyhow::Result<()>,
+    {
+        let mut sources = self.sources.lock().unwrap();
 
-            if self.files.insert(ancestor.to_path_buf(), description).is_some() {
-                break;
+        let mut files = FileTable::clone(&self.files.load());
+        f(&mut files, &mut sources)?;
+        self.files.store(Arc::new(files));
+
+        Ok(())
+    }
+
+    pub(crate) fn snapshot(&self) -> Arc<FileTable> {
+        self.files.load_full()
+    }
+
+    /// Rescans the directory of an alias and replaces its loose files, like XRay's `rescan_path`.
+    ///
+    /// The file table is rebuilt from scratch in the original registration order,
+    /// so archive files shadowed by loose files come back when those are deleted,
+    /// and loose files never win over archives they lost to at startup.
+    pub fn rescan<P: AsRef<Path>>(&self, alias: P) -> anyhow::Result<()> {
+        let alias = alias.as_ref();
+        log::debug!("rescan({})", alias.display());
+
+        let path = self
+            .get_path(alias)
+            .ok_or_else(|| FilesystemFSPathError::UnknownAlias {
+                alias: alias.display().to_string(),
+            })?;
+
+        let root = path.path().clone();
+        let recurse = path.recurse();
+
+        let mut sources = self.sources.lock().unwrap();
+
+        // Aliases inside the rescanned directory are rescanned with it, so their
+        // files keep the place they were registered at
+        let is_rescanned = |source: &ScanSource| {
+            source.root.as_ref().is_some_and(|source_root| {
+                source_root == &root || (recurse && source_root.starts_with(&root))
+            })
+        };
+
+        let mut scan_paths = sources
+            .iter()
+            .filter(|source| is_rescanned(source))
+            .map(|source| (source.root.clone().unwrap(), source.recurse))
+            .collect::<Vec<_>>();
+
+        if scan_paths.is_empty() {
+            sources.push(ScanSource {
+                root: Some(root.clone()),
+                recurse,
+                entries: Vec::new(),
+            });
+            scan_paths.push((root.clone(), recurse));
+        }
+
+        let (scans, mut tables) = self.scan(scan_paths)?;
+        let mut scans = scans.into_iter();
+
+        let rescanned = |name: &Path| {
+            if recurse {
+                name.starts_with(&root) && name != root
+            } else {
+                name.parent() == Some(root.as_path())
             }
+        };
 
-            archive_id = None;
+        for source in sources.iter_mut() {
+            if is_rescanned(source) {
+                source.entries = scans.next().unwrap();
+                continue;
+            }
+
+            source.entries.retain(|entry| match entry {
+                ScanEntry::Directory { path, .. } | ScanEntry::File { path, .. } => {
+                    !rescanned(path)
+                }
+                ScanEntry::Archive(_) => true,
+            });
         }
+        sources.retain(|source| source.root.is_some() || !source.entries.is_empty());
+
+        let current = self.snapshot();
+        let mut files = FileTable {
+            files: HashMap::with_capacity(current.files.len()),
+            archives: current.archives.clone(),
+        };
+
+        self.merge(&mut files, &sources, &mut tables)?;
+        self.files.store(Arc::new(files));
 
         Ok(())
     }
 
-    fn process_single(&mut self, entry: DirEntry, recurse: bool) -> anyhow::Result<()> {
-        log::trace!("process_single: {}", entry.path().display().to_string());
+    /// Mounts an archive that wasn't found while scanning, for example a level archive.
+    pub fn mount_archive<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        let path = std::fs::canonicalize(path)?;
+        log::debug!("mount_archive({})", path.display());
 
-        let metadata = entry.metadata()?;
-
-        if metadata.is_hidden() {
+        if self.snapshot().archive(&path).is_some() {
             return Ok(());
         }
 
-        if metadata.is_dir() {
-            if !recurse || entry.file_name() == "." || entry.file_name() == ".." {
-                return Ok(());
-            }
+        let table = ArchiveTable::read(&path, self.config.codepage)?;
+
+        self.update(|files, sources| {
+            sources.push(ScanSource {
+                root: None,
+                recurse: false,
+                entries: vec![ScanEntry::Archive(path)],
+            });
+
+            self.process_archive(files, table)
+        })
+    }
+
+    /// Registers a loose file that was created after the filesystem was initialized.
+    pub fn register_file<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let metadata = std::fs::metadata(path)?;
+        let size = metadata.len() as usize;
+        let modified = metadata.modified()?;
+
+        self.update(|files, sources| {
+            // Only the last registration of a file counts
+            sources.retain(|source| !source.is_file(path));
+            sources.push(ScanSource {
+                root: None,
+                recurse: false,
+                entries: vec![ScanEntry::File {
+                    path: path.to_path_buf(),
+                    size,
+                    modified,
+                }],
+            });
+
+            files.register(path, None, size, size, 0, modified)
+        })
+    }
+}
 
-            self.register(entry.path(), None, 0, 0, 0)?;
+impl Filesystem {
+    pub fn codepage(&self) -> &'static Encoding {
+        self.config.codepage
+    }
 
-            self.recurse(entry.path(), recurse)
-        } else {
-            let path = entry.path();
+    pub fn exists<P: AsRef<Path>>(&self, path: P) -> bool {
+        self.files.load().files.contains_key(path.as_ref())
+    }
 
-            if let Some(extension) = path.extension() {
-                let extension = extension.to_str().unwrap();
+    pub fn get_file<P: AsRef<Path>>(&self, path: P) -> Option<VirtualFile> {
+        self.files.load().files.get(path.as_ref()).cloned()
+    }
 
-                if extension.starts_with("db") || extension.starts_with("xdb") {
-                    return self.process_archive(path);
-                }
-            }
+    /// Returns the modification time of a registered file, like XRay's `get_file_age`.
+    ///
+    /// Files packed in an archive report the modification time of the archive.
+    pub fn get_file_age<P: AsRef<Path>>(&self, path: P) -> Option<SystemTime> {
+        self.get_file(path).map(|file| file.modified())
+    }
 
-            let size = entry.metadata()?.len() as usize;
+    /// Returns the files registered directly inside `directory`, sorted by name.
+    pub fn file_list<P: AsRef<Path>>(&self, directory: P) -> Vec<PathBuf> {
+        let directory = directory.as_ref();
 
-            self.register(path, None, size, size, 0)
-        }
+        let mut files = self
+            .files
+            .load()
+            .files
+            .values()
+            .filter(|file| !file.is_directory() && file.name().parent() == Some(directory))
+            .map(|file| file.name().clone())
+            .collect::<Vec<_>>();
+
+        files.sort();
+
+        files
+    }
+
+    /// Returns the directories registered directly inside `directory`, sorted by name.
+    pub fn directory_list<P: AsRef<Path>>(&self, directory: P) -> Vec<PathBuf> {
+        let directory = directory.as_ref();
+
+        let mut directories = self
+            .files
+            .load()
+            .files
+            .values()
+            .filter(|file| file.is_directory() && file.name().parent() == Some(directory))
+            .map(|file| file.name().clone())
+            .collect::<Vec<_>>();
+
+        directories.sort();
+
+        directories
+    }
+}
+
+/// Follows the roots of aliases that can't be resolved until one repeats,
+/// returning the cycle they're stuck in, like `$a$ -> $b$ -> $a$`.
+fn alias_cycle(deferred: &[(usize, (PathBuf, FSPath))]) -> Vec<String> {
+    let roots = deferred
+        .iter()
+        .map(|(_, (id, path))| (id.as_path(), path.root()))
+        .collect::<HashMap<_, _>>();
+
+    let (_, (first, _)) = &deferred[0];
+    let mut alias = first.as_path();
+    let mut chain = Vec::new();
+
+    // Every deferred alias is rooted at another deferred one, so this ends in a cycle
+    while !chain.contains(&alias) {
+        chain.push(alias);
+        alias = roots[alias];
     }
+
+    let start = chain.iter().position(|&id| id == alias).unwrap();
+
+    chain[start..]
+        .iter()
+        .chain([&alias])
+        .map(|id| id.display().to_string())
+        .collect()
 }
 
 #[derive(Error, Debug)]
 pub enum FilesystemError {
     #[error("invalid fs_ltx syntax in {file_name} in line {line}")]
     InvalidFsLtxSyntax { file_name: String, line: usize },
+    #[error("unknown codepage {codepage}")]
+    UnknownCodepage { codepage: String },
 }
 
-fn ignore_name(name: &str) -> bool {
-    name == "Thumbs.db" || name == ".svn"
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::stream::Writer;
+    use access_log::FileSource;
+
+    /// A directory tree in the system's temp directory, removed on drop.
+    pub(crate) struct TestTree {
+        root: PathBuf,
+    }
+
+    impl TestTree {
+        pub(crate) fn new(name: &str) -> TestTree {
+            let root =
+                std::env::temp_dir().join(format!("xray-oxide-{name}-{}", std::process::id()));
+
+            let _ = std::fs::remove_dir_all(&root);
+            std::fs::create_dir_all(&root).unwrap();
+
+            TestTree {
+                root: std::fs::canonicalize(root).unwrap(),
+            }
+        }
+
+        pub(crate) fn path(&self, name: &str) -> PathBuf {
+            self.root.join(name)
+        }
+
+        pub(crate) fn write(&self, name: &str, data: &[u8]) {
+            let path = self.path(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, data).unwrap();
+        }
+
+        /// Writes an uncompressed archive with its files registered at `entry_point`.
+        pub(crate) fn write_archive(&self, name: &str, entry_point: &str, files: &[(&str, &[u8])]) {
+            let header = format!("[header]\r\nauto_load = true\r\nentry_point = {entry_point}\r\n");
+
+            let mut archive = Writer::new();
+            archive.w_chunk(666, header.as_bytes());
+
+            archive.open_chunk(0);
+            let mut table = Writer::new();
+            for (file_name, data) in files {
+                table.w_u16((16 + file_name.len()) as u16);
+                table.w_u32(data.len() as u32);
+                table.w_u32(data.len() as u32);
+                table.w_u32(0);
+                table.w_bytes(file_name.as_bytes());
+                table.w_u32(archive.len() as u32);
+                archive.w_bytes(data);
+            }
+            archive.close_chunk();
+
+            archive.w_chunk(1, table.data());
+
+            self.write(name, archive.data());
+        }
+
+        pub(crate) fn filesystem(&self, fs_ltx: &str, config: FilesystemConfig) -> Filesystem {
+            self.write("fsgame.ltx", fs_ltx.as_bytes());
+
+            Filesystem::with_config(self.path("fsgame.ltx").to_str().unwrap(), config).unwrap()
+        }
+    }
+
+    impl Drop for TestTree {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.root);
+        }
+    }
+
+    const FS_LTX: &str = "$game_data$ = true| false| $fs_root$| gamedata\n\
+                          $game_config$ = true| false| $game_data$| configs\n";
+
+    #[test]
+    fn test_scan_threads() {
+        let tree = TestTree::new("scan-threads");
+
+        for i in 0..20 {
+            tree.write(&format!("gamedata/configs/{i}.ltx"), b"[section]");
+            tree.write(&format!("gamedata/textures/dir{}/{i}.dds", i % 4), b"DDS ");
+        }
+        tree.write_archive(
+            "gamedata/configs.db0",
+            "$game_config$\\",
+            &[("archived.ltx", b"[archived]"), ("0.ltx", b"[packed]")],
+        );
+
+        let files = |threads| {
+            let config = FilesystemConfig {
+                threads,
+                ..FilesystemConfig::default()
+            };
+
+            tree.filesystem(FS_LTX, config).snapshot().files.clone()
+        };
+
+        let single = files(1);
+
+        assert!(single.len() > 40);
+        assert!(single.contains_key(&tree.path("gamedata/configs/archived.ltx")));
+        assert_eq!(single, files(4));
+        assert_eq!(single, files(0));
+    }
+
+    #[test]
+    fn test_file_age() {
+        let tree = TestTree::new("file-age");
+
+        tree.write("gamedata/configs/loose.ltx", b"[loose]");
+        tree.write_archive(
+            "gamedata/configs.db0",
+            "$game_config$\\",
+            &[("packed.ltx", b"[packed]")],
+        );
+
+        let fs = tree.filesystem(FS_LTX, FilesystemConfig::default());
+
+        let modified = |name| {
+            std::fs::metadata(tree.path(name))
+                .unwrap()
+                .modified()
+                .unwrap()
+        };
+
+        assert_eq!(
+            fs.get_file_age(tree.path("gamedata/configs/loose.ltx")),
+            Some(modified("gamedata/configs/loose.ltx"))
+        );
+        assert_eq!(
+            fs.get_file_age(tree.path("gamedata/configs/packed.ltx")),
+            Some(modified("gamedata/configs.db0"))
+        );
+        assert_eq!(fs.get_file_age(tree.path("gamedata/missing.ltx")), None);
+    }
+
+    #[test]
+    fn test_alias_order() {
+        let tree = TestTree::new("alias-order");
+
+        tree.write("gamedata/configs/weathers/default.ltx", b"[weather]");
+
+        let fs = tree.filesystem(
+            "$game_weathers$ = true| false| $game_config$| weathers\n\
+             $game_config$ = true| false| $game_data$| configs\n\
+             $game_data$ = true| false| $fs_root$| gamedata\n",
+            FilesystemConfig::default(),
+        );
+
+        let weathers = tree.path("gamedata/configs/weathers");
+
+        assert_eq!(fs.get_path("$game_weathers$").unwrap().path(), &weathers);
+        assert_eq!(
+            fs.update_path("$game_weathers$\\default.ltx").unwrap(),
+            weathers.join("default.ltx")
+        );
+        assert!(fs.exists(weathers.join("default.ltx")));
+
+        tree.write(
+            "fsgame.ltx",
+            b"$c$ = true| false| $a$| c\n\
+              $a$ = true| false| $b$| a\n\
+              $b$ = true| false| $a$| b\n",
+        );
+        let error = Filesystem::with_fs_ltx(tree.path("fsgame.ltx").to_str().unwrap());
+        assert_eq!(
+            error.err().unwrap().to_string(),
+            "Alias cycle: $a$ -> $b$ -> $a$"
+        );
+    }
+
+    #[test]
+    fn test_xrignore() {
+        let tree = TestTree::new("xrignore");
+
+        tree.write("gamedata/skipped/.xrignore", b"*.bak\n");
+        tree.write("gamedata/skipped/file.ltx", b"");
+        // A CP1251 comment, "backups"
+        tree.write(
+            "gamedata/filtered/.xrignore",
+            b"#patterns\n# \xe1\xfd\xea\xe0\xef\xfb\n*.bak\n",
+        );
+        tree.write("gamedata/filtered/file.ltx", b"");
+        tree.write("gamedata/filtered/file.ltx.bak", b"");
+        tree.write("gamedata/filtered/nested/file.bak", b"");
+
+        let fs = tree.filesystem(FS_LTX, FilesystemConfig::default());
+
+        assert!(fs.file_list(tree.path("gamedata/skipped")).is_empty());
+        assert_eq!(
+            fs.file_list(tree.path("gamedata/filtered")),
+            [tree.path("gamedata/filtered/file.ltx")]
+        );
+        assert!(fs
+            .file_list(tree.path("gamedata/filtered/nested"))
+            .is_empty());
+    }
+
+    #[test]
+    fn test_rescan() {
+        let tree = TestTree::new("rescan");
+
+        tree.write("gamedata/configs/shadowed.ltx", b"[loose]");
+        tree.write("gamedata/configs/patched.ltx", b"[loose]");
+        tree.write_archive(
+            "gamedata/base.db0",
+            "$game_config$\\",
+            &[("shadowed.ltx", b"[packed]")],
+        );
+        tree.write_archive(
+            "patches/patch.db0",
+            "$game_config$\\",
+            &[
+                ("patched.ltx", b"[packed]"),
+                ("archived/only.ltx", b"[packed]"),
+            ],
+        );
+
+        let fs = tree.filesystem(
+            "$game_data$ = true| false| $fs_root$| gamedata\n\
+             $game_config$ = true| false| $game_data$| configs\n\
+             $game_patches$ = false| false| $fs_root$| patches\n",
+            FilesystemConfig::default(),
+        );
+
+        let config = |name| tree.path("gamedata/configs").join(name);
+        let packed = |name| fs.get_file(config(name)).unwrap().archive().is_some();
+
+        assert!(!packed("shadowed.ltx"));
+        assert!(packed("patched.ltx"));
+
+        std::fs::remove_file(config("shadowed.ltx")).unwrap();
+        tree.write("gamedata/configs/new.ltx", b"[loose]");
+
+        for alias in ["$game_config$", "$game_data$"] {
+            fs.rescan(alias).unwrap();
+
+            assert!(packed("shadowed.ltx"));
+            assert_eq!(fs.read(config("shadowed.ltx")).unwrap(), b"[packed]");
+            assert!(packed("patched.ltx"));
+            assert!(!packed("new.ltx"));
+            assert!(fs.get_file(config("archived")).unwrap().is_directory());
+            assert!(fs.exists(config("archived/only.ltx")));
+        }
+
+        assert!(fs.rescan("$unknown$").is_err());
+    }
+
+    #[test]
+    fn test_access_log() {
+        let tree = TestTree::new("access-log");
+
+        tree.write("gamedata/configs/loose.ltx", b"[loose]");
+        tree.write_archive(
+            "gamedata/configs.db0",
+            "$game_config$\\",
+            &[("packed.ltx", b"[packed]")],
+        );
+
+        let config = FilesystemConfig {
+            access_log: Some(tree.path("access.log")),
+            ..FilesystemConfig::default()
+        };
+        let fs = tree.filesystem(FS_LTX, config);
+
+        let loose = tree.path("gamedata/configs/loose.ltx");
+        let packed = tree.path("gamedata/configs/packed.ltx");
+
+        fs.read(&loose).unwrap();
+        fs.read(&packed).unwrap();
+
+        let accessed = fs
+            .accessed_files()
+            .unwrap()
+            .into_iter()
+            .map(|access| (access.path, access.source, access.size))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            accessed,
+            [
+                (
+                    loose.clone(),
+                    FileSource::Loose {
+                        alias: Some(PathBuf::from("$game_config$"))
+                    },
+                    7
+                ),
+                (
+                    packed.clone(),
+                    FileSource::Archive(tree.path("gamedata/configs.db0")),
+                    8
+                ),
+            ]
+        );
+
+        fs.dump_access_log().unwrap();
+
+        let log = std::fs::read_to_string(tree.path("access.log")).unwrap();
+        let lines = log
+            .lines()
+            .map(|line| line.split('\t').skip(1).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0][..2], ["$game_config$", "7"]);
+        assert_eq!(lines[1][2], packed.to_str().unwrap());
+
+        let fs = tree.filesystem(FS_LTX, FilesystemConfig::default());
+        fs.read(&loose).unwrap();
+        assert!(fs.accessed_files().is_none());
+    }
 }
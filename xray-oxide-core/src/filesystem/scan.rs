@@ -0,0 +1,122 @@
+use std::{
+    fs::DirEntry,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use rayon::prelude::*;
+
+use crate::{encoding::Encoding, ext::MetadataExt};
+
+use super::xrignore::{read_xrignore, IgnoreRules, XrIgnore, XRIGNORE_FILE};
+
+/// A single registration produced while walking a directory.
+///
+/// Entries are collected in the order a sequential walk would register them,
+/// so that merging them back into the [`Filesystem`](super::Filesystem)
+/// always produces the same result, no matter how many threads did the walk.
+#[derive(Debug)]
+pub(crate) enum ScanEntry {
+    Directory {
+        path: PathBuf,
+        modified: SystemTime,
+    },
+    File {
+        path: PathBuf,
+        size: usize,
+        modified: SystemTime,
+    },
+    Archive(PathBuf),
+}
+
+pub(crate) fn scan<P: AsRef<Path>>(
+    path: P,
+    recurse: bool,
+    rules: &IgnoreRules,
+    codepage: &'static Encoding,
+) -> anyhow::Result<Vec<ScanEntry>> {
+    let path = path.as_ref().to_path_buf();
+    log::trace!("scan({})", path.display().to_string());
+
+    let rules = match read_xrignore(&path, codepage)? {
+        Some(XrIgnore::Everything) => return Ok(Vec::new()),
+        Some(XrIgnore::Patterns(patterns)) => rules.with_file(&path, &patterns)?,
+        None => rules.clone(),
+    };
+
+    let entries = if let Ok(dir) = path.read_dir() {
+        dir.collect::<Result<Vec<_>, _>>()?
+    } else {
+        return Ok(Vec::new());
+    };
+
+    let modified = path.metadata()?.modified()?;
+
+    let mut result = entries
+        .into_par_iter()
+        .filter(|entry| {
+            if entry.file_name() == XRIGNORE_FILE {
+                return false;
+            }
+
+            let is_dir = entry.file_type().map(|ty| ty.is_dir()).unwrap_or(false);
+            !rules.is_ignored(entry.path(), is_dir)
+        })
+        .map(|entry| scan_single(entry, recurse, &rules, codepage))
+        .collect::<anyhow::Result<Vec<_>>>()?
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+
+    result.push(ScanEntry::Directory { path, modified });
+
+    Ok(result)
+}
+
+fn scan_single(
+    entry: DirEntry,
+    recurse: bool,
+    rules: &IgnoreRules,
+    codepage: &'static Encoding,
+) -> anyhow::Result<Vec<ScanEntry>> {
+    log::trace!("scan_single: {}", entry.path().display().to_string());
+
+    let metadata = entry.metadata()?;
+
+    if metadata.is_hidden() {
+        return Ok(Vec::new());
+    }
+
+    if metadata.is_dir() {
+        if !recurse || entry.file_name() == "." || entry.file_name() == ".." {
+            return Ok(Vec::new());
+        }
+
+        let mut result = vec![ScanEntry::Directory {
+            path: entry.path(),
+            modified: metadata.modified()?,
+        }];
+        result.extend(scan(entry.path(), recurse, rules, codepage)?);
+
+        Ok(result)
+    } else {
+        let path = entry.path();
+
+        if let Some(extension) = path.extension() {
+            let extension = extension.to_str().unwrap();
+
+            if extension.starts_with("db") || extension.starts_with("xdb") {
+                return Ok(vec![ScanEntry::Archive(std::fs::canonicalize(path)?)]);
+            }
+        }
+
+        let size = metadata.len() as usize;
+        let modified = metadata.modified()?;
+
+        Ok(vec![ScanEntry::File {
+            path,
+            size,
+            modified,
+        }])
+    }
+}
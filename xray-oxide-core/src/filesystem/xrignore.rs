@@ -0,0 +1,113 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use ignore::{
+    gitignore::{Gitignore, GitignoreBuilder},
+    Match,
+};
+
+use crate::encoding::{self, Encoding};
+
+pub(crate) const XRIGNORE_FILE: &str = ".xrignore";
+/// The first line of an `.xrignore` file that holds patterns instead of
+/// skipping its whole directory.
+pub(crate) const XRIGNORE_PATTERNS: &str = "#patterns";
+
+/// Ignore patterns that apply to a directory while it is scanned.
+///
+/// The rules of every `.xrignore` between the scanned root and the current
+/// directory are stacked on top of the global rules, and the deepest match wins,
+/// the same way nested `.gitignore` files work.
+#[derive(Clone)]
+pub(crate) struct IgnoreRules {
+    matchers: Vec<Arc<Gitignore>>,
+}
+
+impl IgnoreRules {
+    pub(crate) fn new<S: AsRef<str>>(patterns: &[S]) -> anyhow::Result<IgnoreRules> {
+        let mut builder = GitignoreBuilder::new("");
+        builder.case_insensitive(true)?;
+
+        for pattern in patterns {
+            builder.add_line(None, pattern.as_ref())?;
+        }
+
+        Ok(IgnoreRules {
+            matchers: vec![Arc::new(builder.build()?)],
+        })
+    }
+
+    /// Adds the patterns of an `.xrignore` file found in `directory`.
+    pub(crate) fn with_file<P: AsRef<Path>>(
+        &self,
+        directory: P,
+        contents: &str,
+    ) -> anyhow::Result<IgnoreRules> {
+        let directory = directory.as_ref();
+        let from = directory.join(XRIGNORE_FILE);
+
+        let mut builder = GitignoreBuilder::new(directory);
+        builder.case_insensitive(true)?;
+
+        for line in contents.lines() {
+            builder.add_line(Some(from.clone()), line)?;
+        }
+
+        let mut matchers = self.matchers.clone();
+        matchers.push(Arc::new(builder.build()?));
+
+        Ok(IgnoreRules { matchers })
+    }
+
+    pub(crate) fn is_ignored<P: AsRef<Path>>(&self, path: P, is_dir: bool) -> bool {
+        let path = path.as_ref();
+
+        for matcher in self.matchers.iter().rev() {
+            match matcher.matched(path, is_dir) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+                Match::None => {}
+            }
+        }
+
+        false
+    }
+}
+
+/// Reads the `.xrignore` file of a directory.
+///
+/// Returns `None` if there is no such file. Like in XRay, an `.xrignore` file
+/// skips the whole directory, whatever it contains, unless its first line is
+/// [`XRIGNORE_PATTERNS`], then the lines after it are gitignore-style patterns.
+pub(crate) fn read_xrignore<P: AsRef<Path>>(
+    directory: P,
+    codepage: &'static Encoding,
+) -> anyhow::Result<Option<XrIgnore>> {
+    let path: PathBuf = directory.as_ref().join(XRIGNORE_FILE);
+
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let contents = std::fs::read(path)?;
+    let contents = encoding::decode(&contents, codepage).into_owned();
+
+    // The opt in line is a comment to the pattern parser
+    let has_patterns = contents
+        .lines()
+        .next()
+        .is_some_and(|line| line.trim() == XRIGNORE_PATTERNS);
+
+    Ok(Some(if has_patterns {
+        XrIgnore::Patterns(contents)
+    } else {
+        XrIgnore::Everything
+    }))
+}
+
+pub(crate) enum XrIgnore {
+    Everything,
+    Patterns(String),
+}
@@ -0,0 +1,228 @@
+use crate::{
+    ogf::{self, Mesh, MeshVertex, SharedGeometry, SlideWindow, VertexFormat},
+    stream::{ReadError, Reader},
+};
+
+const FSL_VB: u32 = 9;
+const FSL_IB: u32 = 10;
+const FSL_SWIS: u32 = 11;
+
+const DECL_END_STREAM: u16 = 0xFF;
+
+const DECL_TYPE_FLOAT2: u8 = 1;
+const DECL_TYPE_FLOAT3: u8 = 2;
+const DECL_TYPE_D3DCOLOR: u8 = 4;
+const DECL_TYPE_SHORT2: u8 = 6;
+const DECL_TYPE_SHORT4: u8 = 7;
+
+const DECL_USAGE_POSITION: u8 = 0;
+const DECL_USAGE_NORMAL: u8 = 3;
+const DECL_USAGE_TEXCOORD: u8 = 5;
+const DECL_USAGE_TANGENT: u8 = 6;
+const DECL_USAGE_BINORMAL: u8 = 7;
+const DECL_USAGE_COLOR: u8 = 10;
+
+/// Texture coordinates stored as shorts, in 1/1024 units for the base texture
+/// and 1/32768 units for the lightmap.
+const UV_QUANT: f32 = 1024.0;
+const LIGHTMAP_UV_QUANT: f32 = 32768.0;
+
+/// An element of a Direct3D vertex declaration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VertexElement {
+    pub stream: u16,
+    pub offset: u16,
+    pub element_type: u8,
+    pub method: u8,
+    pub usage: u8,
+    pub usage_index: u8,
+}
+
+impl VertexElement {
+    fn size(&self) -> usize {
+        match self.element_type {
+            0 | 4 | 5 | 6 | 8 | 9 | 11 | 13 | 14 | 15 => 4,
+            1 | 7 | 10 | 12 | 16 => 8,
+            2 => 12,
+            3 => 16,
+            _ => 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VertexBuffer {
+    pub declaration: Vec<VertexElement>,
+    pub vertices: Vec<MeshVertex>,
+}
+
+/// The vertex and index buffers all visuals of a level share.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LevelGeometry {
+    pub vertex_buffers: Vec<VertexBuffer>,
+    pub index_buffers: Vec<Vec<u16>>,
+    pub slide_windows: Vec<Vec<SlideWindow>>,
+}
+
+impl LevelGeometry {
+    /// Reads the buffers from the chunks of `level.geom`, `level.geomx` or an
+    /// old `level`. The ones that are missing are left empty.
+    pub fn read(reader: &Reader) -> Result<LevelGeometry, ReadError> {
+        let mut geometry = LevelGeometry::default();
+
+        if let Some(mut chunk) = reader.open_chunk(FSL_VB)? {
+            let count = chunk.r_u32()?;
+            for _ in 0..count {
+                geometry
+                    .vertex_buffers
+                    .push(read_vertex_buffer(&mut chunk)?);
+            }
+        }
+
+        if let Some(mut chunk) = reader.open_chunk(FSL_IB)? {
+            let count = chunk.r_u32()?;
+            for _ in 0..count {
+                let index_count = chunk.r_u32()?;
+
+                let mut indices = Vec::with_capacity(index_count as usize);
+                for _ in 0..index_count {
+                    indices.push(chunk.r_u16()?);
+                }
+                geometry.index_buffers.push(indices);
+            }
+        }
+
+        if let Some(mut chunk) = reader.open_chunk(FSL_SWIS)? {
+            let count = chunk.r_u32()?;
+            for _ in 0..count {
+                geometry
+                    .slide_windows
+                    .push(ogf::read_slide_windows(&mut chunk)?);
+            }
+        }
+
+        Ok(geometry)
+    }
+
+    /// The part of the buffers a visual refers to, as a mesh of its own.
+    pub fn mesh(&self, geometry: &SharedGeometry) -> Option<Mesh> {
+        let range = |offset: u32, count: u32| offset as usize..offset as usize + count as usize;
+
+        let vertices = self
+            .vertex_buffers
+            .get(geometry.vertex_buffer as usize)?
+            .vertices
+            .get(range(geometry.vertex_offset, geometry.vertex_count))?;
+        let indices = self
+            .index_buffers
+            .get(geometry.index_buffer as usize)?
+            .get(range(geometry.index_offset, geometry.index_count))?;
+
+        Some(Mesh {
+            vertex_format: VertexFormat::Declaration,
+            vertices: vertices.to_vec(),
+            indices: indices.to_vec(),
+        })
+    }
+}
+
+fn read_vertex_buffer(chunk: &mut Reader) -> Result<VertexBuffer, ReadError> {
+    let mut declaration = Vec::new();
+    loop {
+        let element = VertexElement {
+            stream: chunk.r_u16()?,
+            offset: chunk.r_u16()?,
+            element_type: chunk.r_u8()?,
+            method: chunk.r_u8()?,
+            usage: chunk.r_u8()?,
+            usage_index: chunk.r_u8()?,
+        };
+
+        if element.stream == DECL_END_STREAM {
+            break;
+        }
+        declaration.push(element);
+    }
+
+    let stride = declaration
+        .iter()
+        .map(|element| element.offset as usize + element.size())
+        .max()
+        .unwrap_or(0);
+
+    let count = chunk.r_u32()? as usize;
+    let mut vertices = Vec::with_capacity(count);
+    for _ in 0..count {
+        let data = chunk.r_bytes(stride)?;
+        vertices.push(read_vertex(data, &declaration)?);
+    }
+
+    Ok(VertexBuffer {
+        declaration,
+        vertices,
+    })
+}
+
+fn read_vertex(data: &[u8], declaration: &[VertexElement]) -> Result<MeshVertex, ReadError> {
+    let mut vertex = MeshVertex::default();
+
+    // The base texture coordinates get extra precision from the alpha of the
+    // tangent and binormal
+    let mut uv = None;
+    let mut uv_fraction = [0.0; 2];
+
+    for element in declaration {
+        let mut reader = Reader::new(data);
+        reader.seek(element.offset as usize)?;
+
+        match (element.usage, element.element_type) {
+            (DECL_USAGE_POSITION, DECL_TYPE_FLOAT3) => {
+                vertex.position = reader.r_fvector3()?.into();
+            }
+            (DECL_USAGE_NORMAL, DECL_TYPE_FLOAT3) => {
+                vertex.normal = reader.r_fvector3()?.into();
+            }
+            (DECL_USAGE_NORMAL, DECL_TYPE_D3DCOLOR) => {
+                vertex.normal = unpack_direction(reader.r_u32()?).0;
+            }
+            (DECL_USAGE_TANGENT, DECL_TYPE_D3DCOLOR) => {
+                (vertex.tangent, uv_fraction[0]) = unpack_direction(reader.r_u32()?);
+            }
+            (DECL_USAGE_BINORMAL, DECL_TYPE_D3DCOLOR) => {
+                (vertex.binormal, uv_fraction[1]) = unpack_direction(reader.r_u32()?);
+            }
+            (DECL_USAGE_TEXCOORD, DECL_TYPE_FLOAT2) => {
+                let value = reader.r_fvector2()?.into();
+                match element.usage_index {
+                    0 => vertex.uv = value,
+                    _ => vertex.lightmap_uv = value,
+                }
+            }
+            (DECL_USAGE_TEXCOORD, DECL_TYPE_SHORT2 | DECL_TYPE_SHORT4) => {
+                let value = [reader.r_s16()? as f32, reader.r_s16()? as f32];
+                match element.usage_index {
+                    0 => uv = Some(value),
+                    _ => vertex.lightmap_uv = value.map(|v| v / LIGHTMAP_UV_QUANT),
+                }
+            }
+            (DECL_USAGE_COLOR, DECL_TYPE_D3DCOLOR) => vertex.color = reader.r_u32()?,
+            _ => {}
+        }
+    }
+
+    if let Some([u, v]) = uv {
+        vertex.uv = [
+            (u + uv_fraction[0]) / UV_QUANT,
+            (v + uv_fraction[1]) / UV_QUANT,
+        ];
+    }
+
+    Ok(vertex)
+}
+
+/// A direction packed into the colour channels, and the alpha.
+fn unpack_direction(color: u32) -> ([f32; 3], f32) {
+    let [b, g, r, a] = color.to_le_bytes().map(|c| c as f32 / 255.0);
+
+    ([r * 2.0 - 1.0, g * 2.0 - 1.0, b * 2.0 - 1.0], a)
+}
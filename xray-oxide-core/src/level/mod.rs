@@ -0,0 +1,498 @@
+//! Compiled levels, as built by xrLC into `$game_levels$\<level>\`.
+//!
+//! The `level` file holds the shaders, the visuals, and how the level is split
+//! into sectors connected by portals. The vertices and indices of the visuals
+//! are in the shared buffers of `level.geom`, with position only copies for
+//! shadows in `level.geomx`.
+
+use cgmath::{Matrix4, Vector3, Vector4};
+use thiserror::Error;
+
+use crate::{
+    filesystem::Filesystem,
+    ogf::{Mesh, Ogf, OgfError},
+    stream::{ReadError, Reader},
+};
+
+pub use geometry::{LevelGeometry, VertexBuffer, VertexElement};
+
+mod geometry;
+
+pub const LEVELS_ROOT: &str = "$game_levels$";
+
+const FSL_HEADER: u32 = 1;
+const FSL_SHADERS: u32 = 2;
+const FSL_VISUALS: u32 = 3;
+const FSL_PORTALS: u32 = 4;
+const FSL_LIGHT_DYNAMIC: u32 = 6;
+const FSL_GLOWS: u32 = 7;
+const FSL_SECTORS: u32 = 8;
+
+const FSP_PORTALS: u32 = 1;
+const FSP_ROOT: u32 = 2;
+
+const PORTAL_MAX_VERTICES: usize = 6;
+
+/// A shader and the textures it's used with, like `def_shaders\def_vertex` and
+/// `ston\ston_stena_wall`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LevelShader {
+    pub shader: String,
+    pub textures: Vec<String>,
+}
+
+/// A polygon between two sectors.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Portal {
+    pub sector_front: u16,
+    pub sector_back: u16,
+    pub vertices: Vec<Vector3<f32>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sector {
+    pub portals: Vec<u16>,
+    /// The visual holding everything in the sector.
+    pub root: u32,
+}
+
+/// A light that can be switched or animated, like Direct3D 9's `D3DLIGHT9`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Light {
+    pub controller: u32,
+    /// Point, spot or directional, from 1 to 3.
+    pub light_type: u32,
+    pub diffuse: Vector4<f32>,
+    pub specular: Vector4<f32>,
+    pub ambient: Vector4<f32>,
+    pub position: Vector3<f32>,
+    pub direction: Vector3<f32>,
+    pub range: f32,
+    pub falloff: f32,
+    pub attenuation: Vector3<f32>,
+    pub theta: f32,
+    pub phi: f32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Glow {
+    pub position: Vector3<f32>,
+    pub radius: f32,
+    /// An index into [`Level::shaders`].
+    pub shader: u16,
+}
+
+/// A visual ready to draw, with its geometry copied out of the shared buffers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LevelVisual {
+    /// The index in [`Level::visuals`].
+    pub index: usize,
+    pub shader: Option<LevelShader>,
+    pub mesh: Option<Mesh>,
+    pub transform: Option<Matrix4<f32>>,
+    pub children: Vec<LevelVisual>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Level {
+    pub version: u16,
+    pub quality: u16,
+    /// By the shader id in the headers of visuals. The first one is reserved and `None`.
+    pub shaders: Vec<Option<LevelShader>>,
+    pub visuals: Vec<Ogf>,
+    pub portals: Vec<Portal>,
+    pub sectors: Vec<Sector>,
+    pub lights: Vec<Light>,
+    pub glows: Vec<Glow>,
+    pub geometry: LevelGeometry,
+    pub fast_geometry: Option<LevelGeometry>,
+}
+
+impl Level {
+    /// Loads a level by its folder under `$game_levels$`, like `l01_escape`.
+    pub fn load(filesystem: &Filesystem, folder: &str) -> anyhow::Result<Level> {
+        let read = |name: &str| -> anyhow::Result<Option<Vec<u8>>> {
+            let path = filesystem.update_path(&format!("{LEVELS_ROOT}\\{folder}\\{name}"))?;
+            if !filesystem.exists(&path) {
+                return Ok(None);
+            }
+
+            Ok(Some(filesystem.read(path)?))
+        };
+
+        let level =
+            read("level")?.ok_or_else(|| anyhow::anyhow!("Level {folder} has no level file"))?;
+        let geom = read("level.geom")?;
+        let geomx = read("level.geomx")?;
+
+        let codepage = filesystem.codepage();
+        let geom = geom
+            .as_deref()
+            .map(|data| Reader::new(data).with_codepage(codepage));
+        let geomx = geomx
+            .as_deref()
+            .map(|data| Reader::new(data).with_codepage(codepage));
+
+        Ok(Level::read(
+            &Reader::new(&level).with_codepage(codepage),
+            geom.as_ref(),
+            geomx.as_ref(),
+        )?)
+    }
+
+    pub fn parse(
+        level: &[u8],
+        geom: Option<&[u8]>,
+        geomx: Option<&[u8]>,
+    ) -> Result<Level, LevelError> {
+        Level::read(
+            &Reader::new(level),
+            geom.map(Reader::new).as_ref(),
+            geomx.map(Reader::new).as_ref(),
+        )
+    }
+
+    /// Reads a level from the chunks of its files. Old levels without a
+    /// `level.geom` have their buffers in the `level` file.
+    pub fn read(
+        level: &Reader,
+        geom: Option<&Reader>,
+        geomx: Option<&Reader>,
+    ) -> Result<Level, LevelError> {
+        let mut header = required_chunk(level, FSL_HEADER)?;
+        let version = header.r_u16()?;
+        let quality = header.r_u16()?;
+
+        let mut shaders = Vec::new();
+        let mut chunk = required_chunk(level, FSL_SHADERS)?;
+        let count = chunk.r_u32()?;
+        for _ in 0..count {
+            shaders.push(parse_shader(&chunk.r_stringz()?));
+        }
+
+        let mut visuals = Vec::new();
+        for (index, chunk) in required_chunk(level, FSL_VISUALS)?.chunks().enumerate() {
+            let visual =
+                Ogf::read(&chunk?.reader).map_err(|error| LevelError::Visual { index, error })?;
+            visuals.push(visual);
+        }
+
+        let mut portals = Vec::new();
+        if let Some(mut chunk) = level.open_chunk(FSL_PORTALS)? {
+            while !chunk.eof() {
+                portals.push(read_portal(&mut chunk)?);
+            }
+        }
+
+        let mut sectors = Vec::new();
+        if let Some(chunk) = level.open_chunk(FSL_SECTORS)? {
+            for sector in chunk.chunks() {
+                sectors.push(read_sector(&sector?.reader)?);
+            }
+        }
+
+        let mut lights = Vec::new();
+        if let Some(mut chunk) = level.open_chunk(FSL_LIGHT_DYNAMIC)? {
+            while !chunk.eof() {
+                lights.push(read_light(&mut chunk)?);
+            }
+        }
+
+        let mut glows = Vec::new();
+        if let Some(mut chunk) = level.open_chunk(FSL_GLOWS)? {
+            while !chunk.eof() {
+                glows.push(Glow {
+                    position: chunk.r_fvector3()?,
+                    radius: chunk.r_float()?,
+                    shader: chunk.r_u16()?,
+                });
+            }
+        }
+
+        Ok(Level {
+            version,
+            quality,
+            shaders,
+            visuals,
+            portals,
+            sectors,
+            lights,
+            glows,
+            geometry: LevelGeometry::read(geom.unwrap_or(level))?,
+            fast_geometry: geomx.map(LevelGeometry::read).transpose()?,
+        })
+    }
+
+    pub fn shader(&self, visual: &Ogf) -> Option<&LevelShader> {
+        self.shaders.get(visual.header.shader_id as usize)?.as_ref()
+    }
+
+    /// A visual and everything under it, with the geometry and shaders they use.
+    pub fn visual(&self, index: usize) -> Option<LevelVisual> {
+        Some(self.build_visual(index, self.visuals.get(index)?))
+    }
+
+    /// The visuals of every sector.
+    pub fn sector_visuals(&self) -> Vec<LevelVisual> {
+        self.sectors
+            .iter()
+            .filter_map(|sector| self.visual(sector.root as usize))
+            .collect()
+    }
+
+    fn build_visual(&self, index: usize, visual: &Ogf) -> LevelVisual {
+        let mesh = match &visual.shared_geometry {
+            Some(geometry) => self.geometry.mesh(geometry),
+            None => visual.mesh.clone(),
+        };
+
+        let children = visual
+            .child_refs
+            .iter()
+            .filter_map(|&child| self.visual(child as usize))
+            .chain(
+                visual
+                    .children
+                    .iter()
+                    .map(|child| self.build_visual(index, child)),
+            )
+            .collect();
+
+        LevelVisual {
+            index,
+            shader: self.shader(visual).cloned(),
+            mesh,
+            transform: visual.tree_transform,
+            children,
+        }
+    }
+}
+
+fn required_chunk<'a>(reader: &'a Reader, id: u32) -> Result<Reader<'a>, LevelError> {
+    reader.open_chunk(id)?.ok_or(LevelError::MissingChunk(id))
+}
+
+/// `shader/texture,texture`, or nothing for the reserved first one.
+fn parse_shader(text: &str) -> Option<LevelShader> {
+    if text.is_empty() {
+        return None;
+    }
+
+    let (shader, textures) = text.split_once('/').unwrap_or((text, ""));
+
+    Some(LevelShader {
+        shader: shader.to_owned(),
+        textures: textures
+            .split(',')
+            .filter(|texture| !texture.is_empty())
+            .map(str::to_owned)
+            .collect(),
+    })
+}
+
+fn read_portal(chunk: &mut Reader) -> Result<Portal, ReadError> {
+    let sector_front = chunk.r_u16()?;
+    let sector_back = chunk.r_u16()?;
+
+    let mut vertices = Vec::with_capacity(PORTAL_MAX_VERTICES);
+    for _ in 0..PORTAL_MAX_VERTICES {
+        vertices.push(chunk.r_fvector3()?);
+    }
+    vertices.truncate(chunk.r_u32()? as usize);
+
+    Ok(Portal {
+        sector_front,
+        sector_back,
+        vertices,
+    })
+}
+
+fn read_sector(reader: &Reader) -> Result<Sector, LevelError> {
+    let mut portals = Vec::new();
+    if let Some(mut chunk) = reader.open_chunk(FSP_PORTALS)? {
+        while !chunk.eof() {
+            portals.push(chunk.r_u16()?);
+        }
+    }
+
+    Ok(Sector {
+        portals,
+        root: required_chunk(reader, FSP_ROOT)?.r_u32()?,
+    })
+}
+
+fn read_light(chunk: &mut Reader) -> Result<Light, ReadError> {
+    Ok(Light {
+        controller: chunk.r_u32()?,
+        light_type: chunk.r_u32()?,
+        diffuse: chunk.r_fvector4()?,
+        specular: chunk.r_fvector4()?,
+        ambient: chunk.r_fvector4()?,
+        position: chunk.r_fvector3()?,
+        direction: chunk.r_fvector3()?,
+        range: chunk.r_float()?,
+        falloff: chunk.r_float()?,
+        attenuation: chunk.r_fvector3()?,
+        theta: chunk.r_float()?,
+        phi: chunk.r_float()?,
+    })
+}
+
+#[derive(Debug, Error)]
+pub enum LevelError {
+    #[error("missing chunk {0}")]
+    MissingChunk(u32),
+    #[error("visual {index}: {error}")]
+    Visual { index: usize, error: OgfError },
+    #[error(transparent)]
+    Read(#[from] ReadError),
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{ogf::VertexFormat, stream::Writer};
+
+    use super::*;
+
+    fn write_visual(writer: &mut Writer, model_type: u8, shader_id: u16) {
+        writer.open_chunk(1);
+        writer.w_u8(4);
+        writer.w_u8(model_type);
+        writer.w_u16(shader_id);
+        writer.w_bytes(&[0; 40]);
+        writer.close_chunk();
+    }
+
+    #[test]
+    fn test_parse() {
+        let mut level = Writer::new();
+
+        level.open_chunk(FSL_HEADER);
+        level.w_u16(14);
+        level.w_u16(1);
+        level.close_chunk();
+
+        level.open_chunk(FSL_SHADERS);
+        level.w_u32(2);
+        level.w_stringz("");
+        level.w_stringz("def_shaders\\def_vertex/ston\\ston_stena_wall,lmap#1_1");
+        level.close_chunk();
+
+        level.open_chunk(FSL_VISUALS);
+        // A hierarchy with the next visual as its child
+        level.open_chunk(0);
+        write_visual(&mut level, 1, 0);
+        level.open_chunk(10);
+        level.w_u32(1);
+        level.w_u32(1);
+        level.close_chunk();
+        level.close_chunk();
+        level.open_chunk(1);
+        write_visual(&mut level, 0, 1);
+        level.open_chunk(21);
+        for value in [0, 1, 3, 0, 3, 3] {
+            level.w_u32(value);
+        }
+        level.close_chunk();
+        level.close_chunk();
+        level.close_chunk();
+
+        level.open_chunk(FSL_SECTORS);
+        level.open_chunk(0);
+        level.open_chunk(FSP_PORTALS);
+        level.w_u16(0);
+        level.close_chunk();
+        level.open_chunk(FSP_ROOT);
+        level.w_u32(0);
+        level.close_chunk();
+        level.close_chunk();
+        level.close_chunk();
+
+        level.open_chunk(FSL_PORTALS);
+        level.w_u16(0);
+        level.w_u16(0);
+        for i in 0..6 {
+            level.w_fvector3(Vector3::new(i as f32, 0.0, 0.0));
+        }
+        level.w_u32(4);
+        level.close_chunk();
+
+        level.open_chunk(FSL_GLOWS);
+        level.w_fvector3(Vector3::new(1.0, 2.0, 3.0));
+        level.w_float(0.5);
+        level.w_u16(1);
+        level.close_chunk();
+
+        let mut geom = Writer::new();
+        geom.open_chunk(9);
+        geom.w_u32(1);
+        // Position, normal, tangent, binormal, base and lightmap texture coordinates
+        for (offset, element_type, usage, usage_index) in [
+            (0, 2, 0, 0),
+            (12, 4, 3, 0),
+            (16, 4, 6, 0),
+            (20, 4, 7, 0),
+            (24, 6, 5, 0),
+            (28, 6, 5, 1),
+        ] {
+            geom.w_u16(0);
+            geom.w_u16(offset);
+            geom.w_u8(element_type);
+            geom.w_u8(0);
+            geom.w_u8(usage);
+            geom.w_u8(usage_index);
+        }
+        geom.w_bytes(&[0xFF, 0, 0, 0, 17, 0, 0, 0]);
+        geom.w_u32(4);
+        for i in 0..4 {
+            geom.w_fvector3(Vector3::new(i as f32, 0.0, 0.0));
+            // Up, with no fraction in the alpha
+            geom.w_u32(0x0080FF80);
+            geom.w_u32(0x00FF8080);
+            geom.w_u32(0x008080FF);
+            geom.w_s16(512 * i);
+            geom.w_s16(1024);
+            geom.w_s16(16384);
+            geom.w_s16(0);
+        }
+        geom.close_chunk();
+
+        geom.open_chunk(10);
+        geom.w_u32(1);
+        geom.w_u32(6);
+        for index in [0, 1, 2, 0, 1, 2] {
+            geom.w_u16(index);
+        }
+        geom.close_chunk();
+
+        let level = Level::parse(&level.into_inner(), Some(&geom.into_inner()), None).unwrap();
+        assert_eq!(level.version, 14);
+        assert_eq!(level.shaders[0], None);
+        assert_eq!(level.portals[0].vertices.len(), 4);
+        assert_eq!(level.sectors[0].portals, [0]);
+        assert_eq!(level.glows[0].radius, 0.5);
+        assert!(level.fast_geometry.is_none());
+
+        let visuals = level.sector_visuals();
+        assert_eq!(visuals.len(), 1);
+        assert_eq!(visuals[0].shader, None);
+
+        let child = &visuals[0].children[0];
+        assert_eq!(child.index, 1);
+
+        let shader = child.shader.as_ref().unwrap();
+        assert_eq!(shader.shader, "def_shaders\\def_vertex");
+        assert_eq!(shader.textures, ["ston\\ston_stena_wall", "lmap#1_1"]);
+
+        let mesh = child.mesh.as_ref().unwrap();
+        assert_eq!(mesh.vertex_format, VertexFormat::Declaration);
+        assert_eq!(mesh.indices, [0, 1, 2]);
+        assert_eq!(mesh.vertices.len(), 3);
+
+        let vertex = &mesh.vertices[1];
+        assert_eq!(vertex.position, [2.0, 0.0, 0.0]);
+        assert!(vertex.normal[1] > 0.99 && vertex.normal[0].abs() < 0.01);
+        assert_eq!(vertex.uv, [1.0, 1.0]);
+        assert_eq!(vertex.lightmap_uv, [0.5, 0.0]);
+    }
+}
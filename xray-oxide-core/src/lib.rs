@@ -1,3 +1,11 @@
+pub mod encoding;
 pub mod filesystem;
 pub mod ext;
+pub mod level;
+pub mod ltx;
 pub mod lzhuf;
+pub mod net_packet;
+pub mod ogf;
+pub mod omf;
+pub mod stream;
+pub mod xml;
@@ -0,0 +1,365 @@
+use std::{
+    fmt::{Display, Formatter},
+    ops::Range,
+    path::Path,
+};
+
+use crate::filesystem::Filesystem;
+
+use super::parser::{
+    is_unterminated, parse_section_header, section_operator, strip_comment, SectionOperator,
+};
+
+/// A single LTX file kept the way it is written, for editing values without
+/// losing comments, key order, blank lines or the inheritance syntax.
+///
+/// Unlike [`Ltx`](super::Ltx), includes and inheritance are left as they are,
+/// and only the text of values that are changed is rewritten.
+///
+/// Keys before the first section header are in the section named `""`.
+#[derive(Debug, Clone)]
+pub struct LtxDocument {
+    entries: Vec<Entry>,
+    separator: Separator,
+    line_ending: &'static str,
+    final_line_ending: bool,
+    modified: bool,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    /// The text of the entry. Only multi-line values span several lines.
+    text: String,
+    kind: EntryKind,
+}
+
+#[derive(Debug, Clone)]
+enum EntryKind {
+    /// Blank lines, comments, `#include`s and DLTX key operators.
+    Other,
+    /// A section header, without a name for `!![section]` deletions.
+    Section { name: Option<String> },
+    Item {
+        section: Option<String>,
+        key: String,
+        /// Where the key ends in the entry's text.
+        key_end: usize,
+        /// Where the value is in the entry's text.
+        value: Option<Range<usize>>,
+    },
+}
+
+/// What separates keys from values.
+#[derive(Debug, Clone, Copy)]
+enum Separator {
+    /// `key = value`
+    Equals,
+    /// `name value`, in console configs.
+    Space,
+}
+
+impl Separator {
+    /// Splits a line into the key and where the value starts.
+    fn split(self, code: &str) -> Option<(&str, usize)> {
+        match self {
+            Separator::Equals => code.find('=').map(|end| (&code[..end], end + 1)),
+            Separator::Space => {
+                let start = code.len() - code.trim_start().len();
+                let end = start + code.trim().find(char::is_whitespace)?;
+
+                Some((&code[start..end], end))
+            }
+        }
+    }
+
+    fn text(self) -> &'static str {
+        match self {
+            Separator::Equals => " = ",
+            Separator::Space => " ",
+        }
+    }
+}
+
+impl LtxDocument {
+    pub fn parse(text: &str) -> LtxDocument {
+        let line_ending = if text.contains("\r\n") { "\r\n" } else { "\n" };
+
+        LtxDocument::parse_with(text, Separator::Equals, line_ending)
+    }
+
+    /// Parses a console config like `user.ltx`, made of `name value` lines
+    /// outside of any section. New files get XRay's `\r\n` line endings.
+    pub fn parse_console(text: &str) -> LtxDocument {
+        let line_ending = if text.contains('\n') && !text.contains("\r\n") {
+            "\n"
+        } else {
+            "\r\n"
+        };
+
+        LtxDocument::parse_with(text, Separator::Space, line_ending)
+    }
+
+    fn parse_with(text: &str, separator: Separator, line_ending: &'static str) -> LtxDocument {
+        let mut entries = Vec::new();
+        let mut lines = text.lines();
+        let mut section = None;
+
+        while let Some(line) = lines.next() {
+            let code = strip_comment(line);
+            let trimmed = code.trim();
+
+            let kind = if trimmed.is_empty() || trimmed.starts_with("#include") {
+                EntryKind::Other
+            } else if let Some((operator, header)) = section_operator(trimmed) {
+                section = match operator {
+                    SectionOperator::Delete => None,
+                    _ => parse_section_header(header).map(|(name, _)| name.to_owned()),
+                };
+
+                EntryKind::Section {
+                    name: section.clone(),
+                }
+            } else if trimmed.starts_with(['!', '>']) {
+                EntryKind::Other
+            } else {
+                let mut text = line.to_owned();
+                let key_start = code.len() - code.trim_start().len();
+
+                let (key, value) = match separator.split(code) {
+                    Some((key, value_start)) => {
+                        let mut end = code.trim_end().len();
+                        let start = (code.len() - code[value_start..].trim_start().len()).min(end);
+
+                        if is_unterminated(&text[start..end]) {
+                            for line in lines.by_ref() {
+                                text.push_str(line_ending);
+                                text.push_str(line);
+
+                                if !is_unterminated(&text[start..]) {
+                                    break;
+                                }
+                            }
+
+                            end = strip_comment(&text).trim_end().len();
+                        }
+
+                        (key.trim(), Some(start..end))
+                    }
+                    None => (trimmed, None),
+                };
+
+                entries.push(Entry {
+                    kind: EntryKind::Item {
+                        section: section.clone(),
+                        key: key.to_owned(),
+                        key_end: key_start + key.len(),
+                        value,
+                    },
+                    text,
+                });
+
+                continue;
+            };
+
+            entries.push(Entry {
+                text: line.to_owned(),
+                kind,
+            });
+        }
+
+        LtxDocument {
+            entries,
+            separator,
+            line_ending,
+            final_line_ending: text.is_empty() || text.ends_with('\n'),
+            modified: false,
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(filesystem: &Filesystem, path: P) -> anyhow::Result<LtxDocument> {
+        Ok(LtxDocument::parse(&filesystem.read_to_string(path)?))
+    }
+
+    pub fn load_console<P: AsRef<Path>>(
+        filesystem: &Filesystem,
+        path: P,
+    ) -> anyhow::Result<LtxDocument> {
+        Ok(LtxDocument::parse_console(
+            &filesystem.read_to_string(path)?,
+        ))
+    }
+
+    /// Writes the document through the filesystem, encoded with its codepage.
+    pub fn save<P: AsRef<Path>>(&self, filesystem: &Filesystem, path: P) -> anyhow::Result<()> {
+        filesystem.write_string(path, &self.to_string())
+    }
+
+    /// Whether any value was changed since the document was parsed.
+    pub fn is_modified(&self) -> bool {
+        self.modified
+    }
+
+    /// The names of the sections, in the order they first appear, without `""`.
+    pub fn sections(&self) -> Vec<&str> {
+        let mut sections = Vec::new();
+
+        for entry in &self.entries {
+            if let EntryKind::Section { name: Some(name) } = &entry.kind {
+                if !sections.contains(&name.as_str()) {
+                    sections.push(name.as_str());
+                }
+            }
+        }
+
+        sections
+    }
+
+    /// The raw value of the last line setting `key`, empty for keys written without a value.
+    pub fn value(&self, section: &str, key: &str) -> Option<&str> {
+        let entry = &self.entries[self.find_item(section, key)?];
+
+        match &entry.kind {
+            EntryKind::Item {
+                value: Some(value), ..
+            } => Some(&entry.text[value.clone()]),
+            _ => Some(""),
+        }
+    }
+
+    /// Sets a value, adding the key at the end of its section, or the section at
+    /// the end of the document, if they don't exist yet.
+    ///
+    /// Setting a value to what it already is changes nothing.
+    pub fn set_value(&mut self, section: &str, key: &str, value: &str) {
+        if let Some(index) = self.find_item(section, key) {
+            let entry = &mut self.entries[index];
+
+            let EntryKind::Item {
+                key_end,
+                value: range,
+                ..
+            } = &mut entry.kind
+            else {
+                unreachable!();
+            };
+
+            match range {
+                Some(range) if entry.text[range.clone()] == *value => return,
+                Some(range) => {
+                    entry.text.replace_range(range.clone(), value);
+                    *range = range.start..range.start + value.len();
+                }
+                None => {
+                    let separator = self.separator.text();
+                    let start = *key_end + separator.len();
+
+                    entry
+                        .text
+                        .insert_str(*key_end, &format!("{separator}{value}"));
+                    *range = Some(start..start + value.len());
+                }
+            }
+
+            self.modified = true;
+            return;
+        }
+
+        let separator = self.separator.text();
+        let start = key.len() + separator.len();
+
+        let item = Entry {
+            text: format!("{key}{separator}{value}"),
+            kind: EntryKind::Item {
+                section: (!section.is_empty()).then(|| section.to_owned()),
+                key: key.to_owned(),
+                key_end: key.len(),
+                value: Some(start..start + value.len()),
+            },
+        };
+
+        let last_line = self.entries.iter().rposition(|entry| match &entry.kind {
+            EntryKind::Section { name } => name.as_deref() == Some(section),
+            EntryKind::Item { section: item, .. } => item.as_deref().unwrap_or("") == section,
+            EntryKind::Other => false,
+        });
+
+        match last_line {
+            Some(index) => self.entries.insert(index + 1, item),
+            None if section.is_empty() => {
+                let first_section = self
+                    .entries
+                    .iter()
+                    .position(|entry| matches!(entry.kind, EntryKind::Section { .. }))
+                    .unwrap_or(self.entries.len());
+
+                self.entries.insert(first_section, item);
+            }
+            None => {
+                if self
+                    .entries
+                    .last()
+                    .is_some_and(|entry| !entry.text.trim().is_empty())
+                {
+                    self.entries.push(Entry {
+                        text: String::new(),
+                        kind: EntryKind::Other,
+                    });
+                }
+
+                self.entries.push(Entry {
+                    text: format!("[{section}]"),
+                    kind: EntryKind::Section {
+                        name: Some(section.to_owned()),
+                    },
+                });
+                self.entries.push(item);
+            }
+        }
+
+        self.modified = true;
+    }
+
+    /// Removes every line setting `key`. Returns whether there were any.
+    pub fn remove_key(&mut self, section: &str, key: &str) -> bool {
+        let len = self.entries.len();
+
+        self.entries.retain(|entry| !is_item(entry, section, key));
+
+        let removed = self.entries.len() != len;
+        self.modified |= removed;
+
+        removed
+    }
+
+    fn find_item(&self, section: &str, key: &str) -> Option<usize> {
+        self.entries
+            .iter()
+            .rposition(|entry| is_item(entry, section, key))
+    }
+}
+
+fn is_item(entry: &Entry, section: &str, key: &str) -> bool {
+    matches!(
+        &entry.kind,
+        EntryKind::Item { section: item_section, key: item_key, .. }
+            if item_section.as_deref().unwrap_or("") == section && item_key == key
+    )
+}
+
+impl Display for LtxDocument {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (idx, entry) in self.entries.iter().enumerate() {
+            if idx > 0 {
+                f.write_str(self.line_ending)?;
+            }
+
+            f.write_str(&entry.text)?;
+        }
+
+        if self.final_line_ending && !self.entries.is_empty() {
+            f.write_str(self.line_ending)?;
+        }
+
+        Ok(())
+    }
+}
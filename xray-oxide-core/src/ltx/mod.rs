@@ -0,0 +1,663 @@
+use std::{
+    fmt::{Display, Formatter},
+    path::{Path, PathBuf},
+};
+
+use indexmap::IndexMap;
+use thiserror::Error;
+
+use crate::filesystem::Filesystem;
+
+use parser::Parser;
+
+pub(crate) use parser::wildcard_match;
+
+pub use document::LtxDocument;
+pub use read::LtxError;
+
+mod document;
+mod parser;
+mod read;
+
+/// Where LTX files and the files they include are read from.
+pub trait LtxSource {
+    fn read_ltx(&self, path: &Path) -> anyhow::Result<String>;
+    /// Lists the files directly inside `directory`, sorted by name.
+    fn list_ltx(&self, directory: &Path) -> Vec<PathBuf>;
+}
+
+impl LtxSource for Filesystem {
+    fn read_ltx(&self, path: &Path) -> anyhow::Result<String> {
+        self.read_to_string(path)
+    }
+
+    fn list_ltx(&self, directory: &Path) -> Vec<PathBuf> {
+        self.file_list(directory)
+    }
+}
+
+/// A single in-memory file, for LTX text that doesn't come from the filesystem.
+struct TextSource<'a> {
+    path: &'a Path,
+    text: &'a str,
+}
+
+impl LtxSource for TextSource<'_> {
+    fn read_ltx(&self, path: &Path) -> anyhow::Result<String> {
+        if path == self.path {
+            Ok(self.text.to_owned())
+        } else {
+            anyhow::bail!("{} not found", path.display())
+        }
+    }
+
+    fn list_ltx(&self, _directory: &Path) -> Vec<PathBuf> {
+        Vec::new()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub file: PathBuf,
+    pub line: usize,
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.file.display(), self.line)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Item {
+    key: String,
+    value: Option<String>,
+    location: Location,
+    patches: Vec<Location>,
+}
+
+impl Item {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// The raw value, including quotes. `None` for keys written without `=`.
+    pub fn value(&self) -> Option<&str> {
+        self.value.as_deref()
+    }
+
+    /// The file and line the value was set in, which is in a parent section for inherited values.
+    pub fn location(&self) -> &Location {
+        &self.location
+    }
+
+    /// The lines of DLTX mod files that changed the value, in the order they were applied.
+    pub fn patches(&self) -> &[Location] {
+        &self.patches
+    }
+
+    pub fn is_patched(&self) -> bool {
+        !self.patches.is_empty()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Section {
+    name: String,
+    parents: Vec<String>,
+    items: IndexMap<String, Item>,
+    location: Location,
+}
+
+impl Section {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn parents(&self) -> &[String] {
+        &self.parents
+    }
+
+    pub fn location(&self) -> &Location {
+        &self.location
+    }
+
+    /// Iterates over all items, inherited ones included.
+    pub fn items(&self) -> impl Iterator<Item = &Item> {
+        self.items.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Item> {
+        self.items.get(key)
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.items.contains_key(key)
+    }
+
+    pub fn value(&self, key: &str) -> Option<&str> {
+        self.get(key).and_then(Item::value)
+    }
+}
+
+/// A parsed XRay LTX file with all of its includes, and with inheritance resolved.
+///
+/// Anomaly DLTX mod files next to the file, named `mod_<name>_*.ltx`, are applied
+/// after it in the order of their names. They can change sections with `![section]`,
+/// remove them with `!![section]`, remove keys with `!key` and append to lists with `>key`.
+#[derive(Debug, Clone)]
+pub struct Ltx {
+    path: PathBuf,
+    mods: Vec<PathBuf>,
+    sections: IndexMap<String, Section>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Ltx {
+    /// Loads an LTX file, failing on the first error like XRay does.
+    ///
+    /// Warnings, like duplicate keys, are logged.
+    pub fn load<S: LtxSource + ?Sized, P: AsRef<Path>>(source: &S, path: P) -> anyhow::Result<Ltx> {
+        let ltx = Ltx::load_lenient(source, path)?;
+
+        if let Some(error) = ltx.errors().next() {
+            return Err(error.clone().into());
+        }
+
+        for warning in ltx.diagnostics() {
+            log::warn!("{warning}");
+        }
+
+        Ok(ltx)
+    }
+
+    /// Loads an LTX file and keeps going after errors, collecting them as diagnostics.
+    ///
+    /// Only fails if the file itself can't be read.
+    pub fn load_lenient<S: LtxSource + ?Sized, P: AsRef<Path>>(
+        source: &S,
+        path: P,
+    ) -> anyhow::Result<Ltx> {
+        let path = path.as_ref();
+
+        let mut parser = Parser::new(source);
+        parser.parse_file(path)?;
+        let mods = parser.parse_mods(path)?;
+
+        let (sections, diagnostics) = parser.finish();
+
+        Ok(Ltx {
+            path: path.to_path_buf(),
+            mods,
+            sections,
+            diagnostics,
+        })
+    }
+
+    /// Parses LTX text that isn't read from a file, like an archive header.
+    ///
+    /// `path` is only used in diagnostics, and `#include`s can't be resolved.
+    pub fn parse<P: AsRef<Path>>(path: P, text: &str) -> anyhow::Result<Ltx> {
+        let path = path.as_ref();
+
+        Ltx::load(&TextSource { path, text }, path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The DLTX mod files that were applied, in order.
+    pub fn mods(&self) -> &[PathBuf] {
+        &self.mods
+    }
+
+    pub fn section(&self, name: &str) -> Option<&Section> {
+        self.sections.get(name)
+    }
+
+    pub fn sections(&self) -> impl Iterator<Item = &Section> {
+        self.sections.values()
+    }
+
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.severity() == Severity::Error)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, Error)]
+#[error("{location}: {kind}")]
+pub struct Diagnostic {
+    pub location: Location,
+    pub kind: DiagnosticKind,
+}
+
+impl Diagnostic {
+    pub fn severity(&self) -> Severity {
+        match self.kind {
+            DiagnosticKind::DuplicateKey { .. } => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Error)]
+pub enum DiagnosticKind {
+    #[error("malformed #include")]
+    InvalidInclude,
+    #[error("unresolved #include \"{include}\"")]
+    UnresolvedInclude { include: String },
+    #[error("recursive #include of {}", .path.display())]
+    RecursiveInclude { path: PathBuf },
+    #[error("malformed section header")]
+    InvalidSection,
+    #[error("key \"{key}\" outside of a section")]
+    KeyOutsideSection { key: String },
+    #[error("unterminated quoted value of \"{key}\"")]
+    UnterminatedQuote { key: String },
+    #[error("duplicate section [{section}], first defined at {previous}")]
+    DuplicateSection { section: String, previous: Location },
+    #[error("duplicate key \"{key}\" in [{section}], first defined at {previous}")]
+    DuplicateKey {
+        section: String,
+        key: String,
+        previous: Location,
+    },
+    #[error("section [{section}] inherits from missing section [{parent}]")]
+    MissingParent { section: String, parent: String },
+    #[error("section [{section}] inherits from [{parent}] in a cycle")]
+    InheritanceCycle { section: String, parent: String },
+    #[error("DLTX patch of missing section [{section}]")]
+    PatchMissingSection { section: String },
+    #[error("key \"{key}\" in section [{section}], which was deleted since its header")]
+    KeyInDeletedSection { section: String, key: String },
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    struct MemorySource(HashMap<PathBuf, &'static str>);
+
+    impl MemorySource {
+        fn new(files: &[(&str, &'static str)]) -> MemorySource {
+            MemorySource(
+                files
+                    .iter()
+                    .map(|(path, text)| (PathBuf::from(path), *text))
+                    .collect(),
+            )
+        }
+    }
+
+    impl LtxSource for MemorySource {
+        fn read_ltx(&self, path: &Path) -> anyhow::Result<String> {
+            self.0
+                .get(path)
+                .map(|text| text.to_string())
+                .ok_or_else(|| anyhow::anyhow!("{} not found", path.display()))
+        }
+
+        fn list_ltx(&self, directory: &Path) -> Vec<PathBuf> {
+            let mut files = self
+                .0
+                .keys()
+                .filter(|path| path.parent() == Some(directory))
+                .cloned()
+                .collect::<Vec<_>>();
+            files.sort();
+            files
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let source = MemorySource::new(&[
+            (
+                "config/system.ltx",
+                "#include \"misc\\base.ltx\"\n\
+                 #include \"mods/*.ltx\"\n\
+                 [child]:base, other ; comment\n\
+                 b = 3\n\
+                 flag\n\
+                 text = \"first line\n\
+                 second; line\" ; comment\n\
+                 url = \"http://x\" // comment\n",
+            ),
+            ("config/misc/base.ltx", "[base]\na = 1\nb = 2\n"),
+            ("config/mods/b.ltx", "[other]\na = 10\n"),
+            ("config/mods/a.txt", "[ignored]\n"),
+        ]);
+
+        let ltx = Ltx::load(&source, "config/system.ltx").unwrap();
+
+        assert_eq!(
+            ltx.sections().map(Section::name).collect::<Vec<_>>(),
+            ["base", "other", "child"]
+        );
+
+        let child = ltx.section("child").unwrap();
+        assert_eq!(child.value("a"), Some("10"));
+        assert_eq!(child.value("b"), Some("3"));
+        assert!(child.contains_key("flag"));
+        assert_eq!(child.value("flag"), None);
+        assert_eq!(child.value("text"), Some("\"first line\r\nsecond; line\""));
+        assert_eq!(child.value("url"), Some("\"http://x\""));
+
+        let a = child.get("a").unwrap();
+        assert_eq!(a.location().file, Path::new("config/mods/b.ltx"));
+        assert_eq!(a.location().line, 2);
+        assert_eq!(child.get("b").unwrap().location().line, 4);
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let source = MemorySource::new(&[(
+            "bad.ltx",
+            "orphan = 1\n\
+             #include \"missing.ltx\"\n\
+             #include \"bad.ltx\"\n\
+             [a]:b\n\
+             key = 1\n\
+             key = 2\n\
+             [b]:a\n\
+             [c]:nope\n\
+             [broken\n",
+        )]);
+
+        let ltx = Ltx::load_lenient(&source, "bad.ltx").unwrap();
+
+        let kinds = ltx
+            .diagnostics()
+            .iter()
+            .map(|diagnostic| (diagnostic.location.line, diagnostic.kind.to_string()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            kinds,
+            [
+                (1, "key \"orphan\" outside of a section".to_owned()),
+                (2, "unresolved #include \"missing.ltx\"".to_owned()),
+                (3, "recursive #include of bad.ltx".to_owned()),
+                (
+                    6,
+                    "duplicate key \"key\" in [a], first defined at bad.ltx:5".to_owned()
+                ),
+                (9, "malformed section header".to_owned()),
+                (7, "section [b] inherits from [a] in a cycle".to_owned()),
+                (
+                    8,
+                    "section [c] inherits from missing section [nope]".to_owned()
+                ),
+            ]
+        );
+
+        assert_eq!(ltx.section("a").unwrap().value("key"), Some("2"));
+        assert!(Ltx::load(&source, "bad.ltx").is_err());
+    }
+
+    #[test]
+    fn test_dltx() {
+        let source = MemorySource::new(&[
+            (
+                "config/system.ltx",
+                "[base]\nlist = a, b\n\
+                 [weapon]:base\ncost = 100\nammo = x\n\
+                 [unused]\nkey = 1\n",
+            ),
+            (
+                "config/mod_system_b.ltx",
+                "![weapon]\ncost = 300\n>list = d\n",
+            ),
+            (
+                "config/mod_system_a.ltx",
+                "![weapon]\ncost = 200\n!ammo\n>list = c\n\
+                 !![unused]\n\
+                 ![missing]\nkey = 1\n",
+            ),
+            ("config/mod_other_a.ltx", "!![base]\n"),
+        ]);
+
+        let ltx = Ltx::load_lenient(&source, "config/system.ltx").unwrap();
+
+        assert_eq!(
+            ltx.mods(),
+            [
+                PathBuf::from("config/mod_system_a.ltx"),
+                PathBuf::from("config/mod_system_b.ltx")
+            ]
+        );
+
+        assert_eq!(
+            ltx.sections().map(Section::name).collect::<Vec<_>>(),
+            ["base", "weapon"]
+        );
+
+        let weapon = ltx.section("weapon").unwrap();
+        assert_eq!(weapon.value("cost"), Some("300"));
+        assert!(!weapon.contains_key("ammo"));
+        assert_eq!(weapon.value("list"), Some("a, b,c,d"));
+
+        let cost = weapon.get("cost").unwrap();
+        assert_eq!(cost.location().file, Path::new("config/mod_system_b.ltx"));
+        assert_eq!(
+            cost.patches()
+                .iter()
+                .map(|location| location.to_string())
+                .collect::<Vec<_>>(),
+            ["config/mod_system_a.ltx:2", "config/mod_system_b.ltx:2"]
+        );
+        assert!(!ltx
+            .section("base")
+            .unwrap()
+            .get("list")
+            .unwrap()
+            .is_patched());
+
+        let errors = ltx
+            .errors()
+            .map(|error| error.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            ["config/mod_system_a.ltx:6: DLTX patch of missing section [missing]"]
+        );
+    }
+
+    #[test]
+    fn test_deleted_section() {
+        let source = MemorySource::new(&[
+            (
+                "config/a.ltx",
+                "[sec]\n#include \"b.ltx\"\nkey = 1\n!other\n>list = 2\n[next]\nkey = 3\n",
+            ),
+            ("config/b.ltx", "!![sec]\n"),
+        ]);
+
+        let ltx = Ltx::load_lenient(&source, "config/a.ltx").unwrap();
+
+        assert!(ltx.section("sec").is_none());
+        assert_eq!(ltx.section("next").unwrap().value("key"), Some("3"));
+
+        let errors = ltx
+            .errors()
+            .map(|error| error.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            ["config/a.ltx:3: key \"key\" in section [sec], which was deleted since its header"]
+        );
+    }
+
+    #[test]
+    fn test_read() {
+        let ltx = Ltx::parse(
+            "test.ltx",
+            "[actor]\n\
+             name = \"stalker\"\n\
+             speed = 1.5\n\
+             health = 100\n\
+             offset = -3\n\
+             god = on\n\
+             position = 1, 2.5, -3\n\
+             color = 0.1, 0.2, 0.3, 1\n\
+             ammo = ammo_9x18, , ammo_5.45x39\n\
+             flag\n",
+        )
+        .unwrap();
+
+        assert!(ltx.section_exist("actor"));
+        assert!(ltx.line_exist("actor", "flag"));
+        assert!(!ltx.line_exist("npc", "flag"));
+
+        assert_eq!(ltx.r_string("actor", "name").unwrap(), "\"stalker\"");
+        assert_eq!(ltx.r_string_wb("actor", "name").unwrap(), "stalker");
+        assert_eq!(ltx.r_float("actor", "speed").unwrap(), 1.5);
+        assert_eq!(ltx.r_u32("actor", "health").unwrap(), 100);
+        assert_eq!(ltx.r_s32("actor", "offset").unwrap(), -3);
+        assert!(ltx.r_bool("actor", "god").unwrap());
+        assert_eq!(
+            ltx.r_fvector3("actor", "position").unwrap(),
+            cgmath::Vector3::new(1.0, 2.5, -3.0)
+        );
+        assert_eq!(
+            ltx.r_fcolor("actor", "color").unwrap(),
+            cgmath::Vector4::new(0.1, 0.2, 0.3, 1.0)
+        );
+        assert_eq!(
+            ltx.r_list("actor", "ammo").unwrap(),
+            ["ammo_9x18", "ammo_5.45x39"]
+        );
+        assert_eq!(ltx.r_string("actor", "flag").unwrap(), "");
+
+        assert_eq!(
+            ltx.r_u32("actor", "offset").unwrap_err().to_string(),
+            "test.ltx:5: [actor] offset = -3 is not a valid u32"
+        );
+        assert_eq!(
+            ltx.r_fvector3("actor", "color").unwrap_err().to_string(),
+            "test.ltx:8: [actor] color = 0.1, 0.2, 0.3, 1 is not a valid vector3"
+        );
+        assert_eq!(
+            ltx.r_bool("actor", "missing").unwrap_err().to_string(),
+            "test.ltx:1: key \"missing\" not found in [actor]"
+        );
+        assert_eq!(
+            ltx.r_float("npc", "speed").unwrap_err().to_string(),
+            "section [npc] not found in test.ltx"
+        );
+    }
+
+    #[test]
+    fn test_document() {
+        let text = "; settings\r\n\
+                    [video]:defaults ; inherits\r\n\
+                    \twidth   = 1920 ; comment\r\n\
+                    vsync\r\n\
+                    empty = ; nothing\r\n\
+                    title = \"first\r\n\
+                    second\" // comment\r\n\
+                    \r\n\
+                    #include \"other.ltx\"\r\n\
+                    [sound]\r\n\
+                    volume = 0.5\r\n";
+
+        let mut document = LtxDocument::parse(text);
+        assert_eq!(document.to_string(), text);
+        assert_eq!(document.sections(), ["video", "sound"]);
+        assert_eq!(document.value("video", "width"), Some("1920"));
+        assert_eq!(document.value("video", "vsync"), Some(""));
+        assert_eq!(document.value("video", "empty"), Some(""));
+        assert_eq!(
+            document.value("video", "title"),
+            Some("\"first\r\nsecond\"")
+        );
+
+        document.set_value("video", "width", "1920");
+        assert!(!document.is_modified());
+
+        document.set_value("video", "width", "2560");
+        document.set_value("video", "vsync", "on");
+        document.set_value("video", "height", "1440");
+        document.set_value("input", "invert", "off");
+        assert!(document.remove_key("sound", "volume"));
+        assert!(!document.remove_key("sound", "volume"));
+
+        assert!(document.is_modified());
+        assert_eq!(
+            document.to_string(),
+            "; settings\r\n\
+             [video]:defaults ; inherits\r\n\
+             \twidth   = 2560 ; comment\r\n\
+             vsync = on\r\n\
+             empty = ; nothing\r\n\
+             title = \"first\r\n\
+             second\" // comment\r\n\
+             height = 1440\r\n\
+             \r\n\
+             #include \"other.ltx\"\r\n\
+             [sound]\r\n\
+             \r\n\
+             [input]\r\n\
+             invert = off\r\n"
+        );
+    }
+
+    #[test]
+    fn test_console_document() {
+        let text = "; user settings\r\n\
+                    rs_v_sync on\r\n\
+                    \tvid_mode   1280x720 ; window\r\n\
+                    bind jump kSPACE\r\n\
+                    flag ; flag\r\n";
+
+        let mut document = LtxDocument::parse_console(text);
+        assert_eq!(document.to_string(), text);
+        assert!(document.sections().is_empty());
+        assert_eq!(document.value("", "vid_mode"), Some("1280x720"));
+        assert_eq!(document.value("", "bind"), Some("jump kSPACE"));
+
+        document.set_value("", "rs_v_sync", "on");
+        assert!(!document.is_modified());
+
+        document.set_value("", "vid_mode", "1920x1080");
+        document.set_value("", "flag", "off");
+        document.set_value("", "texture_lod", "1");
+
+        assert_eq!(
+            document.to_string(),
+            "; user settings\r\n\
+             rs_v_sync on\r\n\
+             \tvid_mode   1920x1080 ; window\r\n\
+             bind jump kSPACE\r\n\
+             flag off ; flag\r\n\
+             texture_lod 1\r\n"
+        );
+
+        let mut document = LtxDocument::parse_console("");
+        document.set_value("", "rs_v_sync", "off");
+        assert_eq!(document.to_string(), "rs_v_sync off\r\n");
+    }
+}
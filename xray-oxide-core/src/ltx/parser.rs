@@ -0,0 +1,625 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use indexmap::IndexMap;
+
+use super::{Diagnostic, DiagnosticKind, Item, Location, LtxSource, Section};
+
+/// A section as written in the files, before inheritance is resolved.
+pub(crate) struct RawSection {
+    parents: Vec<String>,
+    items: IndexMap<String, Item>,
+    /// Keys appended to with `>` that the section doesn't set itself, so the
+    /// inherited value is extended once inheritance is resolved.
+    appends: HashSet<String>,
+    location: Location,
+}
+
+/// The section that keys are currently added to.
+enum Current {
+    None,
+    Section {
+        name: String,
+        patching: bool,
+    },
+    /// A section whose header couldn't be applied, its keys are dropped.
+    Skipped,
+}
+
+pub(crate) enum SectionOperator {
+    /// `[section]`
+    Define,
+    /// `![section]`, changes an existing section.
+    Override,
+    /// `!![section]`, removes an existing section.
+    Delete,
+}
+
+enum KeyOperator {
+    /// `key = value`
+    Set,
+    /// `!key`, removes the key.
+    Delete,
+    /// `>key = value`, appends to a comma separated list.
+    Append,
+}
+
+pub(crate) struct Parser<'a, S: LtxSource + ?Sized> {
+    source: &'a S,
+    sections: IndexMap<String, RawSection>,
+    diagnostics: Vec<Diagnostic>,
+    include_stack: Vec<PathBuf>,
+    in_mod: bool,
+}
+
+impl<'a, S: LtxSource + ?Sized> Parser<'a, S> {
+    pub(crate) fn new(source: &'a S) -> Parser<'a, S> {
+        Parser {
+            source,
+            sections: IndexMap::new(),
+            diagnostics: Vec::new(),
+            include_stack: Vec::new(),
+            in_mod: false,
+        }
+    }
+
+    pub(crate) fn parse_file(&mut self, path: &Path) -> anyhow::Result<()> {
+        log::trace!("parse_file({})", path.display());
+
+        let text = self.source.read_ltx(path)?;
+
+        self.include_stack.push(path.to_path_buf());
+        self.parse_text(path, &text);
+        self.include_stack.pop();
+
+        Ok(())
+    }
+
+    /// Applies the Anomaly DLTX mod files of `path`, which are the `mod_<name>_*.ltx`
+    /// files next to it, in the order of their names.
+    pub(crate) fn parse_mods(&mut self, path: &Path) -> anyhow::Result<Vec<PathBuf>> {
+        let Some(stem) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            return Ok(Vec::new());
+        };
+
+        let pattern = format!("mod_{stem}_*.ltx");
+        let directory = path.parent().unwrap_or(Path::new(""));
+
+        let mut mods = self
+            .source
+            .list_ltx(directory)
+            .into_iter()
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| wildcard_match(&pattern, name))
+            })
+            .collect::<Vec<_>>();
+
+        mods.sort_by_cached_key(|path| path.to_string_lossy().to_lowercase());
+
+        self.in_mod = true;
+
+        for path in &mods {
+            log::debug!("Applying DLTX mod {}", path.display());
+            self.parse_file(path)?;
+        }
+
+        self.in_mod = false;
+
+        Ok(mods)
+    }
+
+    pub(crate) fn finish(mut self) -> (IndexMap<String, Section>, Vec<Diagnostic>) {
+        let mut resolved = IndexMap::new();
+        let mut stack = Vec::new();
+
+        let names = self.sections.keys().cloned().collect::<Vec<_>>();
+
+        for name in &names {
+            self.resolve(name, &mut resolved, &mut stack);
+        }
+
+        let sections = names
+            .iter()
+            .filter_map(|name| resolved.swap_remove_entry(name))
+            .collect();
+
+        (sections, self.diagnostics)
+    }
+
+    fn diagnostic(&mut self, location: Location, kind: DiagnosticKind) {
+        self.diagnostics.push(Diagnostic { location, kind });
+    }
+
+    fn parse_text(&mut self, path: &Path, text: &str) {
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .map(|(line_idx, line)| (line_idx + 1, line));
+
+        let mut current = Current::None;
+
+        while let Some((line_idx, line)) = lines.next() {
+            let location = Location {
+                file: path.to_path_buf(),
+                line: line_idx,
+            };
+
+            let line = strip_comment(line).trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(include) = line.strip_prefix("#include") {
+                self.include(location, include.trim());
+                continue;
+            }
+
+            if let Some((operator, header)) = section_operator(line) {
+                current = self.section_header(operator, header, location);
+                continue;
+            }
+
+            let (operator, line) = if let Some(line) = line.strip_prefix('!') {
+                (KeyOperator::Delete, line.trim_start())
+            } else if let Some(line) = line.strip_prefix('>') {
+                (KeyOperator::Append, line.trim_start())
+            } else {
+                (KeyOperator::Set, line)
+            };
+
+            let (key, mut value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), Some(value.trim().to_owned())),
+                None => (line, None),
+            };
+
+            if let Some(value) = &mut value {
+                // Quoted values continue on the following lines until the quote is closed,
+                // joined with "\r\n" like XRay does.
+                if is_unterminated(value) {
+                    for (_, line) in lines.by_ref() {
+                        value.push_str("\r\n");
+                        value.push_str(line);
+
+                        if !is_unterminated(value) {
+                            break;
+                        }
+                    }
+
+                    if is_unterminated(value) {
+                        self.diagnostic(
+                            location.clone(),
+                            DiagnosticKind::UnterminatedQuote {
+                                key: key.to_owned(),
+                            },
+                        );
+                    } else {
+                        *value = strip_comment(value).trim().to_owned();
+                    }
+                }
+            }
+
+            let (section, patching) = match &current {
+                Current::Section { name, patching } => (name.clone(), *patching),
+                Current::Skipped => continue,
+                Current::None => {
+                    self.diagnostic(
+                        location,
+                        DiagnosticKind::KeyOutsideSection {
+                            key: key.to_owned(),
+                        },
+                    );
+                    continue;
+                }
+            };
+
+            // An included file may have deleted the section since its header
+            if !self.sections.contains_key(&section) {
+                self.diagnostic(
+                    location,
+                    DiagnosticKind::KeyInDeletedSection {
+                        section,
+                        key: key.to_owned(),
+                    },
+                );
+                current = Current::Skipped;
+                continue;
+            }
+
+            let item = Item {
+                key: key.to_owned(),
+                value,
+                location,
+                patches: Vec::new(),
+            };
+
+            match operator {
+                KeyOperator::Set => self.insert_item(&section, item, patching),
+                KeyOperator::Delete => self.delete_item(&section, key),
+                KeyOperator::Append => self.append_item(&section, item),
+            }
+        }
+    }
+
+    fn section_header(
+        &mut self,
+        operator: SectionOperator,
+        header: &str,
+        location: Location,
+    ) -> Current {
+        let Some((name, parents)) = parse_section_header(header) else {
+            self.diagnostic(location, DiagnosticKind::InvalidSection);
+            return Current::Skipped;
+        };
+
+        let name = name.to_owned();
+
+        match operator {
+            SectionOperator::Define => {
+                self.begin_section(&name, parents, location);
+
+                Current::Section {
+                    name,
+                    patching: false,
+                }
+            }
+            _ if !self.sections.contains_key(&name) => {
+                self.diagnostic(
+                    location,
+                    DiagnosticKind::PatchMissingSection { section: name },
+                );
+
+                Current::Skipped
+            }
+            SectionOperator::Override => {
+                if !parents.is_empty() {
+                    self.sections[&name].parents = parents;
+                }
+
+                Current::Section {
+                    name,
+                    patching: true,
+                }
+            }
+            SectionOperator::Delete => {
+                self.sections.shift_remove(&name);
+
+                Current::Skipped
+            }
+        }
+    }
+
+    fn include(&mut self, location: Location, include: &str) {
+        let Some(name) = include
+            .strip_prefix('"')
+            .and_then(|include| include.split_once('"'))
+            .map(|(name, _)| name)
+        else {
+            self.diagnostic(location, DiagnosticKind::InvalidInclude);
+            return;
+        };
+
+        let mut path = location
+            .file
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+
+        for component in name.split(['\\', '/']).filter(|c| !c.is_empty()) {
+            path.push(component);
+        }
+
+        let file_name = path
+            .file_name()
+            .and_then(|file_name| file_name.to_str())
+            .unwrap_or_default()
+            .to_owned();
+
+        let paths = if file_name.contains(['*', '?']) {
+            let directory = path.parent().map(Path::to_path_buf).unwrap_or_default();
+
+            self.source
+                .list_ltx(&directory)
+                .into_iter()
+                .filter(|path| {
+                    path.file_name()
+                        .and_then(|name| name.to_str())
+                        .is_some_and(|name| wildcard_match(&file_name, name))
+                })
+                .collect()
+        } else {
+            vec![path]
+        };
+
+        for path in paths {
+            if self.include_stack.contains(&path) {
+                self.diagnostic(location.clone(), DiagnosticKind::RecursiveInclude { path });
+                continue;
+            }
+
+            if self.parse_file(&path).is_err() {
+                self.diagnostic(
+                    location.clone(),
+                    DiagnosticKind::UnresolvedInclude {
+                        include: name.to_owned(),
+                    },
+                );
+            }
+        }
+    }
+
+    fn begin_section(&mut self, name: &str, parents: Vec<String>, location: Location) {
+        if let Some(section) = self.sections.get_mut(name) {
+            let previous = section.location.clone();
+
+            if !parents.is_empty() {
+                section.parents = parents;
+            }
+
+            self.diagnostic(
+                location,
+                DiagnosticKind::DuplicateSection {
+                    section: name.to_owned(),
+                    previous,
+                },
+            );
+
+            return;
+        }
+
+        self.sections.insert(
+            name.to_owned(),
+            RawSection {
+                parents,
+                items: IndexMap::new(),
+                appends: HashSet::new(),
+                location,
+            },
+        );
+    }
+
+    /// Sets a key. Overriding sections replace values silently, everywhere else
+    /// a repeated key is a warning.
+    fn insert_item(&mut self, section: &str, mut item: Item, patching: bool) {
+        if self.in_mod {
+            item.patches.push(item.location.clone());
+        }
+
+        let Some(raw) = self.sections.get_mut(section) else {
+            return;
+        };
+        raw.appends.remove(&item.key);
+
+        let Some(previous) = raw.items.get(&item.key) else {
+            raw.items.insert(item.key.clone(), item);
+            return;
+        };
+
+        let kind = DiagnosticKind::DuplicateKey {
+            section: section.to_owned(),
+            key: item.key.clone(),
+            previous: previous.location.clone(),
+        };
+        let location = item.location.clone();
+
+        item.patches.splice(0..0, previous.patches.iter().cloned());
+        raw.items.insert(item.key.clone(), item);
+
+        if !patching {
+            self.diagnostic(location, kind);
+        }
+    }
+
+    fn delete_item(&mut self, section: &str, key: &str) {
+        let Some(raw) = self.sections.get_mut(section) else {
+            return;
+        };
+
+        raw.items.shift_remove(key);
+        raw.appends.remove(key);
+    }
+
+    fn append_item(&mut self, section: &str, mut item: Item) {
+        if self.in_mod {
+            item.patches.push(item.location.clone());
+        }
+
+        let Some(raw) = self.sections.get_mut(section) else {
+            return;
+        };
+
+        match raw.items.get(&item.key) {
+            Some(previous) => {
+                item.value = join_list(previous.value.as_deref(), item.value.as_deref());
+                item.patches.splice(0..0, previous.patches.iter().cloned());
+            }
+            None => {
+                raw.appends.insert(item.key.clone());
+            }
+        }
+
+        raw.items.insert(item.key.clone(), item);
+    }
+
+    /// Resolves the inherited items of a section, parents first.
+    ///
+    /// Later parents override earlier ones, and the section's own items override all of them.
+    fn resolve(
+        &mut self,
+        name: &str,
+        resolved: &mut IndexMap<String, Section>,
+        stack: &mut Vec<String>,
+    ) {
+        if resolved.contains_key(name) {
+            return;
+        }
+
+        stack.push(name.to_owned());
+
+        let raw = &self.sections[name];
+        let parents = raw.parents.clone();
+        let location = raw.location.clone();
+
+        let mut items = IndexMap::new();
+
+        for parent in &parents {
+            if stack.contains(parent) {
+                self.diagnostic(
+                    location.clone(),
+                    DiagnosticKind::InheritanceCycle {
+                        section: name.to_owned(),
+                        parent: parent.clone(),
+                    },
+                );
+                continue;
+            }
+
+            if !self.sections.contains_key(parent) {
+                self.diagnostic(
+                    location.clone(),
+                    DiagnosticKind::MissingParent {
+                        section: name.to_owned(),
+                        parent: parent.clone(),
+                    },
+                );
+                continue;
+            }
+
+            self.resolve(parent, resolved, stack);
+
+            if let Some(parent) = resolved.get(parent) {
+                for item in parent.items.values() {
+                    items.insert(item.key.clone(), item.clone());
+                }
+            }
+        }
+
+        let raw = &self.sections[name];
+
+        for item in raw.items.values() {
+            let mut item = item.clone();
+
+            if raw.appends.contains(&item.key) {
+                if let Some(inherited) = items.get(&item.key) {
+                    item.value = join_list(inherited.value(), item.value());
+                    item.patches.splice(0..0, inherited.patches.iter().cloned());
+                }
+            }
+
+            items.insert(item.key.clone(), item);
+        }
+
+        stack.pop();
+
+        resolved.insert(
+            name.to_owned(),
+            Section {
+                name: name.to_owned(),
+                parents,
+                items,
+                location,
+            },
+        );
+    }
+}
+
+/// Joins two comma separated lists, skipping empty ones.
+fn join_list(list: Option<&str>, tail: Option<&str>) -> Option<String> {
+    match (list, tail) {
+        (Some(list), Some(tail)) if !list.is_empty() && !tail.is_empty() => {
+            Some(format!("{list},{tail}"))
+        }
+        (Some(list), Some("")) => Some(list.to_owned()),
+        (list, None) => list.map(str::to_owned),
+        (_, tail) => tail.map(str::to_owned),
+    }
+}
+
+pub(crate) fn section_operator(line: &str) -> Option<(SectionOperator, &str)> {
+    if line.starts_with("!![") {
+        Some((SectionOperator::Delete, &line[2..]))
+    } else if line.starts_with("![") {
+        Some((SectionOperator::Override, &line[1..]))
+    } else if line.starts_with('[') {
+        Some((SectionOperator::Define, line))
+    } else {
+        None
+    }
+}
+
+/// Cuts a line at the first `;` or `//` that isn't inside quotes.
+pub(crate) fn strip_comment(line: &str) -> &str {
+    let bytes = line.as_bytes();
+    let mut in_quotes = false;
+
+    for (idx, &byte) in bytes.iter().enumerate() {
+        match byte {
+            b'"' => in_quotes = !in_quotes,
+            b';' if !in_quotes => return &line[..idx],
+            b'/' if !in_quotes && bytes.get(idx + 1) == Some(&b'/') => return &line[..idx],
+            _ => {}
+        }
+    }
+
+    line
+}
+
+pub(crate) fn is_unterminated(value: &str) -> bool {
+    value.starts_with('"') && value.matches('"').count() % 2 == 1
+}
+
+/// Parses `[name]` or `[name]:parent1, parent2`.
+pub(crate) fn parse_section_header(line: &str) -> Option<(&str, Vec<String>)> {
+    let (name, rest) = line.strip_prefix('[')?.split_once(']')?;
+    let name = name.trim();
+
+    if name.is_empty() {
+        return None;
+    }
+
+    let rest = rest.trim();
+
+    let parents = if rest.is_empty() {
+        Vec::new()
+    } else {
+        rest.strip_prefix(':')?
+            .split(',')
+            .map(str::trim)
+            .filter(|parent| !parent.is_empty())
+            .map(str::to_owned)
+            .collect()
+    };
+
+    Some((name, parents))
+}
+
+/// Matches a file name against a pattern with `*` and `?`, ignoring case.
+pub(crate) fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.to_lowercase().chars().collect::<Vec<_>>();
+    let name = name.to_lowercase().chars().collect::<Vec<_>>();
+
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack = None;
+
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, n));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            n = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
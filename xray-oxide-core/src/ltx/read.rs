@@ -0,0 +1,203 @@
+use std::{path::PathBuf, str::FromStr};
+
+use cgmath::{Vector2, Vector3, Vector4};
+use thiserror::Error;
+
+use crate::ext::StrExt;
+
+use super::{Location, Ltx, Section};
+
+/// Typed reads, named after XRay's `CInifile` readers.
+impl Section {
+    pub fn line_exist(&self, key: &str) -> bool {
+        self.contains_key(key)
+    }
+
+    /// The raw value, with quotes. Keys written without a value read as an empty string.
+    pub fn r_string(&self, key: &str) -> Result<&str, LtxError> {
+        self.get(key)
+            .map(|item| item.value().unwrap_or_default())
+            .ok_or_else(|| LtxError::MissingKey {
+                section: self.name.clone(),
+                key: key.to_owned(),
+                location: self.location.clone(),
+            })
+    }
+
+    /// The value with the surrounding quotes removed.
+    pub fn r_string_wb(&self, key: &str) -> Result<&str, LtxError> {
+        let value = self.r_string(key)?;
+
+        Ok(value
+            .strip_prefix('"')
+            .and_then(|value| value.strip_suffix('"'))
+            .unwrap_or(value))
+    }
+
+    pub fn r_float(&self, key: &str) -> Result<f32, LtxError> {
+        self.r_parse(key, "float", parse_number)
+    }
+
+    pub fn r_u32(&self, key: &str) -> Result<u32, LtxError> {
+        self.r_parse(key, "u32", parse_number)
+    }
+
+    pub fn r_s32(&self, key: &str) -> Result<i32, LtxError> {
+        self.r_parse(key, "s32", parse_number)
+    }
+
+    /// Accepts `on`, `yes`, `true` and `1`, or `off`, `no`, `false` and `0`.
+    pub fn r_bool(&self, key: &str) -> Result<bool, LtxError> {
+        self.r_parse(key, "bool", StrExt::parse_bool)
+    }
+
+    pub fn r_fvector2(&self, key: &str) -> Result<Vector2<f32>, LtxError> {
+        self.r_parse(key, "vector2", |value| {
+            parse_floats::<2>(value).map(Vector2::from)
+        })
+    }
+
+    pub fn r_fvector3(&self, key: &str) -> Result<Vector3<f32>, LtxError> {
+        self.r_parse(key, "vector3", |value| {
+            parse_floats::<3>(value).map(Vector3::from)
+        })
+    }
+
+    pub fn r_fvector4(&self, key: &str) -> Result<Vector4<f32>, LtxError> {
+        self.r_parse(key, "vector4", |value| {
+            parse_floats::<4>(value).map(Vector4::from)
+        })
+    }
+
+    /// A color written as `r, g, b, a`.
+    pub fn r_fcolor(&self, key: &str) -> Result<Vector4<f32>, LtxError> {
+        self.r_parse(key, "color", |value| {
+            parse_floats::<4>(value).map(Vector4::from)
+        })
+    }
+
+    /// A comma separated list, with the elements trimmed and empty ones skipped.
+    pub fn r_list(&self, key: &str) -> Result<Vec<&str>, LtxError> {
+        Ok(split_list(self.r_string(key)?).collect())
+    }
+
+    fn r_parse<T, F>(&self, key: &str, expected: &'static str, parse: F) -> Result<T, LtxError>
+    where
+        F: FnOnce(&str) -> Option<T>,
+    {
+        let value = self.r_string(key)?;
+
+        parse(value).ok_or_else(|| LtxError::InvalidValue {
+            section: self.name.clone(),
+            key: key.to_owned(),
+            value: value.to_owned(),
+            expected,
+            location: self.get(key).unwrap().location().clone(),
+        })
+    }
+}
+
+impl Ltx {
+    pub fn section_exist(&self, section: &str) -> bool {
+        self.sections.contains_key(section)
+    }
+
+    pub fn line_exist(&self, section: &str, key: &str) -> bool {
+        self.section(section)
+            .is_some_and(|section| section.line_exist(key))
+    }
+
+    pub fn r_section(&self, section: &str) -> Result<&Section, LtxError> {
+        self.section(section)
+            .ok_or_else(|| LtxError::MissingSection {
+                section: section.to_owned(),
+                file: self.path.clone(),
+            })
+    }
+
+    pub fn r_string(&self, section: &str, key: &str) -> Result<&str, LtxError> {
+        self.r_section(section)?.r_string(key)
+    }
+
+    pub fn r_string_wb(&self, section: &str, key: &str) -> Result<&str, LtxError> {
+        self.r_section(section)?.r_string_wb(key)
+    }
+
+    pub fn r_float(&self, section: &str, key: &str) -> Result<f32, LtxError> {
+        self.r_section(section)?.r_float(key)
+    }
+
+    pub fn r_u32(&self, section: &str, key: &str) -> Result<u32, LtxError> {
+        self.r_section(section)?.r_u32(key)
+    }
+
+    pub fn r_s32(&self, section: &str, key: &str) -> Result<i32, LtxError> {
+        self.r_section(section)?.r_s32(key)
+    }
+
+    pub fn r_bool(&self, section: &str, key: &str) -> Result<bool, LtxError> {
+        self.r_section(section)?.r_bool(key)
+    }
+
+    pub fn r_fvector2(&self, section: &str, key: &str) -> Result<Vector2<f32>, LtxError> {
+        self.r_section(section)?.r_fvector2(key)
+    }
+
+    pub fn r_fvector3(&self, section: &str, key: &str) -> Result<Vector3<f32>, LtxError> {
+        self.r_section(section)?.r_fvector3(key)
+    }
+
+    pub fn r_fvector4(&self, section: &str, key: &str) -> Result<Vector4<f32>, LtxError> {
+        self.r_section(section)?.r_fvector4(key)
+    }
+
+    pub fn r_fcolor(&self, section: &str, key: &str) -> Result<Vector4<f32>, LtxError> {
+        self.r_section(section)?.r_fcolor(key)
+    }
+
+    pub fn r_list(&self, section: &str, key: &str) -> Result<Vec<&str>, LtxError> {
+        self.r_section(section)?.r_list(key)
+    }
+}
+
+pub(crate) fn split_list(value: &str) -> impl Iterator<Item = &str> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|element| !element.is_empty())
+}
+
+fn parse_number<T: FromStr>(value: &str) -> Option<T> {
+    value.trim().parse().ok()
+}
+
+fn parse_floats<const N: usize>(value: &str) -> Option<[f32; N]> {
+    let mut floats = [0.0; N];
+    let mut elements = value.split(',');
+
+    for float in &mut floats {
+        *float = parse_number(elements.next()?)?;
+    }
+
+    elements.next().is_none().then_some(floats)
+}
+
+#[derive(Error, Debug)]
+pub enum LtxError {
+    #[error("section [{section}] not found in {}", .file.display())]
+    MissingSection { section: String, file: PathBuf },
+    #[error("{location}: key \"{key}\" not found in [{section}]")]
+    MissingKey {
+        section: String,
+        key: String,
+        location: Location,
+    },
+    #[error("{location}: [{section}] {key} = {value} is not a valid {expected}")]
+    InvalidValue {
+        section: String,
+        key: String,
+        value: String,
+        expected: &'static str,
+        location: Location,
+    },
+}
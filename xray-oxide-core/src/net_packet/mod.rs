@@ -0,0 +1,595 @@
+use std::{borrow::Cow, f32::consts::TAU};
+
+use cgmath::{InnerSpace, Vector3, Vector4};
+use thiserror::Error;
+
+use crate::{
+    encoding::{self, Encoding, DEFAULT_CODEPAGE},
+    stream::ReadError,
+};
+
+pub mod normal;
+
+/// The most a packet holds, XRay's `NET_PacketSizeLimit`.
+pub const NET_PACKET_SIZE_LIMIT: usize = 16 * 1024;
+
+/// Directions shorter than this are written by `w_sdir` as zero.
+const SDIR_EPSILON: f32 = 1e-7;
+
+/// Identifies a client of a game server.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct ClientId(pub u32);
+
+/// XRay's `NET_Packet`, the little endian format that spawn files, saves,
+/// server entities and network messages are serialized with.
+///
+/// Writes append to the packet and panic past [`NET_PACKET_SIZE_LIMIT`], like
+/// XRay's asserts. Reads have their own position and fail at the end of the data.
+#[derive(Debug, Clone)]
+pub struct NetPacket {
+    data: Vec<u8>,
+    r_pos: usize,
+    codepage: &'static Encoding,
+}
+
+impl Default for NetPacket {
+    fn default() -> Self {
+        NetPacket::new()
+    }
+}
+
+impl NetPacket {
+    pub fn new() -> NetPacket {
+        NetPacket {
+            data: Vec::new(),
+            r_pos: 0,
+            codepage: DEFAULT_CODEPAGE,
+        }
+    }
+
+    /// A packet holding received data, to be read from the start.
+    pub fn from_bytes(data: &[u8]) -> Result<NetPacket, PacketTooLarge> {
+        if data.len() > NET_PACKET_SIZE_LIMIT {
+            return Err(PacketTooLarge { size: data.len() });
+        }
+
+        Ok(NetPacket {
+            data: data.to_vec(),
+            ..NetPacket::new()
+        })
+    }
+
+    pub fn with_codepage(mut self, codepage: &'static Encoding) -> NetPacket {
+        self.codepage = codepage;
+        self
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Clears the packet and writes its message type.
+    pub fn w_begin(&mut self, message_type: u16) {
+        self.data.clear();
+        self.r_pos = 0;
+        self.w_u16(message_type);
+    }
+
+    pub fn w_tell(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Overwrites already written bytes.
+    ///
+    /// # Panics
+    ///
+    /// If the bytes go past what has been written.
+    pub fn w_seek(&mut self, position: usize, bytes: &[u8]) {
+        self.data[position..position + bytes.len()].copy_from_slice(bytes);
+    }
+
+    /// # Panics
+    ///
+    /// If the packet would grow past [`NET_PACKET_SIZE_LIMIT`].
+    pub fn w(&mut self, bytes: &[u8]) {
+        assert!(
+            self.data.len() + bytes.len() <= NET_PACKET_SIZE_LIMIT,
+            "NET_Packet overflow, writing {} bytes to {}",
+            bytes.len(),
+            self.data.len()
+        );
+
+        self.data.extend_from_slice(bytes);
+    }
+
+    pub fn w_u8(&mut self, value: u8) {
+        self.w(&value.to_le_bytes());
+    }
+
+    pub fn w_u16(&mut self, value: u16) {
+        self.w(&value.to_le_bytes());
+    }
+
+    /// The low 3 bytes of a value.
+    pub fn w_u24(&mut self, value: u32) {
+        self.w(&value.to_le_bytes()[..3]);
+    }
+
+    pub fn w_u32(&mut self, value: u32) {
+        self.w(&value.to_le_bytes());
+    }
+
+    pub fn w_u64(&mut self, value: u64) {
+        self.w(&value.to_le_bytes());
+    }
+
+    pub fn w_s8(&mut self, value: i8) {
+        self.w(&value.to_le_bytes());
+    }
+
+    pub fn w_s16(&mut self, value: i16) {
+        self.w(&value.to_le_bytes());
+    }
+
+    pub fn w_s32(&mut self, value: i32) {
+        self.w(&value.to_le_bytes());
+    }
+
+    pub fn w_s64(&mut self, value: i64) {
+        self.w(&value.to_le_bytes());
+    }
+
+    pub fn w_float(&mut self, value: f32) {
+        self.w(&value.to_le_bytes());
+    }
+
+    pub fn w_vec3(&mut self, value: Vector3<f32>) {
+        self.w_float(value.x);
+        self.w_float(value.y);
+        self.w_float(value.z);
+    }
+
+    pub fn w_vec4(&mut self, value: Vector4<f32>) {
+        self.w_float(value.x);
+        self.w_float(value.y);
+        self.w_float(value.z);
+        self.w_float(value.w);
+    }
+
+    /// A value between `min` and `max` in 16 bits.
+    pub fn w_float_q16(&mut self, value: f32, min: f32, max: f32) {
+        debug_assert!(
+            value >= min && value <= max,
+            "{value} is outside {min}..={max}"
+        );
+
+        let q = (value - min) / (max - min);
+        self.w_u16((q * 65535.0 + 0.5).floor() as u16);
+    }
+
+    /// A value between `min` and `max` in 8 bits.
+    pub fn w_float_q8(&mut self, value: f32, min: f32, max: f32) {
+        debug_assert!(
+            value >= min && value <= max,
+            "{value} is outside {min}..={max}"
+        );
+
+        let q = (value - min) / (max - min);
+        self.w_u8((q * 255.0 + 0.5).floor() as u8);
+    }
+
+    /// An angle in radians in 16 bits, brought into `0..=2π` first.
+    pub fn w_angle16(&mut self, angle: f32) {
+        self.w_float_q16(angle_normalize(angle), 0.0, TAU);
+    }
+
+    /// An angle in radians in 8 bits, brought into `0..=2π` first.
+    pub fn w_angle8(&mut self, angle: f32) {
+        self.w_float_q8(angle_normalize(angle), 0.0, TAU);
+    }
+
+    /// A direction in 16 bits, see [`normal::compress`].
+    pub fn w_dir(&mut self, direction: Vector3<f32>) {
+        self.w_u16(normal::compress(direction));
+    }
+
+    /// A direction and its length.
+    pub fn w_sdir(&mut self, value: Vector3<f32>) {
+        let magnitude = value.magnitude();
+
+        if magnitude > SDIR_EPSILON {
+            self.w_dir(value / magnitude);
+            self.w_float(magnitude);
+        } else {
+            self.w_dir(Vector3::new(0.0, 0.0, 1.0));
+            self.w_float(0.0);
+        }
+    }
+
+    /// A zero terminated string, encoded with the packet's codepage.
+    pub fn w_stringz(&mut self, text: &str) {
+        let bytes = encoding::encode(text, self.codepage);
+
+        self.w(&bytes);
+        self.w_u8(0);
+    }
+
+    pub fn w_client_id(&mut self, id: ClientId) {
+        self.w_u32(id.0);
+    }
+
+    /// Starts a block prefixed by its size in 8 bits, returning where the size goes.
+    pub fn w_chunk_open8(&mut self) -> usize {
+        let position = self.w_tell();
+        self.w_u8(0);
+        position
+    }
+
+    /// Fills in the size of a block started with [`NetPacket::w_chunk_open8`].
+    pub fn w_chunk_close8(&mut self, position: usize) {
+        let size = self.w_tell() - position - 1;
+        debug_assert!(
+            size <= u8::MAX as usize,
+            "block of {size} bytes is too large"
+        );
+
+        self.w_seek(position, &[size as u8]);
+    }
+
+    /// Starts a block prefixed by its size in 16 bits, returning where the size goes.
+    pub fn w_chunk_open16(&mut self) -> usize {
+        let position = self.w_tell();
+        self.w_u16(0);
+        position
+    }
+
+    /// Fills in the size of a block started with [`NetPacket::w_chunk_open16`].
+    pub fn w_chunk_close16(&mut self, position: usize) {
+        let size = self.w_tell() - position - 2;
+        debug_assert!(
+            size <= u16::MAX as usize,
+            "block of {size} bytes is too large"
+        );
+
+        self.w_seek(position, &(size as u16).to_le_bytes());
+    }
+
+    /// Reads the message type from the start of the packet.
+    pub fn r_begin(&mut self) -> Result<u16, ReadError> {
+        self.r_pos = 0;
+        self.r_u16()
+    }
+
+    pub fn r_tell(&self) -> usize {
+        self.r_pos
+    }
+
+    pub fn r_seek(&mut self, position: usize) -> Result<(), ReadError> {
+        if position > self.data.len() {
+            return Err(self.eof_error(position.saturating_sub(self.r_pos)));
+        }
+
+        self.r_pos = position;
+        Ok(())
+    }
+
+    pub fn r_advance(&mut self, count: usize) -> Result<(), ReadError> {
+        self.r(count).map(|_| ())
+    }
+
+    pub fn r_eof(&self) -> bool {
+        self.r_pos >= self.data.len()
+    }
+
+    /// How much is left to read.
+    pub fn r_elapsed(&self) -> usize {
+        self.data.len().saturating_sub(self.r_pos)
+    }
+
+    fn eof_error(&self, needed: usize) -> ReadError {
+        ReadError::UnexpectedEof {
+            position: self.r_pos,
+            needed,
+            remaining: self.r_elapsed(),
+        }
+    }
+
+    pub fn r(&mut self, count: usize) -> Result<&[u8], ReadError> {
+        if count > self.r_elapsed() {
+            return Err(self.eof_error(count));
+        }
+
+        let start = self.r_pos;
+        self.r_pos += count;
+
+        Ok(&self.data[start..self.r_pos])
+    }
+
+    fn r_array<const N: usize>(&mut self) -> Result<[u8; N], ReadError> {
+        Ok(self.r(N)?.try_into().unwrap())
+    }
+
+    pub fn r_u8(&mut self) -> Result<u8, ReadError> {
+        Ok(u8::from_le_bytes(self.r_array()?))
+    }
+
+    pub fn r_u16(&mut self) -> Result<u16, ReadError> {
+        Ok(u16::from_le_bytes(self.r_array()?))
+    }
+
+    pub fn r_u24(&mut self) -> Result<u32, ReadError> {
+        let [a, b, c] = self.r_array()?;
+        Ok(u32::from_le_bytes([a, b, c, 0]))
+    }
+
+    pub fn r_u32(&mut self) -> Result<u32, ReadError> {
+        Ok(u32::from_le_bytes(self.r_array()?))
+    }
+
+    pub fn r_u64(&mut self) -> Result<u64, ReadError> {
+        Ok(u64::from_le_bytes(self.r_array()?))
+    }
+
+    pub fn r_s8(&mut self) -> Result<i8, ReadError> {
+        Ok(i8::from_le_bytes(self.r_array()?))
+    }
+
+    pub fn r_s16(&mut self) -> Result<i16, ReadError> {
+        Ok(i16::from_le_bytes(self.r_array()?))
+    }
+
+    pub fn r_s32(&mut self) -> Result<i32, ReadError> {
+        Ok(i32::from_le_bytes(self.r_array()?))
+    }
+
+    pub fn r_s64(&mut self) -> Result<i64, ReadError> {
+        Ok(i64::from_le_bytes(self.r_array()?))
+    }
+
+    pub fn r_float(&mut self) -> Result<f32, ReadError> {
+        Ok(f32::from_le_bytes(self.r_array()?))
+    }
+
+    pub fn r_vec3(&mut self) -> Result<Vector3<f32>, ReadError> {
+        Ok(Vector3::new(
+            self.r_float()?,
+            self.r_float()?,
+            self.r_float()?,
+        ))
+    }
+
+    pub fn r_vec4(&mut self) -> Result<Vector4<f32>, ReadError> {
+        Ok(Vector4::new(
+            self.r_float()?,
+            self.r_float()?,
+            self.r_float()?,
+            self.r_float()?,
+        ))
+    }
+
+    pub fn r_float_q16(&mut self, min: f32, max: f32) -> Result<f32, ReadError> {
+        let value = self.r_u16()? as f32;
+        Ok((value * (max - min)) / 65535.0 + min)
+    }
+
+    pub fn r_float_q8(&mut self, min: f32, max: f32) -> Result<f32, ReadError> {
+        let value = self.r_u8()? as f32;
+        // Not quite 255, so the largest value reads back a little under `max`
+        Ok((value / 255.0001) * (max - min) + min)
+    }
+
+    pub fn r_angle16(&mut self) -> Result<f32, ReadError> {
+        self.r_float_q16(0.0, TAU)
+    }
+
+    pub fn r_angle8(&mut self) -> Result<f32, ReadError> {
+        self.r_float_q8(0.0, TAU)
+    }
+
+    pub fn r_dir(&mut self) -> Result<Vector3<f32>, ReadError> {
+        Ok(normal::decompress(self.r_u16()?))
+    }
+
+    pub fn r_sdir(&mut self) -> Result<Vector3<f32>, ReadError> {
+        let direction = self.r_u16()?;
+        let magnitude = self.r_float()?;
+
+        Ok(normal::decompress(direction) * magnitude)
+    }
+
+    /// A zero terminated string.
+    pub fn r_stringz(&mut self) -> Result<Cow<'_, str>, ReadError> {
+        let length = self.data[self.r_pos..].iter().position(|&b| b == 0).ok_or(
+            ReadError::UnterminatedString {
+                position: self.r_pos,
+            },
+        )?;
+
+        let codepage = self.codepage;
+        let bytes = self.r(length + 1)?;
+
+        Ok(encoding::decode(&bytes[..length], codepage))
+    }
+
+    pub fn r_client_id(&mut self) -> Result<ClientId, ReadError> {
+        Ok(ClientId(self.r_u32()?))
+    }
+}
+
+/// Brings an angle into `0..=2π`, like XRay's `angle_normalize`.
+pub fn angle_normalize(angle: f32) -> f32 {
+    if (0.0..=TAU).contains(&angle) {
+        return angle;
+    }
+
+    let div = angle / TAU;
+    let whole = if div > 0.0 { div.floor() } else { div.ceil() };
+
+    let mut fraction = div - whole;
+    if fraction < 0.0 {
+        fraction += 1.0;
+    }
+
+    fraction * TAU
+}
+
+#[derive(Debug, Error)]
+#[error("{size} bytes don't fit in a packet of {NET_PACKET_SIZE_LIMIT}")]
+pub struct PacketTooLarge {
+    pub size: usize,
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_layout() {
+        let mut packet = NetPacket::new();
+        packet.w_begin(0x1234);
+        packet.w_u24(0xABCDEF);
+        packet.w_float_q16(0.5, 0.0, 1.0);
+        packet.w_angle8(std::f32::consts::PI);
+        packet.w_dir(Vector3::new(1.0, 0.0, 0.0));
+        packet.w_dir(Vector3::new(0.0, 0.0, -1.0));
+        packet.w_dir(Vector3::new(-0.0, 1.0, -0.0));
+        let chunk = packet.w_chunk_open8();
+        packet.w_stringz("привет");
+        packet.w_chunk_close8(chunk);
+
+        assert_eq!(
+            packet.data(),
+            [
+                0x34, 0x12, 0xEF, 0xCD, 0xAB, 0x00, 0x80, 0x80, 0xFF, 0x00, 0x00, 0x20, 0x7E, 0x00,
+                7, 0xEF, 0xF0, 0xE8, 0xE2, 0xE5, 0xF2, 0
+            ]
+        );
+
+        let mut packet = NetPacket::from_bytes(packet.data()).unwrap();
+        assert_eq!(packet.r_begin().unwrap(), 0x1234);
+        assert_eq!(packet.r_u24().unwrap(), 0xABCDEF);
+        assert_eq!(packet.r_float_q16(0.0, 1.0).unwrap(), 32768.0 / 65535.0);
+        assert_eq!(packet.r_angle8().unwrap(), 128.0 / 255.0001 * TAU);
+        assert_eq!(packet.r_dir().unwrap(), Vector3::new(1.0, 0.0, 0.0));
+        assert_eq!(packet.r_dir().unwrap(), Vector3::new(0.0, 0.0, -1.0));
+        assert_eq!(packet.r_dir().unwrap(), Vector3::new(0.0, 1.0, 0.0));
+        assert_eq!(packet.r_u8().unwrap(), 7);
+        assert_eq!(packet.r_stringz().unwrap(), "привет");
+        assert!(packet.r_eof());
+        assert_eq!(
+            packet.r_u8().unwrap_err().to_string(),
+            "unexpected end of data at 22, needed 1 bytes but 0 are left"
+        );
+
+        assert_eq!(angle_normalize(-std::f32::consts::FRAC_PI_2), 0.75 * TAU);
+    }
+
+    fn direction() -> impl Strategy<Value = Vector3<f32>> {
+        (-1.0f32..1.0, -1.0f32..1.0, -1.0f32..1.0)
+            .prop_filter("too short", |(x, y, z)| x.abs() + y.abs() + z.abs() > 1e-3)
+            .prop_map(|(x, y, z)| Vector3::new(x, y, z))
+    }
+
+    proptest! {
+        #[test]
+        fn test_round_trip(
+            values in (any::<u8>(), any::<u16>(), any::<u32>(), any::<u64>()),
+            signed in (any::<i8>(), any::<i16>(), any::<i32>(), any::<i64>()),
+            float in any::<f32>(),
+            vec3 in any::<[f32; 3]>(),
+            // What the game writes, text in the codepage that can't be mistaken for UTF-8
+            text in "[ -~а-яА-Я]{0,64}",
+            u24 in 0u32..1 << 24,
+            client in any::<u32>(),
+        ) {
+            let mut packet = NetPacket::new();
+            packet.w_u8(values.0);
+            packet.w_u16(values.1);
+            packet.w_u32(values.2);
+            packet.w_u64(values.3);
+            packet.w_s8(signed.0);
+            packet.w_s16(signed.1);
+            packet.w_s32(signed.2);
+            packet.w_s64(signed.3);
+            packet.w_float(float);
+            packet.w_vec3(vec3.into());
+            packet.w_stringz(&text);
+            packet.w_u24(u24);
+            packet.w_client_id(ClientId(client));
+
+            let mut packet = NetPacket::from_bytes(packet.data()).unwrap();
+            prop_assert_eq!(packet.r_u8().unwrap(), values.0);
+            prop_assert_eq!(packet.r_u16().unwrap(), values.1);
+            prop_assert_eq!(packet.r_u32().unwrap(), values.2);
+            prop_assert_eq!(packet.r_u64().unwrap(), values.3);
+            prop_assert_eq!(packet.r_s8().unwrap(), signed.0);
+            prop_assert_eq!(packet.r_s16().unwrap(), signed.1);
+            prop_assert_eq!(packet.r_s32().unwrap(), signed.2);
+            prop_assert_eq!(packet.r_s64().unwrap(), signed.3);
+            prop_assert_eq!(packet.r_float().unwrap().to_bits(), float.to_bits());
+            let read: [f32; 3] = packet.r_vec3().unwrap().into();
+            prop_assert_eq!(read.map(f32::to_bits), vec3.map(f32::to_bits));
+            prop_assert_eq!(packet.r_stringz().unwrap(), text);
+            prop_assert_eq!(packet.r_u24().unwrap(), u24);
+            prop_assert_eq!(packet.r_client_id().unwrap(), ClientId(client));
+            prop_assert!(packet.r_eof());
+        }
+
+        #[test]
+        fn test_quantized_round_trip(
+            q16 in -100.0f32..100.0,
+            q8 in 0.0f32..1.0,
+            angle in -20.0f32..20.0,
+            direction in direction(),
+            length in 0.01f32..1000.0,
+        ) {
+            let mut packet = NetPacket::new();
+            packet.w_float_q16(q16, -100.0, 100.0);
+            packet.w_float_q8(q8, 0.0, 1.0);
+            packet.w_angle16(angle);
+            packet.w_angle8(angle);
+            packet.w_dir(direction);
+            packet.w_sdir(direction.normalize() * length);
+
+            let mut packet = NetPacket::from_bytes(packet.data()).unwrap();
+            prop_assert!((packet.r_float_q16(-100.0, 100.0).unwrap() - q16).abs() <= 200.0 / 65535.0);
+            prop_assert!((packet.r_float_q8(0.0, 1.0).unwrap() - q8).abs() <= 1.0 / 255.0);
+
+            let normalized = angle_normalize(angle);
+            let angle_error = |read: f32| {
+                let error = (read - normalized).abs();
+                error.min(TAU - error)
+            };
+            prop_assert!(angle_error(packet.r_angle16().unwrap()) <= TAU / 65535.0);
+            prop_assert!(angle_error(packet.r_angle8().unwrap()) <= TAU / 255.0);
+
+            // The 13 bits leave about a degree and a half between directions
+            let read = packet.r_dir().unwrap();
+            prop_assert!((read.magnitude() - 1.0).abs() < 1e-5);
+            prop_assert!(read.dot(direction.normalize()) > 0.999);
+
+            let read = packet.r_sdir().unwrap();
+            prop_assert!((read.magnitude() - length).abs() <= length * 1e-5);
+            prop_assert!(read.normalize().dot(direction.normalize()) > 0.999);
+        }
+
+        #[test]
+        fn test_normal_codes(packed in any::<u16>()) {
+            // Every code, including the ones folded onto others, is a unit vector
+            // that compresses to a code for about the same direction
+            let direction = normal::decompress(packed);
+            prop_assert!((direction.magnitude() - 1.0).abs() < 1e-5);
+            prop_assert!(normal::decompress(normal::compress(direction)).dot(direction) > 0.999);
+        }
+    }
+}
@@ -0,0 +1,74 @@
+//! Unit vectors packed into 16 bits, XRay's `pvCompress` and `pvDecompress`.
+//!
+//! The top 3 bits are the signs of x, y and z. The vector is projected onto the
+//! plane through (1,0,0), (0,1,0) and (0,0,1), where the triangle between them is
+//! folded into a 127 by 64 rectangle of 13 bits.
+
+use cgmath::Vector3;
+
+const X_SIGN: u16 = 0x8000;
+const Y_SIGN: u16 = 0x4000;
+const Z_SIGN: u16 = 0x2000;
+const X_BITS: u16 = 0x1F80;
+const Y_BITS: u16 = 0x007F;
+
+/// Packs a direction, which doesn't need to be unit length.
+pub fn compress(vector: Vector3<f32>) -> u16 {
+    let mut packed = 0;
+
+    // Not the sign bit, -0.0 is packed as positive like in XRay
+    if vector.x < 0.0 {
+        packed |= X_SIGN;
+    }
+    if vector.y < 0.0 {
+        packed |= Y_SIGN;
+    }
+    if vector.z < 0.0 {
+        packed |= Z_SIGN;
+    }
+
+    let (x, y, z) = (vector.x.abs(), vector.y.abs(), vector.z.abs());
+
+    let w = 126.0 / (x + y + z);
+    let mut x_bits = (x * w).floor() as i32;
+    let mut y_bits = (y * w).floor() as i32;
+
+    if x_bits >= 64 {
+        x_bits = 127 - x_bits;
+        y_bits = 127 - y_bits;
+    }
+
+    packed | ((x_bits << 7) as u16 & X_BITS) | (y_bits as u16 & Y_BITS)
+}
+
+/// Unpacks a unit length direction.
+pub fn decompress(packed: u16) -> Vector3<f32> {
+    let mut x_bits = ((packed & X_BITS) >> 7) as i32;
+    let mut y_bits = (packed & Y_BITS) as i32;
+
+    if x_bits + y_bits >= 127 {
+        x_bits = 127 - x_bits;
+        y_bits = 127 - y_bits;
+    }
+
+    let x = x_bits as f32;
+    let y = y_bits as f32;
+    let z = (126 - x_bits - y_bits) as f32;
+
+    // In the order XRay sums up its lookup table
+    let scale = 1.0 / (y * y + z * z + x * x).sqrt();
+
+    let sign = |mask: u16, value: f32| {
+        if packed & mask != 0 {
+            -value
+        } else {
+            value
+        }
+    };
+
+    Vector3::new(
+        sign(X_SIGN, scale * x),
+        sign(Y_SIGN, scale * y),
+        sign(Z_SIGN, scale * z),
+    )
+}
@@ -0,0 +1,627 @@
+//! OGF visuals, the meshes of objects, characters and level geometry.
+//!
+//! An OGF is a tree of chunks. Every visual has a header, and depending on its
+//! [`ModelType`] it holds a mesh, child visuals, or both along with a skeleton.
+//! Level visuals keep their geometry in the level's shared buffers instead and
+//! only refer to it.
+
+use std::borrow::Cow;
+
+use cgmath::{Matrix4, Vector3};
+use thiserror::Error;
+
+use crate::{
+    encoding,
+    filesystem::Filesystem,
+    omf::{Omf, OmfError, OGF_S_MOTIONS, OGF_S_SMPARAMS},
+    stream::{ReadError, Reader},
+};
+
+pub use vertex::{MeshVertex, VertexFormat};
+
+mod vertex;
+
+pub const MESHES_ROOT: &str = "$game_meshes$";
+
+/// The only version of the format, used since before SoC.
+pub const OGF_VERSION: u8 = 4;
+
+const OGF_HEADER: u32 = 1;
+const OGF_TEXTURE: u32 = 2;
+const OGF_VERTICES: u32 = 3;
+const OGF_INDICES: u32 = 4;
+const OGF_SWIDATA: u32 = 6;
+const OGF_CHILDREN: u32 = 9;
+const OGF_CHILDREN_L: u32 = 10;
+const OGF_TREEDEF2: u32 = 12;
+const OGF_S_BONE_NAMES: u32 = 13;
+const OGF_S_IKDATA: u32 = 16;
+const OGF_S_USERDATA: u32 = 17;
+const OGF_S_MOTION_REFS: u32 = 19;
+const OGF_SWICONTAINER: u32 = 20;
+const OGF_GCONTAINER: u32 = 21;
+const OGF_FASTPATH: u32 = 22;
+const OGF_S_LODS: u32 = 23;
+const OGF_S_MOTION_REFS2: u32 = 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModelType {
+    Normal,
+    Hierarchy,
+    Progressive,
+    SkeletonAnim,
+    SkeletonGeomDefPm,
+    SkeletonGeomDefSt,
+    Lod,
+    TreeSt,
+    ParticleEffect,
+    ParticleGroup,
+    SkeletonRigid,
+    TreePm,
+    /// A volume of smoke or fog simulated on the GPU, only in CoP levels.
+    FluidVolume,
+}
+
+impl ModelType {
+    pub fn from_index(index: u8) -> Option<ModelType> {
+        Some(match index {
+            0 => ModelType::Normal,
+            1 => ModelType::Hierarchy,
+            2 => ModelType::Progressive,
+            3 => ModelType::SkeletonAnim,
+            4 => ModelType::SkeletonGeomDefPm,
+            5 => ModelType::SkeletonGeomDefSt,
+            6 => ModelType::Lod,
+            7 => ModelType::TreeSt,
+            8 => ModelType::ParticleEffect,
+            9 => ModelType::ParticleGroup,
+            10 => ModelType::SkeletonRigid,
+            11 => ModelType::TreePm,
+            12 => ModelType::FluidVolume,
+            _ => return None,
+        })
+    }
+
+    /// Whether the visual has a skeleton, which its children are skinned to.
+    pub fn is_skeleton(self) -> bool {
+        matches!(self, ModelType::SkeletonAnim | ModelType::SkeletonRigid)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OgfHeader {
+    pub model_type: ModelType,
+    /// An index into the level's shader table, for level visuals.
+    pub shader_id: u16,
+    pub bbox_min: Vector3<f32>,
+    pub bbox_max: Vector3<f32>,
+    pub bsphere_center: Vector3<f32>,
+    pub bsphere_radius: f32,
+}
+
+/// Vertices and triangles, independent of how the file stores them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mesh {
+    pub vertex_format: VertexFormat,
+    pub vertices: Vec<MeshVertex>,
+    /// Triangle lists.
+    pub indices: Vec<u16>,
+}
+
+/// A level of detail of a progressive mesh, a window into its indices that
+/// only uses the first vertices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlideWindow {
+    pub offset: u32,
+    pub triangle_count: u16,
+    pub vertex_count: u16,
+}
+
+/// Geometry stored in the level's shared vertex and index buffers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SharedGeometry {
+    pub vertex_buffer: u32,
+    pub vertex_offset: u32,
+    pub vertex_count: u32,
+    pub index_buffer: u32,
+    pub index_offset: u32,
+    pub index_count: u32,
+}
+
+/// An oriented bounding box.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Obb {
+    pub rotation: [Vector3<f32>; 3],
+    pub translation: Vector3<f32>,
+    pub half_size: Vector3<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bone {
+    pub name: String,
+    /// `None` for the root bone.
+    pub parent: Option<String>,
+    pub obb: Obb,
+    /// The physics material. This and the rest are from the IK data, zero without it.
+    pub material: String,
+    /// The bind pose relative to the parent, as XRay's inverted XYZ Euler angles.
+    pub bind_rotation: Vector3<f32>,
+    pub bind_position: Vector3<f32>,
+    pub mass: f32,
+    pub center_of_mass: Vector3<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ogf {
+    pub header: OgfHeader,
+    pub texture: Option<String>,
+    pub shader: Option<String>,
+    pub mesh: Option<Mesh>,
+    pub shared_geometry: Option<SharedGeometry>,
+    /// Position only geometry in the level's `level.geomx` buffers, for shadows and depth.
+    pub fast_path: Option<SharedGeometry>,
+    /// Levels of detail of a progressive mesh, from the most detailed one.
+    pub slide_windows: Vec<SlideWindow>,
+    /// Levels of detail in the level's shared list, by index.
+    pub shared_slide_windows: Option<u32>,
+    /// Where a level's tree is placed.
+    pub tree_transform: Option<Matrix4<f32>>,
+    pub children: Vec<Ogf>,
+    /// Children that are level visuals, by their index in the level.
+    pub child_refs: Vec<u32>,
+    pub bones: Vec<Bone>,
+    /// Names of the `.omf` files with the animations of a skeleton.
+    pub motion_refs: Vec<String>,
+    /// Animations stored in the file itself.
+    pub motions: Option<Omf>,
+    /// Settings for the object in ltx format.
+    pub user_data: Option<String>,
+    /// The visual to draw in the distance instead.
+    pub lod: Option<String>,
+}
+
+impl Ogf {
+    /// Loads a visual from `$game_meshes$`, by its name with or without the `.ogf` extension.
+    pub fn load(filesystem: &Filesystem, name: &str) -> anyhow::Result<Ogf> {
+        let name = name.strip_suffix(".ogf").unwrap_or(name);
+        let path = filesystem.update_path(&format!("{MESHES_ROOT}\\{name}.ogf"))?;
+        let data = filesystem.read(path)?;
+
+        Ok(Ogf::read(
+            &Reader::new(&data).with_codepage(filesystem.codepage()),
+        )?)
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Ogf, OgfError> {
+        Ogf::read(&Reader::new(data))
+    }
+
+    /// Reads a visual from the chunks of a reader, like a child visual or one in a level.
+    pub fn read(reader: &Reader) -> Result<Ogf, OgfError> {
+        let header = read_header(&mut required_chunk(reader, OGF_HEADER)?)?;
+
+        let mut ogf = Ogf {
+            header,
+            texture: None,
+            shader: None,
+            mesh: None,
+            shared_geometry: None,
+            fast_path: None,
+            slide_windows: Vec::new(),
+            shared_slide_windows: None,
+            tree_transform: None,
+            children: Vec::new(),
+            child_refs: Vec::new(),
+            bones: Vec::new(),
+            motion_refs: Vec::new(),
+            motions: None,
+            user_data: None,
+            lod: None,
+        };
+
+        if let Some(mut chunk) = reader.open_chunk(OGF_TEXTURE)? {
+            ogf.texture = Some(chunk.r_stringz()?.into_owned());
+            ogf.shader = Some(chunk.r_stringz()?.into_owned());
+        }
+
+        if let Some(mut chunk) = reader.open_chunk(OGF_VERTICES)? {
+            ogf.mesh = Some(read_mesh(
+                &mut chunk,
+                &mut required_chunk(reader, OGF_INDICES)?,
+            )?);
+        }
+
+        if let Some(mut chunk) = reader.open_chunk(OGF_GCONTAINER)? {
+            ogf.shared_geometry = Some(read_shared_geometry(&mut chunk)?);
+        }
+
+        if let Some(chunk) = reader.open_chunk(OGF_FASTPATH)? {
+            if let Some(mut chunk) = chunk.open_chunk(OGF_GCONTAINER)? {
+                ogf.fast_path = Some(read_shared_geometry(&mut chunk)?);
+            }
+        }
+
+        if let Some(mut chunk) = reader.open_chunk(OGF_SWICONTAINER)? {
+            ogf.shared_slide_windows = Some(chunk.r_u32()?);
+        }
+
+        if let Some(mut chunk) = reader.open_chunk(OGF_TREEDEF2)? {
+            // XRay's rows are the columns here, its matrices transform row vectors
+            ogf.tree_transform = Some(Matrix4::from_cols(
+                chunk.r_fvector4()?,
+                chunk.r_fvector4()?,
+                chunk.r_fvector4()?,
+                chunk.r_fvector4()?,
+            ));
+        }
+
+        if let Some(mut chunk) = reader.open_chunk(OGF_SWIDATA)? {
+            ogf.slide_windows = read_slide_windows(&mut chunk)?;
+        }
+
+        if let Some(chunk) = reader.open_chunk(OGF_CHILDREN)? {
+            for child in chunk.chunks() {
+                ogf.children.push(Ogf::read(&child?.reader)?);
+            }
+        }
+
+        if let Some(mut chunk) = reader.open_chunk(OGF_CHILDREN_L)? {
+            let count = chunk.r_u32()?;
+            for _ in 0..count {
+                ogf.child_refs.push(chunk.r_u32()?);
+            }
+        }
+
+        if let Some(mut chunk) = reader.open_chunk(OGF_S_BONE_NAMES)? {
+            let count = chunk.r_u32()?;
+            for _ in 0..count {
+                ogf.bones.push(read_bone(&mut chunk)?);
+            }
+        }
+
+        if let Some(mut chunk) = reader.open_chunk(OGF_S_IKDATA)? {
+            for bone in &mut ogf.bones {
+                read_bone_ik(&mut chunk, bone)?;
+            }
+        }
+
+        if let Some(mut chunk) = reader.open_chunk(OGF_S_MOTION_REFS2)? {
+            let count = chunk.r_u32()?;
+            for _ in 0..count {
+                ogf.motion_refs.push(chunk.r_stringz()?.into_owned());
+            }
+        } else if let Some(mut chunk) = reader.open_chunk(OGF_S_MOTION_REFS)? {
+            ogf.motion_refs = chunk
+                .r_stringz()?
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(str::to_owned)
+                .collect();
+        }
+
+        if reader.open_chunk(OGF_S_SMPARAMS)?.is_some()
+            && reader.open_chunk(OGF_S_MOTIONS)?.is_some()
+        {
+            ogf.motions = Some(Omf::read(reader)?);
+        }
+
+        if let Some(chunk) = reader.open_chunk(OGF_S_USERDATA)? {
+            ogf.user_data = Some(read_text(&chunk).into_owned());
+        }
+
+        if let Some(chunk) = reader.open_chunk(OGF_S_LODS)? {
+            let lod = read_text(&chunk);
+            let lod = lod.trim();
+            if !lod.is_empty() {
+                ogf.lod = Some(lod.to_owned());
+            }
+        }
+
+        Ok(ogf)
+    }
+
+    /// The indices of a level of detail, all of them for meshes that have no levels.
+    pub fn lod_indices(&self, lod: usize) -> Option<&[u16]> {
+        let indices = &self.mesh.as_ref()?.indices;
+
+        match self.slide_windows.get(lod) {
+            Some(window) => {
+                let start = window.offset as usize;
+                indices.get(start..start + window.triangle_count as usize * 3)
+            }
+            None if self.slide_windows.is_empty() => Some(indices),
+            None => None,
+        }
+    }
+}
+
+fn required_chunk<'a>(reader: &'a Reader, id: u32) -> Result<Reader<'a>, OgfError> {
+    reader.open_chunk(id)?.ok_or(OgfError::MissingChunk(id))
+}
+
+fn read_header(chunk: &mut Reader) -> Result<OgfHeader, OgfError> {
+    let version = chunk.r_u8()?;
+    if version != OGF_VERSION {
+        return Err(OgfError::UnsupportedVersion(version));
+    }
+
+    let model_type = chunk.r_u8()?;
+
+    Ok(OgfHeader {
+        model_type: ModelType::from_index(model_type)
+            .ok_or(OgfError::UnknownModelType(model_type))?,
+        shader_id: chunk.r_u16()?,
+        bbox_min: chunk.r_fvector3()?,
+        bbox_max: chunk.r_fvector3()?,
+        bsphere_center: chunk.r_fvector3()?,
+        bsphere_radius: chunk.r_float()?,
+    })
+}
+
+fn read_shared_geometry(chunk: &mut Reader) -> Result<SharedGeometry, ReadError> {
+    Ok(SharedGeometry {
+        vertex_buffer: chunk.r_u32()?,
+        vertex_offset: chunk.r_u32()?,
+        vertex_count: chunk.r_u32()?,
+        index_buffer: chunk.r_u32()?,
+        index_offset: chunk.r_u32()?,
+        index_count: chunk.r_u32()?,
+    })
+}
+
+pub(crate) fn read_slide_windows(chunk: &mut Reader) -> Result<Vec<SlideWindow>, ReadError> {
+    // Reserved
+    chunk.advance(16)?;
+
+    let count = chunk.r_u32()?;
+    let mut windows = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        windows.push(SlideWindow {
+            offset: chunk.r_u32()?,
+            triangle_count: chunk.r_u16()?,
+            vertex_count: chunk.r_u16()?,
+        });
+    }
+
+    Ok(windows)
+}
+
+fn read_mesh(vertices: &mut Reader, indices: &mut Reader) -> Result<Mesh, OgfError> {
+    let vertex_format = VertexFormat::from_raw(vertices.r_u32()?)?;
+
+    let count = vertices.r_u32()? as usize;
+    let mut mesh = Mesh {
+        vertex_format,
+        vertices: Vec::with_capacity(count),
+        indices: Vec::new(),
+    };
+
+    for _ in 0..count {
+        mesh.vertices
+            .push(vertex::read_vertex(vertices, vertex_format)?);
+    }
+
+    let count = indices.r_u32()? as usize;
+    mesh.indices.reserve(count);
+    for _ in 0..count {
+        mesh.indices.push(indices.r_u16()?);
+    }
+
+    Ok(mesh)
+}
+
+fn read_bone(chunk: &mut Reader) -> Result<Bone, ReadError> {
+    let name = chunk.r_stringz()?.into_owned();
+    let parent = chunk.r_stringz()?;
+    let parent = (!parent.is_empty()).then(|| parent.into_owned());
+
+    Ok(Bone {
+        name,
+        parent,
+        obb: Obb {
+            rotation: [
+                chunk.r_fvector3()?,
+                chunk.r_fvector3()?,
+                chunk.r_fvector3()?,
+            ],
+            translation: chunk.r_fvector3()?,
+            half_size: chunk.r_fvector3()?,
+        },
+        material: String::new(),
+        bind_rotation: Vector3::new(0.0, 0.0, 0.0),
+        bind_position: Vector3::new(0.0, 0.0, 0.0),
+        mass: 0.0,
+        center_of_mass: Vector3::new(0.0, 0.0, 0.0),
+    })
+}
+
+fn read_bone_ik(chunk: &mut Reader, bone: &mut Bone) -> Result<(), ReadError> {
+    let version = chunk.r_u32()?;
+    bone.material = chunk.r_stringz()?.into_owned();
+
+    // The collision shape and the joint, which only physics needs
+    chunk.advance(112)?;
+    chunk.advance(if version > 0 { 76 } else { 72 })?;
+
+    bone.bind_rotation = chunk.r_fvector3()?;
+    bone.bind_position = chunk.r_fvector3()?;
+    bone.mass = chunk.r_float()?;
+    bone.center_of_mass = chunk.r_fvector3()?;
+
+    Ok(())
+}
+
+/// A chunk holding text, which may or may not be zero terminated.
+fn read_text<'a>(chunk: &'a Reader) -> Cow<'a, str> {
+    let data = chunk.data();
+    let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+
+    encoding::decode(&data[..end], chunk.codepage())
+}
+
+#[derive(Debug, Error)]
+pub enum OgfError {
+    #[error("missing chunk {0}")]
+    MissingChunk(u32),
+    #[error("unsupported version {0}")]
+    UnsupportedVersion(u8),
+    #[error("unknown model type {0}")]
+    UnknownModelType(u8),
+    #[error("unsupported vertex format {0:#x}")]
+    UnsupportedVertexFormat(u32),
+    #[error("embedded motions: {0}")]
+    Motions(#[from] OmfError),
+    #[error(transparent)]
+    Read(#[from] ReadError),
+}
+
+#[cfg(test)]
+mod test {
+    use crate::stream::Writer;
+
+    use super::*;
+
+    fn write_header(writer: &mut Writer, model_type: u8) {
+        writer.open_chunk(OGF_HEADER);
+        writer.w_u8(OGF_VERSION);
+        writer.w_u8(model_type);
+        writer.w_u16(0);
+        writer.w_fvector3(Vector3::new(-1.0, -1.0, -1.0));
+        writer.w_fvector3(Vector3::new(1.0, 1.0, 1.0));
+        writer.w_fvector3(Vector3::new(0.0, 0.0, 0.0));
+        writer.w_float(1.5);
+        writer.close_chunk();
+    }
+
+    fn write_vector(writer: &mut Writer, value: f32) {
+        writer.w_fvector3(Vector3::new(value, value, value));
+    }
+
+    #[test]
+    fn test_parse() {
+        let mut writer = Writer::new();
+        write_header(&mut writer, 3);
+
+        writer.open_chunk(OGF_S_BONE_NAMES);
+        writer.w_u32(2);
+        for (name, parent) in [("bip01", ""), ("bip01_spine", "bip01")] {
+            writer.w_stringz(name);
+            writer.w_stringz(parent);
+            for _ in 0..15 {
+                writer.w_float(0.0);
+            }
+        }
+        writer.close_chunk();
+
+        writer.open_chunk(OGF_S_IKDATA);
+        for position in [0.0, 0.5] {
+            writer.w_u32(1);
+            writer.w_stringz("materials\\bone");
+            writer.w_bytes(&[0; 112 + 76]);
+            writer.w_fvector3(Vector3::new(0.0, 0.0, 0.0));
+            writer.w_fvector3(Vector3::new(0.0, position, 0.0));
+            writer.w_float(10.0);
+            writer.w_fvector3(Vector3::new(0.0, 0.0, 0.0));
+        }
+        writer.close_chunk();
+
+        writer.open_chunk(OGF_S_MOTION_REFS2);
+        writer.w_u32(2);
+        writer.w_stringz("stalker_animation");
+        writer.w_stringz("stalker_hand");
+        writer.close_chunk();
+
+        writer.w_chunk(OGF_S_USERDATA, b"[ph_skeleton]\r\n\0");
+
+        writer.open_chunk(OGF_CHILDREN);
+        writer.open_chunk(0);
+        write_header(&mut writer, 4);
+
+        writer.open_chunk(OGF_TEXTURE);
+        writer.w_stringz("act\\act_stalker_head");
+        writer.w_stringz("models\\model");
+        writer.close_chunk();
+
+        writer.open_chunk(OGF_VERTICES);
+        writer.w_u32(4 * 0x12071980);
+        writer.w_u32(3);
+        for i in 0..3 {
+            writer.w_u16(0);
+            writer.w_u16(1);
+            writer.w_u16(i);
+            write_vector(&mut writer, i as f32);
+            for _ in 0..3 {
+                write_vector(&mut writer, 0.0);
+            }
+            writer.w_float(0.25);
+            writer.w_float(0.5);
+            writer.w_fvector2([0.5, i as f32].into());
+        }
+        writer.close_chunk();
+
+        writer.open_chunk(OGF_INDICES);
+        writer.w_u32(3);
+        for i in [0, 2, 1] {
+            writer.w_u16(i);
+        }
+        writer.close_chunk();
+
+        writer.open_chunk(OGF_SWIDATA);
+        writer.w_bytes(&[0; 16]);
+        writer.w_u32(2);
+        writer.w_u32(0);
+        writer.w_u16(1);
+        writer.w_u16(3);
+        writer.w_u32(3);
+        writer.w_u16(0);
+        writer.w_u16(0);
+        writer.close_chunk();
+
+        writer.close_chunk();
+        writer.close_chunk();
+
+        let ogf = Ogf::parse(&writer.into_inner()).unwrap();
+        assert_eq!(ogf.header.model_type, ModelType::SkeletonAnim);
+        assert_eq!(ogf.header.bsphere_radius, 1.5);
+        assert!(ogf.mesh.is_none());
+        assert_eq!(ogf.bones.len(), 2);
+        assert_eq!(ogf.bones[0].parent, None);
+        assert_eq!(ogf.bones[1].parent.as_deref(), Some("bip01"));
+        assert_eq!(ogf.bones[1].material, "materials\\bone");
+        assert_eq!(ogf.bones[1].bind_position, Vector3::new(0.0, 0.5, 0.0));
+        assert_eq!(ogf.bones[1].mass, 10.0);
+        assert_eq!(ogf.motion_refs, ["stalker_animation", "stalker_hand"]);
+        assert!(ogf.motions.is_none());
+        assert_eq!(ogf.user_data.as_deref(), Some("[ph_skeleton]\r\n"));
+
+        let child = &ogf.children[0];
+        assert_eq!(child.header.model_type, ModelType::SkeletonGeomDefPm);
+        assert_eq!(child.texture.as_deref(), Some("act\\act_stalker_head"));
+        assert_eq!(child.shader.as_deref(), Some("models\\model"));
+
+        let mesh = child.mesh.as_ref().unwrap();
+        assert_eq!(mesh.vertex_format, VertexFormat::Skinned(3));
+        assert_eq!(mesh.vertices[2].position, [2.0; 3]);
+        assert_eq!(mesh.vertices[2].bones, [0, 1, 2, 0]);
+        assert_eq!(mesh.vertices[2].weights, [0.25, 0.5, 0.25, 0.0]);
+        assert_eq!(mesh.vertices[2].uv, [0.5, 2.0]);
+        assert_eq!(mesh.indices, [0, 2, 1]);
+
+        assert_eq!(child.lod_indices(0), Some(&[0, 2, 1][..]));
+        assert_eq!(child.lod_indices(1), Some(&[][..]));
+        assert_eq!(child.lod_indices(2), None);
+
+        let mut writer = Writer::new();
+        write_header(&mut writer, 12);
+        let ogf = Ogf::parse(&writer.into_inner()).unwrap();
+        assert_eq!(ogf.header.model_type, ModelType::FluidVolume);
+
+        let mut writer = Writer::new();
+        write_header(&mut writer, 13);
+        assert!(matches!(
+            Ogf::parse(&writer.into_inner()),
+            Err(OgfError::UnknownModelType(13))
+        ));
+    }
+}
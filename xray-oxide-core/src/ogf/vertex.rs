@@ -0,0 +1,175 @@
+use crate::stream::{ReadError, Reader};
+
+use super::OgfError;
+
+const FVF_POSITION_MASK: u32 = 0x400E;
+const FVF_XYZ: u32 = 0x002;
+const FVF_NORMAL: u32 = 0x010;
+const FVF_PSIZE: u32 = 0x020;
+const FVF_DIFFUSE: u32 = 0x040;
+const FVF_SPECULAR: u32 = 0x080;
+const FVF_TEXCOUNT_MASK: u32 = 0xF00;
+const FVF_TEXCOUNT_SHIFT: u32 = 8;
+
+const SKINNED_1: u32 = 0x12071980;
+const SKINNED_2: u32 = 2 * 0x12071980;
+const SKINNED_3: u32 = 4 * 0x12071980;
+const SKINNED_4: u32 = 5 * 0x12071980;
+
+/// How the vertices of a mesh are stored in the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VertexFormat {
+    /// A Direct3D flexible vertex format, for static meshes.
+    Fvf(u32),
+    /// Skinned to this many bones, 1 to 4.
+    Skinned(u8),
+    /// A Direct3D vertex declaration, for level geometry.
+    Declaration,
+}
+
+impl VertexFormat {
+    pub(super) fn from_raw(raw: u32) -> Result<VertexFormat, OgfError> {
+        match raw {
+            SKINNED_1 => Ok(VertexFormat::Skinned(1)),
+            SKINNED_2 => Ok(VertexFormat::Skinned(2)),
+            SKINNED_3 => Ok(VertexFormat::Skinned(3)),
+            SKINNED_4 => Ok(VertexFormat::Skinned(4)),
+            fvf if fvf & FVF_POSITION_MASK == FVF_XYZ => Ok(VertexFormat::Fvf(fvf)),
+            _ => Err(OgfError::UnsupportedVertexFormat(raw)),
+        }
+    }
+
+    /// How many bones each vertex is skinned to, 0 for static meshes.
+    pub fn bone_count(self) -> usize {
+        match self {
+            VertexFormat::Fvf(_) | VertexFormat::Declaration => 0,
+            VertexFormat::Skinned(bones) => bones as usize,
+        }
+    }
+}
+
+/// A vertex of any format. What a format doesn't store is zero, apart from
+/// the colour, which is white.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MeshVertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub tangent: [f32; 3],
+    pub binormal: [f32; 3],
+    pub uv: [f32; 2],
+    pub lightmap_uv: [f32; 2],
+    /// A Direct3D colour, `0xAARRGGBB`.
+    pub color: u32,
+    pub bones: [u16; 4],
+    /// How much each bone moves the vertex, adding up to 1 for skinned vertices.
+    pub weights: [f32; 4],
+}
+
+impl Default for MeshVertex {
+    fn default() -> Self {
+        MeshVertex {
+            position: [0.0; 3],
+            normal: [0.0; 3],
+            tangent: [0.0; 3],
+            binormal: [0.0; 3],
+            uv: [0.0; 2],
+            lightmap_uv: [0.0; 2],
+            color: 0xFFFFFFFF,
+            bones: [0; 4],
+            weights: [0.0; 4],
+        }
+    }
+}
+
+fn r_vec3(reader: &mut Reader) -> Result<[f32; 3], ReadError> {
+    Ok(reader.r_fvector3()?.into())
+}
+
+fn r_vec2(reader: &mut Reader) -> Result<[f32; 2], ReadError> {
+    Ok(reader.r_fvector2()?.into())
+}
+
+pub(super) fn read_vertex(
+    reader: &mut Reader,
+    format: VertexFormat,
+) -> Result<MeshVertex, ReadError> {
+    let mut vertex = MeshVertex::default();
+
+    match format {
+        VertexFormat::Fvf(fvf) => {
+            vertex.position = r_vec3(reader)?;
+
+            if fvf & FVF_NORMAL != 0 {
+                vertex.normal = r_vec3(reader)?;
+            }
+            if fvf & FVF_PSIZE != 0 {
+                reader.advance(4)?;
+            }
+            if fvf & FVF_DIFFUSE != 0 {
+                vertex.color = reader.r_u32()?;
+            }
+            if fvf & FVF_SPECULAR != 0 {
+                reader.advance(4)?;
+            }
+
+            let tex_count = (fvf & FVF_TEXCOUNT_MASK) >> FVF_TEXCOUNT_SHIFT;
+            for set in 0..tex_count {
+                // Two bits per set from bit 16: 2, 3, 4 or 1 floats
+                let floats = match (fvf >> (16 + set * 2)) & 3 {
+                    0 => 2,
+                    1 => 3,
+                    2 => 4,
+                    _ => 1,
+                };
+
+                if set == 0 && floats >= 2 {
+                    vertex.uv = r_vec2(reader)?;
+                    reader.advance((floats - 2) * 4)?;
+                } else {
+                    reader.advance(floats * 4)?;
+                }
+            }
+        }
+        VertexFormat::Skinned(1) => {
+            vertex.position = r_vec3(reader)?;
+            vertex.normal = r_vec3(reader)?;
+            vertex.tangent = r_vec3(reader)?;
+            vertex.binormal = r_vec3(reader)?;
+            vertex.uv = r_vec2(reader)?;
+            vertex.bones[0] = reader.r_u32()? as u16;
+            vertex.weights[0] = 1.0;
+        }
+        VertexFormat::Skinned(bones) => {
+            let bones = bones as usize;
+
+            for bone in &mut vertex.bones[..bones] {
+                *bone = reader.r_u16()?;
+            }
+
+            vertex.position = r_vec3(reader)?;
+            vertex.normal = r_vec3(reader)?;
+            vertex.tangent = r_vec3(reader)?;
+            vertex.binormal = r_vec3(reader)?;
+
+            if bones == 2 {
+                // The weight of the second bone, the first one gets the rest
+                let weight = reader.r_float()?;
+                vertex.weights[..2].copy_from_slice(&[1.0 - weight, weight]);
+            } else {
+                let mut rest = 1.0;
+                for weight in &mut vertex.weights[..bones - 1] {
+                    *weight = reader.r_float()?;
+                    rest -= *weight;
+                }
+                vertex.weights[bones - 1] = rest;
+            }
+
+            vertex.uv = r_vec2(reader)?;
+        }
+        VertexFormat::Declaration => {
+            unreachable!("OGF files don't store vertex declarations")
+        }
+    }
+
+    Ok(vertex)
+}
@@ -0,0 +1,423 @@
+//! OMF skeletal animations, the motions of characters and weapons.
+//!
+//! An OMF holds the same two chunks a skeleton's OGF can embed: the keyframes
+//! of every motion, and the parameters that split the skeleton into bone parts
+//! and describe how each motion is played.
+
+use cgmath::{Quaternion, Vector3};
+use thiserror::Error;
+
+use crate::{
+    filesystem::Filesystem,
+    ogf::MESHES_ROOT,
+    stream::{ReadError, Reader},
+};
+
+pub use sampler::{MotionState, Pose, Sampler, Skeleton};
+
+mod sampler;
+
+pub(crate) const OGF_S_MOTIONS: u32 = 14;
+pub(crate) const OGF_S_SMPARAMS: u32 = 15;
+
+/// The newest version of the motion parameters, which added marks.
+pub const SMPARAMS_VERSION: u16 = 4;
+
+/// Keyframes are sampled at a fixed rate.
+pub const SAMPLE_FPS: f32 = 30.0;
+
+const KEY_TRANSLATION_PRESENT: u8 = 1 << 0;
+const KEY_ROTATION_ABSENT: u8 = 1 << 1;
+const KEY_TRANSLATION_16_BIT: u8 = 1 << 2;
+
+const KEY_QUANT: f32 = 32767.0;
+
+pub const MOTION_FX: u32 = 1 << 0;
+pub const MOTION_STOP_AT_END: u32 = 1 << 1;
+pub const MOTION_NO_MIX: u32 = 1 << 2;
+pub const MOTION_SYNC_PART: u32 = 1 << 3;
+pub const MOTION_USE_FOOTSTEPS: u32 = 1 << 4;
+pub const MOTION_ROOT_MOVER: u32 = 1 << 5;
+pub const MOTION_IDLE: u32 = 1 << 6;
+pub const MOTION_USE_WEAPON_BONE: u32 = 1 << 7;
+
+/// Applies to every bone part in [`MotionDef::bone_or_part`].
+pub const ALL_PARTS: u16 = 0xFFFF;
+
+/// A group of bones that plays its own motions, like the torso and the legs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BonePart {
+    pub name: String,
+    pub bones: Vec<PartBone>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartBone {
+    /// Only stored since version 2, earlier ones refer to skeleton bones by index.
+    pub name: Option<String>,
+    /// The index of the bone in the keyframes of every motion.
+    pub motion_bone: u32,
+}
+
+/// Named intervals of a motion, in seconds, like when a foot touches the ground.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MotionMarks {
+    pub name: String,
+    pub intervals: Vec<(f32, f32)>,
+}
+
+/// How a motion is played.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MotionDef {
+    pub name: String,
+    pub flags: u32,
+    /// The bone an effect plays on, or the part a cycle plays on.
+    pub bone_or_part: u16,
+    /// The index of the keyframes in [`Omf::motions`].
+    pub motion: u16,
+    pub speed: f32,
+    pub power: f32,
+    pub accrue: f32,
+    pub falloff: f32,
+    pub marks: Vec<MotionMarks>,
+}
+
+impl MotionDef {
+    pub fn is_fx(&self) -> bool {
+        self.flags & MOTION_FX != 0
+    }
+
+    pub fn stops_at_end(&self) -> bool {
+        self.flags & MOTION_STOP_AT_END != 0
+    }
+
+    pub fn is_no_mix(&self) -> bool {
+        self.flags & MOTION_NO_MIX != 0
+    }
+}
+
+/// The keyframes of one bone. Rotations and translations hold either one key
+/// for the whole motion or one per frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BoneMotion {
+    /// In XRay's convention, see [`Sampler`].
+    pub rotations: Vec<Quaternion<f32>>,
+    pub translations: Vec<Vector3<f32>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Motion {
+    pub name: String,
+    pub frame_count: u32,
+    /// By [`PartBone::motion_bone`].
+    pub bones: Vec<BoneMotion>,
+}
+
+impl Motion {
+    /// The length in seconds of one loop.
+    pub fn length(&self) -> f32 {
+        self.frame_count as f32 / SAMPLE_FPS
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Omf {
+    pub version: u16,
+    pub parts: Vec<BonePart>,
+    pub definitions: Vec<MotionDef>,
+    pub motions: Vec<Motion>,
+}
+
+impl Omf {
+    /// Loads motions from `$game_meshes$`, by their name with or without the `.omf` extension.
+    pub fn load(filesystem: &Filesystem, name: &str) -> anyhow::Result<Omf> {
+        let name = name.strip_suffix(".omf").unwrap_or(name);
+        let path = filesystem.update_path(&format!("{MESHES_ROOT}\\{name}.omf"))?;
+        let data = filesystem.read(path)?;
+
+        Ok(Omf::read(
+            &Reader::new(&data).with_codepage(filesystem.codepage()),
+        )?)
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Omf, OmfError> {
+        Omf::read(&Reader::new(data))
+    }
+
+    /// Reads motions from the chunks of a reader, like an OGF with embedded ones.
+    pub fn read(reader: &Reader) -> Result<Omf, OmfError> {
+        let mut params = reader
+            .open_chunk(OGF_S_SMPARAMS)?
+            .ok_or(OmfError::MissingChunk(OGF_S_SMPARAMS))?;
+
+        let version = params.r_u16()?;
+        if version == 0 || version > SMPARAMS_VERSION {
+            return Err(OmfError::UnsupportedVersion(version));
+        }
+
+        let part_count = params.r_u16()?;
+        let mut parts = Vec::with_capacity(part_count as usize);
+        for _ in 0..part_count {
+            parts.push(read_part(&mut params, version)?);
+        }
+
+        let bone_count = parts.iter().map(|part| part.bones.len()).sum();
+
+        let definition_count = params.r_u16()?;
+        let mut definitions = Vec::with_capacity(definition_count as usize);
+        for _ in 0..definition_count {
+            definitions.push(read_definition(&mut params, version)?);
+        }
+
+        let keys = reader
+            .open_chunk(OGF_S_MOTIONS)?
+            .ok_or(OmfError::MissingChunk(OGF_S_MOTIONS))?;
+
+        let motion_count = keys
+            .open_chunk(0)?
+            .ok_or(OmfError::MissingChunk(0))?
+            .r_u32()?;
+
+        let mut motions = Vec::with_capacity(motion_count as usize);
+        for index in 1..=motion_count {
+            let mut chunk = keys
+                .open_chunk(index)?
+                .ok_or(OmfError::MissingChunk(index))?;
+            motions.push(read_motion(&mut chunk, bone_count)?);
+        }
+
+        Ok(Omf {
+            version,
+            parts,
+            definitions,
+            motions,
+        })
+    }
+
+    pub fn find_definition(&self, name: &str) -> Option<&MotionDef> {
+        self.definitions
+            .iter()
+            .find(|definition| definition.name.eq_ignore_ascii_case(name))
+    }
+}
+
+fn read_part(params: &mut Reader, version: u16) -> Result<BonePart, ReadError> {
+    let name = params.r_stringz()?.to_lowercase();
+
+    let count = params.r_u16()?;
+    let mut bones = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let name = if version >= 2 {
+            Some(params.r_stringz()?.to_lowercase())
+        } else {
+            None
+        };
+
+        bones.push(PartBone {
+            name,
+            motion_bone: params.r_u32()?,
+        });
+    }
+
+    Ok(BonePart { name, bones })
+}
+
+fn read_definition(params: &mut Reader, version: u16) -> Result<MotionDef, ReadError> {
+    let mut definition = MotionDef {
+        name: params.r_stringz()?.to_lowercase(),
+        flags: params.r_u32()?,
+        bone_or_part: params.r_u16()?,
+        motion: params.r_u16()?,
+        speed: params.r_float()?,
+        power: params.r_float()?,
+        accrue: params.r_float()?,
+        falloff: params.r_float()?,
+        marks: Vec::new(),
+    };
+
+    if version >= 4 {
+        let count = params.r_u32()?;
+        for _ in 0..count {
+            let name = params.r_string()?.into_owned();
+
+            let count = params.r_u32()?;
+            let mut intervals = Vec::with_capacity(count as usize);
+            for _ in 0..count {
+                intervals.push((params.r_float()?, params.r_float()?));
+            }
+
+            definition.marks.push(MotionMarks { name, intervals });
+        }
+    }
+
+    Ok(definition)
+}
+
+fn read_motion(chunk: &mut Reader, bone_count: usize) -> Result<Motion, ReadError> {
+    let name = chunk.r_stringz()?.to_lowercase();
+    let frame_count = chunk.r_u32()?;
+
+    let mut bones = Vec::with_capacity(bone_count);
+    for _ in 0..bone_count {
+        bones.push(read_bone_motion(chunk, frame_count)?);
+    }
+
+    Ok(Motion {
+        name,
+        frame_count,
+        bones,
+    })
+}
+
+fn read_bone_motion(chunk: &mut Reader, frame_count: u32) -> Result<BoneMotion, ReadError> {
+    let flags = chunk.r_u8()?;
+
+    let rotation_count = if flags & KEY_ROTATION_ABSENT != 0 {
+        1
+    } else {
+        let _crc = chunk.r_u32()?;
+        frame_count
+    };
+
+    let mut rotations = Vec::with_capacity(rotation_count as usize);
+    for _ in 0..rotation_count {
+        let x = chunk.r_s16()? as f32 / KEY_QUANT;
+        let y = chunk.r_s16()? as f32 / KEY_QUANT;
+        let z = chunk.r_s16()? as f32 / KEY_QUANT;
+        let w = chunk.r_s16()? as f32 / KEY_QUANT;
+        rotations.push(Quaternion::new(w, x, y, z));
+    }
+
+    let translations = if flags & KEY_TRANSLATION_PRESENT != 0 {
+        let _crc = chunk.r_u32()?;
+
+        let mut keys = Vec::with_capacity(frame_count as usize);
+        for _ in 0..frame_count {
+            let key = if flags & KEY_TRANSLATION_16_BIT != 0 {
+                [chunk.r_s16()?, chunk.r_s16()?, chunk.r_s16()?].map(f32::from)
+            } else {
+                [chunk.r_s8()?, chunk.r_s8()?, chunk.r_s8()?].map(f32::from)
+            };
+            keys.push(Vector3::from(key));
+        }
+
+        let size = chunk.r_fvector3()?;
+        let init = chunk.r_fvector3()?;
+
+        keys.iter()
+            .map(|key| Vector3::new(key.x * size.x, key.y * size.y, key.z * size.z) + init)
+            .collect()
+    } else {
+        vec![chunk.r_fvector3()?]
+    };
+
+    Ok(BoneMotion {
+        rotations,
+        translations,
+    })
+}
+
+#[derive(Debug, Error)]
+pub enum OmfError {
+    #[error("missing chunk {0}")]
+    MissingChunk(u32),
+    #[error("unsupported motion parameters version {0}")]
+    UnsupportedVersion(u16),
+    #[error(transparent)]
+    Read(#[from] ReadError),
+}
+
+#[cfg(test)]
+mod test {
+    use crate::stream::Writer;
+
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let mut writer = Writer::new();
+
+        writer.open_chunk(OGF_S_SMPARAMS);
+        writer.w_u16(SMPARAMS_VERSION);
+        writer.w_u16(1);
+        writer.w_stringz("Default");
+        writer.w_u16(2);
+        writer.w_stringz("bip01");
+        writer.w_u32(0);
+        writer.w_stringz("bip01_head");
+        writer.w_u32(1);
+
+        writer.w_u16(1);
+        writer.w_stringz("idle");
+        writer.w_u32(MOTION_STOP_AT_END | MOTION_IDLE);
+        writer.w_u16(0);
+        writer.w_u16(0);
+        for value in [1.0, 2.0, 3.0, 4.0] {
+            writer.w_float(value);
+        }
+        writer.w_u32(1);
+        writer.w_string("step");
+        writer.w_u32(1);
+        writer.w_float(0.1);
+        writer.w_float(0.2);
+        writer.close_chunk();
+
+        writer.open_chunk(OGF_S_MOTIONS);
+        writer.open_chunk(0);
+        writer.w_u32(1);
+        writer.close_chunk();
+        writer.open_chunk(1);
+        writer.w_stringz("idle");
+        writer.w_u32(2);
+
+        // Constant rotation and translation
+        writer.w_u8(KEY_ROTATION_ABSENT);
+        for value in [0, 0, 0, 32767] {
+            writer.w_s16(value);
+        }
+        writer.w_fvector3(Vector3::new(0.0, 1.0, 0.0));
+
+        // Both keyed, with 8 bit translations
+        writer.w_u8(KEY_TRANSLATION_PRESENT);
+        writer.w_u32(0);
+        for value in [0, 0, 0, 32767, 0, 0, 32767, 0] {
+            writer.w_s16(value);
+        }
+        writer.w_u32(0);
+        for value in [0, 0, 0, 10, -10, 0] {
+            writer.w_s8(value);
+        }
+        writer.w_fvector3(Vector3::new(0.5, 0.5, 0.5));
+        writer.w_fvector3(Vector3::new(0.0, 2.0, 0.0));
+        writer.close_chunk();
+        writer.close_chunk();
+
+        let omf = Omf::parse(&writer.into_inner()).unwrap();
+        assert_eq!(omf.parts[0].name, "default");
+        assert_eq!(omf.parts[0].bones[1].name.as_deref(), Some("bip01_head"));
+
+        let idle = omf.find_definition("IDLE").unwrap();
+        assert!(idle.stops_at_end());
+        assert!(!idle.is_fx());
+        assert_eq!(idle.accrue, 3.0);
+        assert_eq!(idle.marks[0].name, "step");
+        assert_eq!(idle.marks[0].intervals, [(0.1, 0.2)]);
+
+        let motion = &omf.motions[idle.motion as usize];
+        assert_eq!(motion.frame_count, 2);
+        assert_eq!(motion.length(), 2.0 / SAMPLE_FPS);
+        assert_eq!(
+            motion.bones[0].rotations,
+            [Quaternion::new(1.0, 0.0, 0.0, 0.0)]
+        );
+        assert_eq!(motion.bones[0].translations, [Vector3::new(0.0, 1.0, 0.0)]);
+        assert_eq!(
+            motion.bones[1].rotations[1],
+            Quaternion::new(0.0, 0.0, 0.0, 1.0)
+        );
+        assert_eq!(
+            motion.bones[1].translations,
+            [Vector3::new(0.0, 2.0, 0.0), Vector3::new(5.0, -3.0, 0.0)]
+        );
+    }
+}
@@ -0,0 +1,394 @@
+use cgmath::{InnerSpace, Matrix3, Matrix4, Quaternion, SquareMatrix, Vector3, VectorSpace};
+
+use crate::ogf::Bone;
+
+use super::{BoneMotion, Omf, SAMPLE_FPS};
+
+/// The bones of a skeleton and their bind poses, in the order of the OGF,
+/// which is what skinned vertices refer to them by.
+#[derive(Debug, Clone)]
+pub struct Skeleton {
+    bones: Vec<SkeletonBone>,
+    /// Parents come before their children.
+    order: Vec<usize>,
+}
+
+#[derive(Debug, Clone)]
+struct SkeletonBone {
+    name: String,
+    parent: Option<usize>,
+    bind_rotation: Quaternion<f32>,
+    bind_position: Vector3<f32>,
+    /// From the model into the bone's bind pose.
+    inverse_bind: Matrix4<f32>,
+}
+
+impl Skeleton {
+    pub fn new(bones: &[Bone]) -> Skeleton {
+        let find = |name: &str| {
+            bones
+                .iter()
+                .position(|bone| bone.name.eq_ignore_ascii_case(name))
+        };
+
+        let mut skeleton = Skeleton {
+            bones: bones
+                .iter()
+                .map(|bone| SkeletonBone {
+                    name: bone.name.to_lowercase(),
+                    parent: bone.parent.as_deref().and_then(find),
+                    bind_rotation: Quaternion::from(xyz_inverted(bone.bind_rotation)),
+                    bind_position: bone.bind_position,
+                    inverse_bind: Matrix4::identity(),
+                })
+                .collect(),
+            order: Vec::with_capacity(bones.len()),
+        };
+
+        // Bones whose parent is missing are treated as roots
+        let mut stack: Vec<usize> = (0..bones.len())
+            .filter(|&bone| skeleton.bones[bone].parent.is_none())
+            .rev()
+            .collect();
+        while let Some(bone) = stack.pop() {
+            skeleton.order.push(bone);
+            stack.extend(
+                (0..bones.len())
+                    .rev()
+                    .filter(|&child| skeleton.bones[child].parent == Some(bone)),
+            );
+        }
+
+        let bind_pose = skeleton.bind_pose();
+        for (bone, world) in skeleton.world_matrices(&bind_pose).into_iter().enumerate() {
+            skeleton.bones[bone].inverse_bind = world.invert().unwrap_or(Matrix4::identity());
+        }
+
+        skeleton
+    }
+
+    pub fn len(&self) -> usize {
+        self.bones.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bones.is_empty()
+    }
+
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.bones
+            .iter()
+            .position(|bone| bone.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn parent(&self, bone: usize) -> Option<usize> {
+        self.bones[bone].parent
+    }
+
+    pub fn bind_pose(&self) -> Pose {
+        Pose {
+            rotations: self.bones.iter().map(|bone| bone.bind_rotation).collect(),
+            translations: self.bones.iter().map(|bone| bone.bind_position).collect(),
+        }
+    }
+
+    /// Transforms of every bone from its space into the model's.
+    pub fn world_matrices(&self, pose: &Pose) -> Vec<Matrix4<f32>> {
+        let mut matrices = vec![Matrix4::identity(); self.bones.len()];
+
+        for &bone in &self.order {
+            let local = Matrix4::from_translation(pose.translations[bone])
+                * Matrix4::from(pose.rotations[bone]);
+
+            matrices[bone] = match self.bones[bone].parent {
+                Some(parent) => matrices[parent] * local,
+                None => local,
+            };
+        }
+
+        matrices
+    }
+
+    /// Transforms of every bone from the bind pose of the model into the
+    /// pose, what skinned vertices are multiplied with.
+    pub fn skin_matrices(&self, pose: &Pose) -> Vec<Matrix4<f32>> {
+        self.world_matrices(pose)
+            .into_iter()
+            .zip(&self.bones)
+            .map(|(world, bone)| world * bone.inverse_bind)
+            .collect()
+    }
+}
+
+/// Local transforms of every bone, relative to its parent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pose {
+    pub rotations: Vec<Quaternion<f32>>,
+    pub translations: Vec<Vector3<f32>>,
+}
+
+/// A motion being played.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MotionState {
+    /// The index in [`Omf::motions`].
+    pub motion: usize,
+    /// In seconds from the start.
+    pub time: f32,
+    pub weight: f32,
+    /// The bone part it plays on, or `None` for all of them.
+    pub part: Option<usize>,
+    pub looped: bool,
+}
+
+/// Samples and blends the motions of an OMF on a skeleton.
+///
+/// XRay's quaternions are the conjugates of the ones used here, since its
+/// matrices transform row vectors. Keyframes keep XRay's convention and are
+/// converted when sampled.
+pub struct Sampler<'a> {
+    skeleton: &'a Skeleton,
+    omf: &'a Omf,
+    /// The skeleton bone and bone part of each bone of the motions.
+    motion_bones: Vec<Option<(usize, usize)>>,
+}
+
+impl<'a> Sampler<'a> {
+    pub fn new(skeleton: &'a Skeleton, omf: &'a Omf) -> Sampler<'a> {
+        let bone_count = omf.parts.iter().map(|part| part.bones.len()).sum();
+        let mut motion_bones = vec![None; bone_count];
+
+        for (part_index, part) in omf.parts.iter().enumerate() {
+            for bone in &part.bones {
+                let skeleton_bone = match &bone.name {
+                    Some(name) => skeleton.find(name),
+                    None => Some(bone.motion_bone as usize).filter(|&b| b < skeleton.len()),
+                };
+
+                let slot = motion_bones.get_mut(bone.motion_bone as usize);
+                if let (Some(skeleton_bone), Some(slot)) = (skeleton_bone, slot) {
+                    *slot = Some((skeleton_bone, part_index));
+                } else {
+                    log::warn!("Motion bone {:?} is not in the skeleton", bone.name);
+                }
+            }
+        }
+
+        Sampler {
+            skeleton,
+            omf,
+            motion_bones,
+        }
+    }
+
+    /// Blends motions by their weights, per bone part. Bones no motion plays
+    /// on stay in the bind pose.
+    pub fn sample(&self, states: &[MotionState]) -> Pose {
+        let mut pose = self.skeleton.bind_pose();
+        let mut weights = vec![0.0; self.skeleton.len()];
+
+        for state in states {
+            let Some(motion) = self.omf.motions.get(state.motion) else {
+                continue;
+            };
+            if state.weight <= 0.0 || motion.frame_count == 0 {
+                continue;
+            }
+
+            let (frame, next, delta) = frames(motion.frame_count, state.time, state.looped);
+
+            for (keys, target) in motion.bones.iter().zip(&self.motion_bones) {
+                let Some((bone, part)) = *target else {
+                    continue;
+                };
+                if state.part.is_some_and(|state_part| state_part != part) {
+                    continue;
+                }
+
+                let (rotation, translation) = sample_keys(keys, frame, next, delta);
+
+                let amount = state.weight / (weights[bone] + state.weight);
+                weights[bone] += state.weight;
+
+                pose.rotations[bone] = slerp(pose.rotations[bone], rotation, amount);
+                pose.translations[bone] = pose.translations[bone].lerp(translation, amount);
+            }
+        }
+
+        pose
+    }
+}
+
+/// The keyframes around a time and how far it is between them.
+fn frames(frame_count: u32, time: f32, looped: bool) -> (usize, usize, f32) {
+    let count = frame_count as f32;
+    let mut position = (time * SAMPLE_FPS).max(0.0);
+
+    if looped {
+        position %= count;
+    } else {
+        position = position.min(count - 1.0);
+    }
+
+    let frame = position.floor() as usize;
+    let next = if looped {
+        (frame + 1) % frame_count as usize
+    } else {
+        (frame + 1).min(frame_count as usize - 1)
+    };
+
+    (frame, next, position - frame as f32)
+}
+
+fn sample_keys(
+    keys: &BoneMotion,
+    frame: usize,
+    next: usize,
+    delta: f32,
+) -> (Quaternion<f32>, Vector3<f32>) {
+    let key = |count: usize, frame: usize| if count == 1 { 0 } else { frame.min(count - 1) };
+
+    let rotation = |frame| keys.rotations[key(keys.rotations.len(), frame)].conjugate();
+    let translation = |frame| keys.translations[key(keys.translations.len(), frame)];
+
+    (
+        slerp(rotation(frame), rotation(next), delta).normalize(),
+        translation(frame).lerp(translation(next), delta),
+    )
+}
+
+/// Spherical interpolation along the shorter way.
+fn slerp(from: Quaternion<f32>, to: Quaternion<f32>, amount: f32) -> Quaternion<f32> {
+    let to = if from.dot(to) < 0.0 { -to } else { to };
+    from.slerp(to, amount)
+}
+
+/// XRay's `setXYZi`, a rotation from negated Euler angles.
+fn xyz_inverted(angles: Vector3<f32>) -> Matrix3<f32> {
+    let (sh, ch) = (-angles.y).sin_cos();
+    let (sp, cp) = (-angles.x).sin_cos();
+    let (sb, cb) = (-angles.z).sin_cos();
+
+    let cc = ch * cb;
+    let cs = ch * sb;
+    let sc = sh * cb;
+    let ss = sh * sb;
+
+    // XRay's rows are the columns here
+    Matrix3::from_cols(
+        Vector3::new(cc - sp * ss, -cp * sb, sp * cs + sc),
+        Vector3::new(sp * sc + cs, cp * cb, ss - sp * cc),
+        Vector3::new(-cp * sh, sp, cp * ch),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use cgmath::{Deg, EuclideanSpace, Point3, Rotation3, Transform};
+
+    use crate::{
+        ogf::Obb,
+        omf::{BonePart, Motion, PartBone},
+    };
+
+    use super::*;
+
+    fn bone(name: &str, parent: Option<&str>, position: Vector3<f32>) -> Bone {
+        let zero = Vector3::new(0.0, 0.0, 0.0);
+
+        Bone {
+            name: name.to_owned(),
+            parent: parent.map(str::to_owned),
+            obb: Obb {
+                rotation: [zero; 3],
+                translation: zero,
+                half_size: zero,
+            },
+            material: String::new(),
+            bind_rotation: zero,
+            bind_position: position,
+            mass: 0.0,
+            center_of_mass: zero,
+        }
+    }
+
+    fn assert_near(actual: Vector3<f32>, expected: Vector3<f32>) {
+        assert!(
+            (actual - expected).magnitude() < 1e-3,
+            "{actual:?} != {expected:?}"
+        );
+    }
+
+    #[test]
+    fn test_sample() {
+        let up = Vector3::new(0.0, 1.0, 0.0);
+        let skeleton = Skeleton::new(&[
+            bone("bip01_spine", Some("bip01"), up),
+            bone("bip01", None, Vector3::new(0.0, 0.0, 0.0)),
+        ]);
+
+        // The root turns by 90 degrees around z, stored the way XRay does
+        let turn = Quaternion::from_angle_z(Deg(90.0)).conjugate();
+        let omf = Omf {
+            version: 4,
+            parts: vec![BonePart {
+                name: "default".to_owned(),
+                bones: vec![
+                    PartBone {
+                        name: Some("bip01".to_owned()),
+                        motion_bone: 0,
+                    },
+                    PartBone {
+                        name: Some("bip01_spine".to_owned()),
+                        motion_bone: 1,
+                    },
+                ],
+            }],
+            definitions: Vec::new(),
+            motions: vec![Motion {
+                name: "turn".to_owned(),
+                frame_count: 2,
+                bones: vec![
+                    BoneMotion {
+                        rotations: vec![Quaternion::new(1.0, 0.0, 0.0, 0.0), turn],
+                        translations: vec![Vector3::new(0.0, 0.0, 0.0)],
+                    },
+                    BoneMotion {
+                        rotations: vec![Quaternion::new(1.0, 0.0, 0.0, 0.0)],
+                        translations: vec![up],
+                    },
+                ],
+            }],
+        };
+
+        let sampler = Sampler::new(&skeleton, &omf);
+        let state = |time| MotionState {
+            motion: 0,
+            time,
+            weight: 1.0,
+            part: None,
+            looped: false,
+        };
+        let tip = Point3::new(0.0, 2.0, 0.0);
+        let skin = skeleton.skin_matrices(&skeleton.bind_pose())[0];
+        assert_near(skin.transform_point(tip).to_vec(), tip.to_vec());
+
+        let world = skeleton.world_matrices(&sampler.sample(&[state(0.0)]));
+        assert_near(world[0].w.truncate(), up);
+
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        let world = skeleton.world_matrices(&sampler.sample(&[state(0.5 / SAMPLE_FPS)]));
+        assert_near(world[0].w.truncate(), Vector3::new(-half, half, 0.0));
+
+        // Past the end without looping
+        let skin = skeleton.skin_matrices(&sampler.sample(&[state(1.0)]))[0];
+        assert_near(
+            skin.transform_point(tip).to_vec(),
+            Vector3::new(-2.0, 0.0, 0.0),
+        );
+
+        // Blending the first and last frames evenly
+        let pose = sampler.sample(&[state(0.0), state(1.0)]);
+        let world = skeleton.world_matrices(&pose);
+        assert_near(world[0].w.truncate(), Vector3::new(-half, half, 0.0));
+    }
+}
@@ -0,0 +1,118 @@
+//! The chunked binary container nearly every XRay format is stored in, like
+//! XRay's `IReader` and `IWriter`.
+//!
+//! A chunk is a `u32` id and a `u32` size followed by its data. Ids with
+//! [`CHUNK_COMPRESSED`] set hold LZH compressed data. Chunks can nest, and the
+//! data inside them is read with fixed-layout little endian reads.
+
+use thiserror::Error;
+
+pub use reader::{Chunk, Chunks, Reader};
+pub use writer::Writer;
+
+mod reader;
+mod writer;
+
+/// Set in the id of a chunk whose data is LZH compressed.
+pub const CHUNK_COMPRESSED: u32 = 1 << 31;
+
+#[derive(Debug, Error)]
+pub enum ReadError {
+    #[error(
+        "unexpected end of data at {position}, needed {needed} bytes but {remaining} are left"
+    )]
+    UnexpectedEof {
+        position: usize,
+        needed: usize,
+        remaining: usize,
+    },
+    #[error("chunk {id:#x} at {position} is {size} bytes, but only {remaining} are left")]
+    TruncatedChunk {
+        id: u32,
+        position: usize,
+        size: usize,
+        remaining: usize,
+    },
+    #[error("unterminated string at {position}")]
+    UnterminatedString { position: usize },
+    #[error("failed to decompress chunk {id:#x}: {error}")]
+    Decompress { id: u32, error: anyhow::Error },
+}
+
+#[cfg(test)]
+mod test {
+    use cgmath::Vector3;
+
+    use super::*;
+
+    #[test]
+    fn test_chunks() {
+        let mut writer = Writer::new();
+        writer.w_u32(7);
+        writer.open_chunk(1);
+        writer.open_chunk(5);
+        writer.w_stringz("ogf");
+        writer.w_fvector3(Vector3::new(1.0, -2.0, 0.5));
+        writer.close_chunk();
+        writer.open_chunk(6);
+        writer.w_u16(0xBEEF);
+        writer.close_chunk();
+        writer.close_chunk();
+        writer.w_chunk(2, &[1, 2, 3]);
+
+        let data = writer.into_inner();
+        assert_eq!(data.len(), 4 + 8 + 8 + 4 + 12 + 8 + 2 + 8 + 3);
+
+        let mut reader = Reader::new(&data);
+        assert_eq!(reader.r_u32().unwrap(), 7);
+
+        let reader = Reader::new(&data[4..]);
+        let ids = reader
+            .chunks()
+            .map(|chunk| chunk.map(|chunk| chunk.id))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(ids, [1, 2]);
+
+        let chunk = reader.open_chunk(1).unwrap().unwrap();
+        let mut nested = chunk.open_chunk(5).unwrap().unwrap();
+        assert_eq!(nested.r_stringz().unwrap(), "ogf");
+        assert_eq!(nested.r_fvector3().unwrap(), Vector3::new(1.0, -2.0, 0.5));
+        assert!(nested.eof());
+
+        let mut nested = chunk.open_chunk(6).unwrap().unwrap();
+        assert_eq!(nested.r_u16().unwrap(), 0xBEEF);
+        assert!(nested.eof());
+        assert_eq!(
+            nested.r_u8().unwrap_err().to_string(),
+            "unexpected end of data at 2, needed 1 bytes but 0 are left"
+        );
+
+        assert_eq!(reader.open_chunk(2).unwrap().unwrap().data(), [1, 2, 3]);
+        assert!(reader.open_chunk(3).unwrap().is_none());
+
+        let truncated = Reader::new(&data[4..data.len() - 1]);
+        assert_eq!(
+            truncated.open_chunk(2).unwrap_err().to_string(),
+            "chunk 0x2 at 42 is 3 bytes, but only 2 are left"
+        );
+    }
+
+    #[test]
+    fn test_string() {
+        let mut writer = Writer::new();
+        writer.w_bytes(b"marks\r\n");
+        writer.w_u32(10);
+        writer.w_bytes(b"\nfirst\n\rlast");
+
+        let data = writer.into_inner();
+        let mut reader = Reader::new(&data);
+        assert_eq!(reader.r_string().unwrap(), "marks");
+        assert_eq!(reader.r_u32().unwrap(), 10);
+        assert_eq!(reader.r_string().unwrap(), "");
+        assert_eq!(reader.r_string().unwrap(), "first");
+        assert_eq!(reader.r_string().unwrap(), "");
+        assert_eq!(reader.r_string().unwrap(), "last");
+        assert!(reader.eof());
+    }
+}
@@ -0,0 +1,298 @@
+use std::borrow::Cow;
+
+use cgmath::{Vector2, Vector3, Vector4};
+
+use crate::{
+    encoding::{self, Encoding, DEFAULT_CODEPAGE},
+    lzhuf,
+};
+
+use super::{ReadError, CHUNK_COMPRESSED};
+
+/// Reads little endian values from a slice, like a file loaded into memory or
+/// mapped, or from data it owns, like a decompressed chunk.
+///
+/// Strings are decoded with the reader's codepage, which chunks opened from it inherit.
+#[derive(Debug, Clone)]
+pub struct Reader<'a> {
+    data: Cow<'a, [u8]>,
+    position: usize,
+    codepage: &'static Encoding,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader {
+            data: Cow::Borrowed(data),
+            position: 0,
+            codepage: DEFAULT_CODEPAGE,
+        }
+    }
+
+    pub fn from_vec(data: Vec<u8>) -> Reader<'static> {
+        Reader {
+            data: Cow::Owned(data),
+            position: 0,
+            codepage: DEFAULT_CODEPAGE,
+        }
+    }
+
+    pub fn with_codepage(mut self, codepage: &'static Encoding) -> Reader<'a> {
+        self.codepage = codepage;
+        self
+    }
+
+    pub fn codepage(&self) -> &'static Encoding {
+        self.codepage
+    }
+
+    /// All of the data, whatever has been read.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The data that hasn't been read yet.
+    pub fn remaining_data(&self) -> &[u8] {
+        &self.data[self.position..]
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.position
+    }
+
+    pub fn eof(&self) -> bool {
+        self.position >= self.data.len()
+    }
+
+    pub fn seek(&mut self, position: usize) -> Result<(), ReadError> {
+        if position > self.data.len() {
+            return Err(ReadError::UnexpectedEof {
+                position: self.position,
+                needed: position - self.position,
+                remaining: self.remaining(),
+            });
+        }
+
+        self.position = position;
+        Ok(())
+    }
+
+    pub fn advance(&mut self, count: usize) -> Result<(), ReadError> {
+        self.r_bytes(count).map(|_| ())
+    }
+
+    pub fn r_bytes(&mut self, count: usize) -> Result<&[u8], ReadError> {
+        if count > self.remaining() {
+            return Err(ReadError::UnexpectedEof {
+                position: self.position,
+                needed: count,
+                remaining: self.remaining(),
+            });
+        }
+
+        let start = self.position;
+        self.position += count;
+
+        Ok(&self.data[start..self.position])
+    }
+
+    fn r_array<const N: usize>(&mut self) -> Result<[u8; N], ReadError> {
+        Ok(self.r_bytes(N)?.try_into().unwrap())
+    }
+
+    pub fn r_u8(&mut self) -> Result<u8, ReadError> {
+        Ok(u8::from_le_bytes(self.r_array()?))
+    }
+
+    pub fn r_u16(&mut self) -> Result<u16, ReadError> {
+        Ok(u16::from_le_bytes(self.r_array()?))
+    }
+
+    pub fn r_u32(&mut self) -> Result<u32, ReadError> {
+        Ok(u32::from_le_bytes(self.r_array()?))
+    }
+
+    pub fn r_u64(&mut self) -> Result<u64, ReadError> {
+        Ok(u64::from_le_bytes(self.r_array()?))
+    }
+
+    pub fn r_s8(&mut self) -> Result<i8, ReadError> {
+        Ok(i8::from_le_bytes(self.r_array()?))
+    }
+
+    pub fn r_s16(&mut self) -> Result<i16, ReadError> {
+        Ok(i16::from_le_bytes(self.r_array()?))
+    }
+
+    pub fn r_s32(&mut self) -> Result<i32, ReadError> {
+        Ok(i32::from_le_bytes(self.r_array()?))
+    }
+
+    pub fn r_s64(&mut self) -> Result<i64, ReadError> {
+        Ok(i64::from_le_bytes(self.r_array()?))
+    }
+
+    pub fn r_float(&mut self) -> Result<f32, ReadError> {
+        Ok(f32::from_le_bytes(self.r_array()?))
+    }
+
+    pub fn r_fvector2(&mut self) -> Result<Vector2<f32>, ReadError> {
+        Ok(Vector2::new(self.r_float()?, self.r_float()?))
+    }
+
+    pub fn r_fvector3(&mut self) -> Result<Vector3<f32>, ReadError> {
+        Ok(Vector3::new(
+            self.r_float()?,
+            self.r_float()?,
+            self.r_float()?,
+        ))
+    }
+
+    pub fn r_fvector4(&mut self) -> Result<Vector4<f32>, ReadError> {
+        Ok(Vector4::new(
+            self.r_float()?,
+            self.r_float()?,
+            self.r_float()?,
+            self.r_float()?,
+        ))
+    }
+
+    /// A zero terminated string.
+    pub fn r_stringz(&mut self) -> Result<Cow<'_, str>, ReadError> {
+        let length = self.remaining_data().iter().position(|&b| b == 0).ok_or(
+            ReadError::UnterminatedString {
+                position: self.position,
+            },
+        )?;
+
+        let codepage = self.codepage;
+        let bytes = self.r_bytes(length + 1)?;
+
+        Ok(encoding::decode(&bytes[..length], codepage))
+    }
+
+    /// A string ended by a line break or the end of the data, like XRay's
+    /// `r_string`. The line break, `\r\n` or a lone `\r` or `\n`, is skipped.
+    pub fn r_string(&mut self) -> Result<Cow<'_, str>, ReadError> {
+        let data = self.remaining_data();
+        let length = data
+            .iter()
+            .position(|&b| b == b'\r' || b == b'\n')
+            .unwrap_or(data.len());
+        let breaks = match &data[length..] {
+            [b'\r', b'\n', ..] => 2,
+            [] => 0,
+            _ => 1,
+        };
+
+        let codepage = self.codepage;
+        let bytes = self.r_bytes(length + breaks)?;
+
+        Ok(encoding::decode(&bytes[..length], codepage))
+    }
+
+    /// Iterates over the chunks of the whole data, from the start.
+    pub fn chunks(&self) -> Chunks<'_> {
+        Chunks {
+            data: &self.data,
+            position: 0,
+            codepage: self.codepage,
+        }
+    }
+
+    /// Finds the first chunk with an id, decompressing it if needed.
+    pub fn open_chunk(&self, id: u32) -> Result<Option<Reader<'_>>, ReadError> {
+        for chunk in self.chunks() {
+            let chunk = chunk?;
+
+            if chunk.id == id {
+                return Ok(Some(chunk.reader));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// A chunk, with the compressed flag taken out of its id.
+#[derive(Debug, Clone)]
+pub struct Chunk<'a> {
+    pub id: u32,
+    pub compressed: bool,
+    pub reader: Reader<'a>,
+}
+
+pub struct Chunks<'a> {
+    data: &'a [u8],
+    position: usize,
+    codepage: &'static Encoding,
+}
+
+impl<'a> Chunks<'a> {
+    fn read_chunk(&mut self) -> Result<Chunk<'a>, ReadError> {
+        let mut header = Reader::new(self.data);
+        header.seek(self.position)?;
+        let raw_id = header.r_u32()?;
+        let size = header.r_u32()? as usize;
+
+        let id = raw_id & !CHUNK_COMPRESSED;
+        let start = self.position + 8;
+
+        if size > self.data.len() - start {
+            return Err(ReadError::TruncatedChunk {
+                id,
+                position: self.position,
+                size,
+                remaining: self.data.len() - start,
+            });
+        }
+
+        let data = &self.data[start..start + size];
+        self.position = start + size;
+
+        let compressed = raw_id & CHUNK_COMPRESSED != 0;
+        let reader = if compressed {
+            let data =
+                lzhuf::decompress(data).map_err(|error| ReadError::Decompress { id, error })?;
+            Reader::from_vec(data)
+        } else {
+            Reader::new(data)
+        };
+
+        Ok(Chunk {
+            id,
+            compressed,
+            reader: reader.with_codepage(self.codepage),
+        })
+    }
+}
+
+impl<'a> Iterator for Chunks<'a> {
+    type Item = Result<Chunk<'a>, ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.position >= self.data.len() {
+            return None;
+        }
+
+        let chunk = self.read_chunk();
+        if chunk.is_err() {
+            // Nothing after a broken chunk can be found
+            self.position = self.data.len();
+        }
+
+        Some(chunk)
+    }
+}
@@ -0,0 +1,162 @@
+use cgmath::{Vector2, Vector3, Vector4};
+
+use crate::encoding::{self, Encoding, DEFAULT_CODEPAGE};
+
+/// Writes little endian values and chunks to memory, like XRay's `CMemoryWriter`.
+///
+/// Chunks are opened and closed around their contents and can nest. Their
+/// sizes are filled in when they are closed.
+#[derive(Debug, Clone)]
+pub struct Writer {
+    data: Vec<u8>,
+    open_chunks: Vec<usize>,
+    codepage: &'static Encoding,
+}
+
+impl Default for Writer {
+    fn default() -> Self {
+        Writer::new()
+    }
+}
+
+impl Writer {
+    pub fn new() -> Writer {
+        Writer {
+            data: Vec::new(),
+            open_chunks: Vec::new(),
+            codepage: DEFAULT_CODEPAGE,
+        }
+    }
+
+    pub fn with_codepage(mut self, codepage: &'static Encoding) -> Writer {
+        self.codepage = codepage;
+        self
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The written data.
+    ///
+    /// # Panics
+    ///
+    /// If a chunk is still open.
+    pub fn into_inner(self) -> Vec<u8> {
+        assert!(
+            self.open_chunks.is_empty(),
+            "{} chunks are still open",
+            self.open_chunks.len()
+        );
+
+        self.data
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn w_bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    pub fn w_u8(&mut self, value: u8) {
+        self.w_bytes(&value.to_le_bytes());
+    }
+
+    pub fn w_u16(&mut self, value: u16) {
+        self.w_bytes(&value.to_le_bytes());
+    }
+
+    pub fn w_u32(&mut self, value: u32) {
+        self.w_bytes(&value.to_le_bytes());
+    }
+
+    pub fn w_u64(&mut self, value: u64) {
+        self.w_bytes(&value.to_le_bytes());
+    }
+
+    pub fn w_s8(&mut self, value: i8) {
+        self.w_bytes(&value.to_le_bytes());
+    }
+
+    pub fn w_s16(&mut self, value: i16) {
+        self.w_bytes(&value.to_le_bytes());
+    }
+
+    pub fn w_s32(&mut self, value: i32) {
+        self.w_bytes(&value.to_le_bytes());
+    }
+
+    pub fn w_s64(&mut self, value: i64) {
+        self.w_bytes(&value.to_le_bytes());
+    }
+
+    pub fn w_float(&mut self, value: f32) {
+        self.w_bytes(&value.to_le_bytes());
+    }
+
+    pub fn w_fvector2(&mut self, value: Vector2<f32>) {
+        self.w_float(value.x);
+        self.w_float(value.y);
+    }
+
+    pub fn w_fvector3(&mut self, value: Vector3<f32>) {
+        self.w_float(value.x);
+        self.w_float(value.y);
+        self.w_float(value.z);
+    }
+
+    pub fn w_fvector4(&mut self, value: Vector4<f32>) {
+        self.w_float(value.x);
+        self.w_float(value.y);
+        self.w_float(value.z);
+        self.w_float(value.w);
+    }
+
+    /// A zero terminated string, encoded with the writer's codepage.
+    pub fn w_stringz(&mut self, text: &str) {
+        let bytes = encoding::encode(text, self.codepage);
+
+        self.w_bytes(&bytes);
+        self.w_u8(0);
+    }
+
+    /// A string ended by a line break, encoded with the writer's codepage.
+    pub fn w_string(&mut self, text: &str) {
+        let bytes = encoding::encode(text, self.codepage);
+
+        self.w_bytes(&bytes);
+        self.w_bytes(b"\r\n");
+    }
+
+    /// Starts a chunk, which everything written until [`Writer::close_chunk`] goes into.
+    pub fn open_chunk(&mut self, id: u32) {
+        self.w_u32(id);
+        self.open_chunks.push(self.data.len());
+        self.w_u32(0);
+    }
+
+    /// Ends the innermost open chunk.
+    ///
+    /// # Panics
+    ///
+    /// If no chunk is open.
+    pub fn close_chunk(&mut self) {
+        let size_position = self.open_chunks.pop().expect("no chunk is open");
+        let size = (self.data.len() - size_position - 4) as u32;
+
+        self.data[size_position..size_position + 4].copy_from_slice(&size.to_le_bytes());
+    }
+
+    /// Writes a whole chunk.
+    pub fn w_chunk(&mut self, id: u32, data: &[u8]) {
+        self.w_u32(id);
+        self.w_u32(data.len() as u32);
+        self.w_bytes(data);
+    }
+}
@@ -0,0 +1,161 @@
+use std::{
+    collections::HashMap,
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+};
+
+use crate::ltx::wildcard_match;
+
+use super::{Xml, XmlSource};
+
+/// Edits a document after it's parsed, like an Anomaly DXML `on_xml_read` callback.
+pub type XmlPatcher = dyn Fn(&mut Xml) -> anyhow::Result<()> + Send + Sync;
+
+struct Patch {
+    pattern: String,
+    patcher: Box<XmlPatcher>,
+}
+
+/// Loads XML files with the registered patchers applied, and caches the results.
+///
+/// A patcher is matched by a pattern on the end of the file path, with `*`
+/// and `?` wildcards and either kind of slash, like `ui\ui_mm_main.xml` or
+/// `gameplay/character_desc_*.xml`. Wildcards stay within a folder name, a
+/// `**` folder matches any number of folders. Patchers run in the order they
+/// were registered.
+pub struct XmlLoader<S: XmlSource + ?Sized> {
+    source: Arc<S>,
+    patches: RwLock<Vec<Patch>>,
+    cache: Mutex<HashMap<PathBuf, Arc<Xml>>>,
+    dump_dir: RwLock<Option<PathBuf>>,
+}
+
+impl<S: XmlSource + ?Sized> XmlLoader<S> {
+    pub fn new(source: Arc<S>) -> XmlLoader<S> {
+        XmlLoader {
+            source,
+            patches: RwLock::new(Vec::new()),
+            cache: Mutex::new(HashMap::new()),
+            dump_dir: RwLock::new(None),
+        }
+    }
+
+    /// Registers a patcher for the files matching `pattern`. Cached files it
+    /// matches are loaded again on their next use.
+    pub fn register<F>(&self, pattern: &str, patcher: F)
+    where
+        F: Fn(&mut Xml) -> anyhow::Result<()> + Send + Sync + 'static,
+    {
+        let pattern = normalize(pattern);
+
+        self.cache
+            .lock()
+            .unwrap()
+            .retain(|path, _| !matches(&pattern, path));
+
+        self.patches.write().unwrap().push(Patch {
+            pattern,
+            patcher: Box::new(patcher),
+        });
+    }
+
+    /// Writes every patched file under `dir` as it's loaded, to see what the
+    /// patchers made of it. `None` turns it off.
+    pub fn set_dump_dir(&self, dir: Option<PathBuf>) {
+        *self.dump_dir.write().unwrap() = dir;
+    }
+
+    /// Loads a file, or returns it from the cache.
+    pub fn load<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<Arc<Xml>> {
+        let path = path.as_ref();
+
+        if let Some(xml) = self.cache.lock().unwrap().get(path) {
+            return Ok(xml.clone());
+        }
+
+        let mut xml = Xml::load(self.source.as_ref(), path)?;
+        let mut patched = false;
+
+        for patch in self.patches.read().unwrap().iter() {
+            if !matches(&patch.pattern, path) {
+                continue;
+            }
+
+            log::debug!("Patching {} for {}", path.display(), patch.pattern);
+            patched = true;
+
+            if let Err(e) = (patch.patcher)(&mut xml) {
+                log::error!("Failed to patch {}: {e}", path.display());
+            }
+        }
+
+        if patched {
+            self.dump(&xml);
+        }
+
+        let xml = Arc::new(xml);
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(path.to_path_buf(), xml.clone());
+
+        Ok(xml)
+    }
+
+    /// Forgets every loaded file, after the files on disk changed for example.
+    pub fn clear_cache(&self) {
+        self.cache.lock().unwrap().clear();
+    }
+
+    fn dump(&self, xml: &Xml) {
+        let Some(dir) = self.dump_dir.read().unwrap().clone() else {
+            return;
+        };
+
+        // The whole path is kept so files with the same name don't overwrite each other
+        let mut path = dir;
+        path.extend(
+            xml.path()
+                .components()
+                .filter_map(|component| match component {
+                    Component::Normal(name) => Some(name),
+                    _ => None,
+                }),
+        );
+
+        let result = path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| std::fs::write(&path, xml.to_string()));
+
+        match result {
+            Ok(()) => log::info!("Dumped patched XML to {}", path.display()),
+            Err(e) => log::warn!("Failed to dump patched XML to {}: {e}", path.display()),
+        }
+    }
+}
+
+fn normalize(path: &str) -> String {
+    path.replace('\\', "/")
+}
+
+fn matches(pattern: &str, path: &Path) -> bool {
+    let path = normalize(&path.to_string_lossy());
+    let path = path.split('/').collect::<Vec<_>>();
+    let pattern = pattern.split('/').collect::<Vec<_>>();
+
+    (0..path.len()).any(|start| matches_components(&pattern, &path[start..]))
+}
+
+/// Matches a whole path, folder by folder.
+fn matches_components(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", pattern)) => {
+            (0..=path.len()).any(|skip| matches_components(pattern, &path[skip..]))
+        }
+        Some((component, pattern)) => path.split_first().is_some_and(|(name, path)| {
+            wildcard_match(component, name) && matches_components(pattern, path)
+        }),
+    }
+}
@@ -0,0 +1,650 @@
+use std::{
+    fmt::{Display, Formatter},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use thiserror::Error;
+
+use crate::{filesystem::Filesystem, ltx::Location};
+
+use parser::{encode_entities, preprocess, Preprocessed, XmlParser};
+
+pub use dxml::{XmlLoader, XmlPatcher};
+
+mod dxml;
+mod parser;
+
+/// Where XML files and the files they include are read from.
+pub trait XmlSource {
+    fn read_xml(&self, path: &Path) -> anyhow::Result<String>;
+}
+
+impl XmlSource for Filesystem {
+    fn read_xml(&self, path: &Path) -> anyhow::Result<String> {
+        self.read_to_string(path)
+    }
+}
+
+/// A single in-memory file, for XML that doesn't come from the filesystem.
+struct TextSource<'a> {
+    path: &'a Path,
+    text: &'a str,
+}
+
+impl XmlSource for TextSource<'_> {
+    fn read_xml(&self, path: &Path) -> anyhow::Result<String> {
+        if path == self.path {
+            Ok(self.text.to_owned())
+        } else {
+            anyhow::bail!("{} not found", path.display())
+        }
+    }
+}
+
+/// An element, with its attributes, text and child elements.
+#[derive(Debug, Clone)]
+pub struct XmlNode {
+    name: String,
+    attributes: Vec<(String, String)>,
+    text: String,
+    children: Vec<XmlNode>,
+    location: Location,
+}
+
+impl XmlNode {
+    /// An empty element, to insert into a document.
+    pub fn new(name: &str) -> XmlNode {
+        XmlNode::with_location(
+            name,
+            Location {
+                file: PathBuf::new(),
+                line: 0,
+            },
+        )
+    }
+
+    /// Parses markup into the elements to insert into a document, like DXML's
+    /// `insertFromXMLString`.
+    pub fn parse_fragment(text: &str) -> anyhow::Result<Vec<XmlNode>> {
+        Ok(Xml::parse("<fragment>", text)?.root.children)
+    }
+
+    fn with_location(name: &str, location: Location) -> XmlNode {
+        XmlNode {
+            name: name.to_owned(),
+            attributes: Vec::new(),
+            text: String::new(),
+            children: Vec::new(),
+            location,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The text inside the element, trimmed.
+    pub fn text(&self) -> &str {
+        self.text.trim()
+    }
+
+    /// The file and line the element starts at.
+    pub fn location(&self) -> &Location {
+        &self.location
+    }
+
+    pub fn set_text(&mut self, text: &str) {
+        self.text = text.to_owned();
+    }
+
+    pub fn attributes(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.attributes
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(attribute, _)| attribute == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn set_attribute(&mut self, name: &str, value: &str) {
+        match self
+            .attributes
+            .iter_mut()
+            .find(|(attribute, _)| attribute == name)
+        {
+            Some((_, old)) => *old = value.to_owned(),
+            None => self.attributes.push((name.to_owned(), value.to_owned())),
+        }
+    }
+
+    pub fn remove_attribute(&mut self, name: &str) -> Option<String> {
+        let idx = self
+            .attributes
+            .iter()
+            .position(|(attribute, _)| attribute == name)?;
+
+        Some(self.attributes.remove(idx).1)
+    }
+
+    pub fn children(&self) -> &[XmlNode] {
+        &self.children
+    }
+
+    /// The child elements, to insert, remove or reorder them.
+    pub fn children_mut(&mut self) -> &mut Vec<XmlNode> {
+        &mut self.children
+    }
+
+    pub fn children_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a XmlNode> {
+        self.children.iter().filter(move |child| child.name == name)
+    }
+
+    /// Finds a descendant by a path of element names separated by `:`, like
+    /// XRay's `NavigateToNode`.
+    ///
+    /// `index` picks among the elements matching the first name, deeper names
+    /// take their first match. An empty path is the node itself.
+    pub fn navigate(&self, path: &str, index: usize) -> Option<&XmlNode> {
+        let mut names = path.split(':').filter(|name| !name.is_empty());
+
+        let Some(first) = names.next() else {
+            return Some(self);
+        };
+
+        let mut node = self
+            .children
+            .iter()
+            .filter(|child| child.name == first)
+            .nth(index)?;
+
+        for name in names {
+            node = node.children.iter().find(|child| child.name == name)?;
+        }
+
+        Some(node)
+    }
+
+    /// See [`XmlNode::navigate`].
+    pub fn navigate_mut(&mut self, path: &str, index: usize) -> Option<&mut XmlNode> {
+        let mut names = path.split(':').filter(|name| !name.is_empty());
+
+        let Some(first) = names.next() else {
+            return Some(self);
+        };
+
+        let mut node = self
+            .children
+            .iter_mut()
+            .filter(|child| child.name == first)
+            .nth(index)?;
+
+        for name in names {
+            node = node.children.iter_mut().find(|child| child.name == name)?;
+        }
+
+        Some(node)
+    }
+
+    /// Finds the first descendant called `tag` with `attribute` set to `value`,
+    /// like XRay's `SearchForAttribute`.
+    pub fn search_for_attribute(
+        &self,
+        tag: &str,
+        attribute: &str,
+        value: &str,
+    ) -> Option<&XmlNode> {
+        self.children.iter().find_map(|child| {
+            if child.name == tag && child.attribute(attribute) == Some(value) {
+                Some(child)
+            } else {
+                child.search_for_attribute(tag, attribute, value)
+            }
+        })
+    }
+
+    /// See [`XmlNode::search_for_attribute`].
+    pub fn search_for_attribute_mut(
+        &mut self,
+        tag: &str,
+        attribute: &str,
+        value: &str,
+    ) -> Option<&mut XmlNode> {
+        for child in &mut self.children {
+            if child.name == tag && child.attribute(attribute) == Some(value) {
+                return Some(child);
+            }
+
+            if let Some(found) = child.search_for_attribute_mut(tag, attribute, value) {
+                return Some(found);
+            }
+        }
+
+        None
+    }
+
+    pub fn r_attrib(&self, attribute: &str) -> Result<&str, XmlError> {
+        self.attribute(attribute)
+            .ok_or_else(|| XmlError::MissingAttribute {
+                node: self.name.clone(),
+                attribute: attribute.to_owned(),
+                location: self.location.clone(),
+            })
+    }
+
+    /// Reads an attribute and parses it, as a number for example.
+    pub fn r_attrib_parse<T: FromStr>(&self, attribute: &str) -> Result<T, XmlError> {
+        let value = self.r_attrib(attribute)?;
+
+        value
+            .trim()
+            .parse()
+            .map_err(|_| XmlError::InvalidAttribute {
+                node: self.name.clone(),
+                attribute: attribute.to_owned(),
+                value: value.to_owned(),
+                location: self.location.clone(),
+            })
+    }
+}
+
+impl XmlNode {
+    fn write(&self, f: &mut Formatter<'_>, depth: usize) -> std::fmt::Result {
+        // The document node only holds the top level elements
+        if self.name.is_empty() {
+            return self
+                .children
+                .iter()
+                .try_for_each(|child| child.write(f, depth));
+        }
+
+        let indent = "\t".repeat(depth);
+
+        write!(f, "{indent}<{}", self.name)?;
+
+        for (name, value) in &self.attributes {
+            write!(f, " {name}=\"{}\"", encode_entities(value))?;
+        }
+
+        if self.text().is_empty() && self.children.is_empty() {
+            return writeln!(f, "/>");
+        }
+
+        write!(f, ">{}", encode_entities(self.text()))?;
+
+        if !self.children.is_empty() {
+            writeln!(f)?;
+
+            for child in &self.children {
+                child.write(f, depth + 1)?;
+            }
+
+            write!(f, "{indent}")?;
+        }
+
+        writeln!(f, "</{}>", self.name)
+    }
+}
+
+/// Writes the element back out as markup, with its text before its children.
+impl Display for XmlNode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.write(f, 0)
+    }
+}
+
+/// A parsed XRay XML file, with its `#include`s expanded.
+///
+/// Text is decoded with the filesystem's codepage, whatever the XML declaration says.
+#[derive(Debug, Clone)]
+pub struct Xml {
+    path: PathBuf,
+    root: XmlNode,
+    diagnostics: Vec<XmlDiagnostic>,
+}
+
+impl Xml {
+    /// Loads an XML file. Fails if it or an include can't be read, malformed
+    /// markup is only logged.
+    pub fn load<S: XmlSource + ?Sized, P: AsRef<Path>>(source: &S, path: P) -> anyhow::Result<Xml> {
+        let path = path.as_ref();
+        log::debug!("Loading XML {}", path.display());
+
+        let root = path.parent().unwrap_or(Path::new(""));
+
+        let mut preprocessed = Preprocessed::default();
+        preprocess(source, root, path, &mut preprocessed, &mut Vec::new())?;
+
+        let (root, diagnostics) = XmlParser::new(&preprocessed, path).parse();
+
+        for diagnostic in &diagnostics {
+            log::warn!("{diagnostic}");
+        }
+
+        Ok(Xml {
+            path: path.to_path_buf(),
+            root,
+            diagnostics,
+        })
+    }
+
+    /// Parses XML text that isn't read from a file. `path` is only used in errors.
+    pub fn parse<P: AsRef<Path>>(path: P, text: &str) -> anyhow::Result<Xml> {
+        let path = path.as_ref();
+
+        Xml::load(&TextSource { path, text }, path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The document node, which holds the top level elements.
+    pub fn root(&self) -> &XmlNode {
+        &self.root
+    }
+
+    pub fn root_mut(&mut self) -> &mut XmlNode {
+        &mut self.root
+    }
+
+    pub fn diagnostics(&self) -> &[XmlDiagnostic] {
+        &self.diagnostics
+    }
+
+    /// See [`XmlNode::navigate`].
+    pub fn navigate_to_node(&self, path: &str, index: usize) -> Option<&XmlNode> {
+        self.root.navigate(path, index)
+    }
+
+    /// See [`XmlNode::navigate`].
+    pub fn navigate_to_node_mut(&mut self, path: &str, index: usize) -> Option<&mut XmlNode> {
+        self.root.navigate_mut(path, index)
+    }
+
+    pub fn r_node(&self, path: &str, index: usize) -> Result<&XmlNode, XmlError> {
+        self.navigate_to_node(path, index)
+            .ok_or_else(|| XmlError::MissingNode {
+                path: path.to_owned(),
+                index,
+                file: self.path.clone(),
+            })
+    }
+
+    /// The text of a node, or `default` if there is no such node.
+    pub fn read<'a>(&'a self, path: &str, index: usize, default: &'a str) -> &'a str {
+        self.navigate_to_node(path, index)
+            .map_or(default, XmlNode::text)
+    }
+
+    pub fn read_int(&self, path: &str, index: usize, default: i32) -> i32 {
+        self.read(path, index, "").parse().unwrap_or(default)
+    }
+
+    pub fn read_flt(&self, path: &str, index: usize, default: f32) -> f32 {
+        self.read(path, index, "").parse().unwrap_or(default)
+    }
+
+    /// An attribute of a node, or `default` if there is no such node or attribute.
+    pub fn read_attrib<'a>(
+        &'a self,
+        path: &str,
+        index: usize,
+        attribute: &str,
+        default: &'a str,
+    ) -> &'a str {
+        self.navigate_to_node(path, index)
+            .and_then(|node| node.attribute(attribute))
+            .unwrap_or(default)
+    }
+
+    pub fn read_attrib_int(&self, path: &str, index: usize, attribute: &str, default: i32) -> i32 {
+        self.read_attrib(path, index, attribute, "")
+            .trim()
+            .parse()
+            .unwrap_or(default)
+    }
+
+    pub fn read_attrib_flt(&self, path: &str, index: usize, attribute: &str, default: f32) -> f32 {
+        self.read_attrib(path, index, attribute, "")
+            .trim()
+            .parse()
+            .unwrap_or(default)
+    }
+
+    /// Counts the `tag` elements directly inside a node, like XRay's `GetNodesNum`.
+    pub fn get_nodes_num(&self, path: &str, index: usize, tag: &str) -> usize {
+        self.navigate_to_node(path, index)
+            .map_or(0, |node| node.children_named(tag).count())
+    }
+
+    /// See [`XmlNode::search_for_attribute`].
+    pub fn search_for_attribute(
+        &self,
+        tag: &str,
+        attribute: &str,
+        value: &str,
+    ) -> Option<&XmlNode> {
+        self.root.search_for_attribute(tag, attribute, value)
+    }
+}
+
+impl Display for Xml {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.root.fmt(f)
+    }
+}
+
+#[derive(Debug, Clone, Error)]
+#[error("{location}: {kind}")]
+pub struct XmlDiagnostic {
+    pub location: Location,
+    pub kind: XmlDiagnosticKind,
+}
+
+#[derive(Debug, Clone, Error)]
+pub enum XmlDiagnosticKind {
+    #[error("malformed #include")]
+    InvalidInclude,
+    #[error("unresolved #include \"{include}\"")]
+    UnresolvedInclude { include: String },
+    #[error("recursive #include of {}", .path.display())]
+    RecursiveInclude { path: PathBuf },
+    #[error("unterminated comment or declaration")]
+    UnterminatedMarkup,
+    #[error("unterminated tag <{name}>")]
+    UnterminatedTag { name: String },
+    #[error("element <{name}> is never closed")]
+    UnclosedElement { name: String },
+    #[error("end tag </{name}> without a start tag")]
+    UnexpectedEndTag { name: String },
+}
+
+#[derive(Debug, Error)]
+pub enum XmlError {
+    #[error("node \"{path}\" #{index} not found in {}", .file.display())]
+    MissingNode {
+        path: String,
+        index: usize,
+        file: PathBuf,
+    },
+    #[error("{location}: <{node}> has no attribute \"{attribute}\"")]
+    MissingAttribute {
+        node: String,
+        attribute: String,
+        location: Location,
+    },
+    #[error("{location}: <{node}> attribute {attribute}=\"{value}\" is invalid")]
+    InvalidAttribute {
+        node: String,
+        attribute: String,
+        value: String,
+        location: Location,
+    },
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, sync::Arc};
+
+    use super::*;
+
+    struct MemorySource(HashMap<PathBuf, &'static str>);
+
+    impl XmlSource for MemorySource {
+        fn read_xml(&self, path: &Path) -> anyhow::Result<String> {
+            self.0
+                .get(path)
+                .map(|text| text.to_string())
+                .ok_or_else(|| anyhow::anyhow!("{} not found", path.display()))
+        }
+    }
+
+    #[test]
+    fn test_load() {
+        let source = MemorySource(HashMap::from([
+            (
+                PathBuf::from("ui/main.xml"),
+                "<?xml version=\"1.0\" encoding=\"windows-1251\"?>\n\
+                 <w>\n\
+                 <!-- comment <with> markup -->\n\
+                 #include \"parts\\buttons.xml\"\n\
+                 <caption x=\"10\" y=20 visible>Tom & Jerry &lt;3 &#x41;</caption>\n\
+                 <list><item/><item id='b'>\n\
+                 </list>\n\
+                 </stray>\n\
+                 </w>\n",
+            ),
+            (
+                PathBuf::from("ui/parts/buttons.xml"),
+                "<button id=\"ok\" width=\"1.5\">ok</button>\n\
+                 <button id=\"cancel\" width=\"wide\">cancel</button>\n",
+            ),
+        ]));
+
+        let xml = Xml::load(&source, "ui/main.xml").unwrap();
+
+        assert_eq!(xml.get_nodes_num("w", 0, "button"), 2);
+        assert_eq!(xml.read("w:button", 0, ""), "ok");
+        assert_eq!(xml.navigate_to_node("w", 0).unwrap().children().len(), 4);
+
+        let caption = xml.r_node("w:caption", 0).unwrap();
+        assert_eq!(caption.text(), "Tom & Jerry <3 A");
+        assert_eq!(caption.location().line, 5);
+        assert_eq!(xml.read_attrib_int("w:caption", 0, "x", 0), 10);
+        assert_eq!(xml.read_attrib_int("w:caption", 0, "y", 0), 20);
+        assert_eq!(xml.read_attrib("w:caption", 0, "visible", "no"), "");
+
+        assert_eq!(xml.read("w:list:item", 0, "none"), "");
+        assert_eq!(xml.get_nodes_num("w:list", 0, "item"), 2);
+
+        let cancel = xml.search_for_attribute("button", "id", "cancel").unwrap();
+        assert_eq!(cancel.text(), "cancel");
+        assert_eq!(cancel.location().file, Path::new("ui/parts/buttons.xml"));
+        assert_eq!(cancel.location().line, 2);
+        assert_eq!(
+            cancel
+                .r_attrib_parse::<f32>("width")
+                .unwrap_err()
+                .to_string(),
+            "ui/parts/buttons.xml:2: <button> attribute width=\"wide\" is invalid"
+        );
+        assert_eq!(
+            xml.r_node("w:missing", 0).unwrap_err().to_string(),
+            "node \"w:missing\" #0 not found in ui/main.xml"
+        );
+
+        let diagnostics = xml
+            .diagnostics()
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            diagnostics,
+            [
+                "ui/main.xml:6: element <item> is never closed",
+                "ui/main.xml:8: end tag </stray> without a start tag",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dxml() {
+        let source = Arc::new(MemorySource(HashMap::from([
+            (
+                PathBuf::from("ui/ui_mm_main.xml"),
+                "<w>\n<btn id=\"play\" x=\"1\">Play</btn>\n<btn id=\"quit\"/>\n</w>\n",
+            ),
+            (PathBuf::from("ui/other.xml"), "<w/>\n"),
+            (PathBuf::from("ui/scripts/sub/nested.xml"), "<w/>\n"),
+        ])));
+
+        let loader = XmlLoader::new(source);
+        let before = loader.load("ui/ui_mm_main.xml").unwrap();
+
+        loader.register("UI\\ui_mm_*.xml", |xml| {
+            let w = xml.navigate_to_node_mut("w", 0).unwrap();
+            w.children_mut()
+                .retain(|btn| btn.attribute("id") != Some("quit"));
+            w.children_mut().extend(XmlNode::parse_fragment(
+                "<btn id=\"mods\">Mods & \"more\"</btn>",
+            )?);
+
+            let play = w.search_for_attribute_mut("btn", "id", "play").unwrap();
+            play.set_text("New game");
+            play.remove_attribute("x");
+
+            Ok(())
+        });
+
+        let xml = loader.load("ui/ui_mm_main.xml").unwrap();
+        assert!(!Arc::ptr_eq(&before, &xml));
+        assert!(Arc::ptr_eq(
+            &xml,
+            &loader.load("ui/ui_mm_main.xml").unwrap()
+        ));
+        assert_eq!(
+            xml.to_string(),
+            "<w>\n\t<btn id=\"play\">New game</btn>\n\t<btn id=\"mods\">Mods &amp; &quot;more&quot;</btn>\n</w>\n"
+        );
+        assert_eq!(loader.load("ui/other.xml").unwrap().to_string(), "<w/>\n");
+
+        let patcher = |xml: &mut Xml| {
+            xml.navigate_to_node_mut("w", 0)
+                .unwrap()
+                .set_text("patched");
+            Ok(())
+        };
+
+        // `*` doesn't reach into subfolders, `**` does
+        loader.register("ui/*.xml", patcher);
+        assert_ne!(loader.load("ui/other.xml").unwrap().to_string(), "<w/>\n");
+        assert_eq!(
+            loader
+                .load("ui/scripts/sub/nested.xml")
+                .unwrap()
+                .to_string(),
+            "<w/>\n"
+        );
+
+        loader.register("ui/**/nested.xml", patcher);
+        assert_ne!(
+            loader
+                .load("ui/scripts/sub/nested.xml")
+                .unwrap()
+                .to_string(),
+            "<w/>\n"
+        );
+    }
+
+    #[test]
+    fn test_missing_include() {
+        let error = Xml::parse("a.xml", "<a>\n#include \"b.xml\"\n</a>").unwrap_err();
+
+        assert_eq!(error.to_string(), "a.xml:2: unresolved #include \"b.xml\"");
+    }
+}
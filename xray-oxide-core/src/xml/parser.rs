@@ -0,0 +1,434 @@
+use std::{
+    borrow::Cow,
+    path::{Path, PathBuf},
+};
+
+use crate::ltx::Location;
+
+use super::{XmlDiagnostic, XmlDiagnosticKind, XmlNode, XmlSource};
+
+/// The text of a file with its `#include`s expanded, and where every line came from.
+#[derive(Default)]
+pub(crate) struct Preprocessed {
+    pub(crate) text: String,
+    pub(crate) lines: Vec<Location>,
+}
+
+/// Expands `#include "name"` lines the way XRay's `CXml` does.
+///
+/// Included names are relative to `root`, the directory of the file that was
+/// loaded, no matter which file includes them.
+pub(crate) fn preprocess<S: XmlSource + ?Sized>(
+    source: &S,
+    root: &Path,
+    path: &Path,
+    out: &mut Preprocessed,
+    stack: &mut Vec<PathBuf>,
+) -> anyhow::Result<()> {
+    log::trace!("preprocess({})", path.display());
+
+    let text = source.read_xml(path)?;
+
+    stack.push(path.to_path_buf());
+
+    for (line_idx, line) in text.lines().enumerate() {
+        let location = Location {
+            file: path.to_path_buf(),
+            line: line_idx + 1,
+        };
+
+        let Some(include) = line.trim_start().strip_prefix("#include") else {
+            out.text.push_str(line);
+            out.text.push('\n');
+            out.lines.push(location);
+            continue;
+        };
+
+        let name = include
+            .trim()
+            .strip_prefix('"')
+            .and_then(|include| include.split_once('"'))
+            .map(|(name, _)| name)
+            .ok_or_else(|| XmlDiagnostic {
+                location: location.clone(),
+                kind: XmlDiagnosticKind::InvalidInclude,
+            })?;
+
+        let mut include_path = root.to_path_buf();
+
+        for component in name.split(['\\', '/']).filter(|c| !c.is_empty()) {
+            include_path.push(component);
+        }
+
+        if stack.contains(&include_path) {
+            return Err(XmlDiagnostic {
+                location,
+                kind: XmlDiagnosticKind::RecursiveInclude { path: include_path },
+            }
+            .into());
+        }
+
+        if preprocess(source, root, &include_path, out, stack).is_err() {
+            return Err(XmlDiagnostic {
+                location,
+                kind: XmlDiagnosticKind::UnresolvedInclude {
+                    include: name.to_owned(),
+                },
+            }
+            .into());
+        }
+    }
+
+    stack.pop();
+
+    Ok(())
+}
+
+/// A forgiving XML parser for the markup the games ship with.
+///
+/// Unescaped `&` and `<`, unquoted attribute values, stray end tags and
+/// unclosed elements are accepted and reported as diagnostics instead.
+pub(crate) struct XmlParser<'a> {
+    text: &'a str,
+    pos: usize,
+    line: usize,
+    lines: &'a [Location],
+    file: &'a Path,
+    diagnostics: Vec<XmlDiagnostic>,
+}
+
+impl<'a> XmlParser<'a> {
+    pub(crate) fn new(preprocessed: &'a Preprocessed, file: &'a Path) -> XmlParser<'a> {
+        XmlParser {
+            text: &preprocessed.text,
+            pos: 0,
+            line: 0,
+            lines: &preprocessed.lines,
+            file,
+            diagnostics: Vec::new(),
+        }
+    }
+
+    /// Parses the whole text into a document node holding the top level elements.
+    pub(crate) fn parse(mut self) -> (XmlNode, Vec<XmlDiagnostic>) {
+        let mut stack = vec![XmlNode::with_location("", self.location())];
+
+        while self.pos < self.text.len() {
+            let rest = self.rest();
+
+            if rest.starts_with("<!--") {
+                self.skip_past("-->", XmlDiagnosticKind::UnterminatedMarkup);
+            } else if let Some(cdata) = rest.strip_prefix("<![CDATA[") {
+                let text = cdata.split_once("]]>").map_or(cdata, |(text, _)| text);
+                stack.last_mut().unwrap().text.push_str(text);
+                self.skip_past("]]>", XmlDiagnosticKind::UnterminatedMarkup);
+            } else if rest.starts_with("<?") {
+                self.skip_past("?>", XmlDiagnosticKind::UnterminatedMarkup);
+            } else if rest.starts_with("<!") {
+                self.skip_past(">", XmlDiagnosticKind::UnterminatedMarkup);
+            } else if rest.starts_with("</") {
+                self.end_tag(&mut stack);
+            } else if rest.starts_with('<') && rest[1..].starts_with(is_name_start) {
+                self.start_tag(&mut stack);
+            } else {
+                self.text(&mut stack);
+            }
+        }
+
+        while stack.len() > 1 {
+            let node = stack.pop().unwrap();
+
+            self.diagnostic(
+                node.location.clone(),
+                XmlDiagnosticKind::UnclosedElement {
+                    name: node.name.clone(),
+                },
+            );
+
+            stack.last_mut().unwrap().children.push(node);
+        }
+
+        (stack.pop().unwrap(), self.diagnostics)
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.text[self.pos..]
+    }
+
+    fn location(&self) -> Location {
+        self.lines
+            .get(self.line)
+            .or(self.lines.last())
+            .cloned()
+            .unwrap_or_else(|| Location {
+                file: self.file.to_path_buf(),
+                line: 0,
+            })
+    }
+
+    fn diagnostic(&mut self, location: Location, kind: XmlDiagnosticKind) {
+        self.diagnostics.push(XmlDiagnostic { location, kind });
+    }
+
+    fn advance(&mut self, len: usize) {
+        let len = len.min(self.text.len() - self.pos);
+
+        self.line += self.text[self.pos..self.pos + len].matches('\n').count();
+        self.pos += len;
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.advance(rest.len() - rest.trim_start().len());
+    }
+
+    fn skip_past(&mut self, end: &str, kind: XmlDiagnosticKind) {
+        match self.rest().find(end) {
+            Some(idx) => self.advance(idx + end.len()),
+            None => {
+                let location = self.location();
+                self.diagnostic(location, kind);
+                self.advance(self.rest().len());
+            }
+        }
+    }
+
+    fn read_name(&mut self) -> &'a str {
+        let rest = self.rest();
+        let len = rest.find(|c| !is_name_char(c)).unwrap_or(rest.len());
+
+        self.advance(len);
+
+        &rest[..len]
+    }
+
+    fn start_tag(&mut self, stack: &mut Vec<XmlNode>) {
+        let location = self.location();
+        self.advance(1);
+
+        let mut node = XmlNode::with_location(self.read_name(), location);
+
+        loop {
+            self.skip_whitespace();
+
+            let rest = self.rest();
+
+            if rest.starts_with("/>") {
+                self.advance(2);
+                stack.last_mut().unwrap().children.push(node);
+                return;
+            }
+
+            if rest.starts_with('>') {
+                self.advance(1);
+                break;
+            }
+
+            // A missing `>`, the tag ends where the next one starts
+            if rest.is_empty() || rest.starts_with('<') {
+                let location = self.location();
+                self.diagnostic(
+                    location,
+                    XmlDiagnosticKind::UnterminatedTag {
+                        name: node.name.clone(),
+                    },
+                );
+                break;
+            }
+
+            let name = self.read_name();
+
+            if name.is_empty() {
+                self.advance(rest.chars().next().map_or(1, char::len_utf8));
+                continue;
+            }
+
+            self.skip_whitespace();
+
+            let value = if self.rest().starts_with('=') {
+                self.advance(1);
+                self.skip_whitespace();
+                self.attribute_value()
+            } else {
+                String::new()
+            };
+
+            node.set_attribute(name, &value);
+        }
+
+        stack.push(node);
+    }
+
+    fn attribute_value(&mut self) -> String {
+        let rest = self.rest();
+
+        let value = match rest.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let value = &rest[1..];
+                let len = value.find(quote).unwrap_or(value.len());
+                self.advance(len + 2);
+
+                &value[..len]
+            }
+            _ => {
+                let len = rest
+                    .find(|c: char| c.is_whitespace() || c == '>' || c == '<')
+                    .unwrap_or(rest.len());
+                let len = if rest[..len].ends_with('/') && rest[len..].starts_with('>') {
+                    len - 1
+                } else {
+                    len
+                };
+                self.advance(len);
+
+                &rest[..len]
+            }
+        };
+
+        decode_entities(value)
+    }
+
+    fn end_tag(&mut self, stack: &mut Vec<XmlNode>) {
+        let location = self.location();
+        self.advance(2);
+
+        let name = self.read_name();
+
+        match self.rest().find('>') {
+            Some(idx) => self.advance(idx + 1),
+            None => self.advance(self.rest().len()),
+        }
+
+        let Some(open) = stack
+            .iter()
+            .skip(1)
+            .rposition(|node| node.name.eq_ignore_ascii_case(name))
+            .map(|idx| idx + 1)
+        else {
+            self.diagnostic(
+                location,
+                XmlDiagnosticKind::UnexpectedEndTag {
+                    name: name.to_owned(),
+                },
+            );
+            return;
+        };
+
+        while stack.len() > open {
+            let node = stack.pop().unwrap();
+
+            if stack.len() > open {
+                self.diagnostic(
+                    node.location.clone(),
+                    XmlDiagnosticKind::UnclosedElement {
+                        name: node.name.clone(),
+                    },
+                );
+            }
+
+            stack.last_mut().unwrap().children.push(node);
+        }
+    }
+
+    fn text(&mut self, stack: &mut [XmlNode]) {
+        let rest = self.rest();
+
+        // A `<` that doesn't start any markup is kept as text
+        let first = rest.chars().next().map_or(1, char::len_utf8);
+        let len = rest[first..]
+            .find('<')
+            .map_or(rest.len(), |idx| idx + first);
+        self.advance(len);
+
+        let text = &rest[..len];
+
+        if !text.trim().is_empty() {
+            stack
+                .last_mut()
+                .unwrap()
+                .text
+                .push_str(&decode_entities(text));
+        }
+    }
+}
+
+fn is_name_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_' || c == ':'
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | ':' | '-' | '.')
+}
+
+/// Replaces character and predefined entity references. Anything else,
+/// like a bare `&`, is kept as it is.
+pub(crate) fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(idx) = rest.find('&') {
+        decoded.push_str(&rest[..idx]);
+        rest = &rest[idx..];
+
+        let entity = rest[1..]
+            .find(';')
+            .filter(|&end| end <= 10)
+            .map(|end| &rest[1..end + 1]);
+
+        let replacement = entity.and_then(|entity| match entity {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => {
+                let code = if let Some(hex) = entity
+                    .strip_prefix("#x")
+                    .or_else(|| entity.strip_prefix("#X"))
+                {
+                    u32::from_str_radix(hex, 16).ok()
+                } else {
+                    entity.strip_prefix('#')?.parse().ok()
+                };
+
+                code.and_then(char::from_u32)
+            }
+        });
+
+        match (entity, replacement) {
+            (Some(entity), Some(replacement)) => {
+                decoded.push(replacement);
+                rest = &rest[entity.len() + 2..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    decoded.push_str(rest);
+
+    decoded
+}
+
+/// Escapes the characters that can't appear as they are in text or attribute values.
+pub(crate) fn encode_entities(text: &str) -> Cow<'_, str> {
+    if !text.contains(['&', '<', '>', '"']) {
+        return Cow::Borrowed(text);
+    }
+
+    let mut encoded = String::with_capacity(text.len() + 16);
+
+    for c in text.chars() {
+        match c {
+            '&' => encoded.push_str("&amp;"),
+            '<' => encoded.push_str("&lt;"),
+            '>' => encoded.push_str("&gt;"),
+            '"' => encoded.push_str("&quot;"),
+            c => encoded.push(c),
+        }
+    }
+
+    Cow::Owned(encoded)
+}
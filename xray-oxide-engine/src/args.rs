@@ -0,0 +1,147 @@
+use std::path::PathBuf;
+
+use thiserror::Error;
+use xray_oxide_core::filesystem::{FilesystemConfig, DEFAULT_FS_LTX};
+
+/// The console config loaded at startup and saved on exit, unless `-ltx` names another.
+pub const DEFAULT_USER_LTX: &str = "user.ltx";
+
+/// What the engine is started with, read from the same command line flags
+/// XRay and OpenXRay take, so existing shortcuts keep working.
+///
+/// Flags this doesn't know are kept for the console, which runs the ones
+/// naming a command as `-name arguments` once it's loaded and skips the rest.
+#[derive(Debug, Clone)]
+pub struct StartupConfig {
+    /// `-fsltx <path>`, the fsgame.ltx describing the gamedata stack.
+    pub fs_ltx: String,
+    /// `-ltx <name>`, the console config in `$app_data_root$`.
+    pub user_ltx: String,
+    /// `-start <server(...) client(...)>`, the game to start right away.
+    pub start: Option<String>,
+    /// `-nointro`, skips the intro videos.
+    pub no_intro: bool,
+    /// `-nosplash`, doesn't show the splash screen while loading.
+    pub no_splash: bool,
+    /// `-nosound`, runs without sound.
+    pub no_sound: bool,
+    /// `-dedicated`, runs a server without a window.
+    pub dedicated: bool,
+    /// `-fs_ignore <patterns>` and `-fs_access_log <path>`.
+    pub filesystem: FilesystemConfig,
+    /// The flags left for the console, in order.
+    pub console_args: Vec<String>,
+}
+
+impl Default for StartupConfig {
+    fn default() -> Self {
+        StartupConfig {
+            fs_ltx: DEFAULT_FS_LTX.to_owned(),
+            user_ltx: DEFAULT_USER_LTX.to_owned(),
+            start: None,
+            no_intro: false,
+            no_splash: false,
+            no_sound: false,
+            dedicated: false,
+            filesystem: FilesystemConfig::default(),
+            console_args: Vec::new(),
+        }
+    }
+}
+
+impl StartupConfig {
+    /// Reads the arguments the engine was started with.
+    pub fn from_env() -> Result<StartupConfig, ArgsError> {
+        StartupConfig::parse(std::env::args().skip(1))
+    }
+
+    /// Reads a list of arguments, without the program name.
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<StartupConfig, ArgsError> {
+        let mut config = StartupConfig::default();
+        let mut args = args.into_iter().peekable();
+
+        while let Some(arg) = args.next() {
+            // A flag's value runs until the next flag, `-start` values contain spaces
+            let mut value = || {
+                let mut value = Vec::new();
+
+                while let Some(arg) = args.next_if(|arg| !arg.starts_with('-')) {
+                    value.push(arg);
+                }
+
+                if value.is_empty() {
+                    Err(ArgsError::MissingValue { flag: arg.clone() })
+                } else {
+                    Ok(value.join(" "))
+                }
+            };
+
+            match arg.as_str() {
+                "-fsltx" => config.fs_ltx = value()?,
+                "-ltx" => config.user_ltx = value()?,
+                "-start" => config.start = Some(value()?),
+                "-fs_ignore" => config
+                    .filesystem
+                    .ignore
+                    .extend(value()?.split('|').map(str::trim).map(str::to_owned)),
+                "-fs_access_log" => config.filesystem.access_log = Some(PathBuf::from(value()?)),
+                "-nointro" => config.no_intro = true,
+                "-nosplash" => config.no_splash = true,
+                "-nosound" => config.no_sound = true,
+                "-dedicated" => config.dedicated = true,
+                _ => config.console_args.push(arg),
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ArgsError {
+    #[error("{flag} needs a value")]
+    MissingValue { flag: String },
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(args: &str) -> Result<StartupConfig, ArgsError> {
+        StartupConfig::parse(args.split_whitespace().map(str::to_owned))
+    }
+
+    #[test]
+    fn test_parse() {
+        let config = parse(
+            "-nointro -fsltx ..\\fsgame.ltx -ltx user_mod.ltx -rs_v_sync on \
+             -start server(all/single/alife/new) client(localhost) \
+             -fs_ignore *.bak|*.psd -nosound -nosplash -dedicated -vid_mode 1280x720",
+        )
+        .unwrap();
+
+        assert!(config.no_intro);
+        assert!(config.no_sound);
+        assert!(config.no_splash);
+        assert!(config.dedicated);
+        assert_eq!(config.fs_ltx, "..\\fsgame.ltx");
+        assert_eq!(config.user_ltx, "user_mod.ltx");
+        assert_eq!(
+            config.start.as_deref(),
+            Some("server(all/single/alife/new) client(localhost)")
+        );
+        assert!(config
+            .filesystem
+            .ignore
+            .ends_with(&["*.bak".to_owned(), "*.psd".to_owned()]));
+        assert_eq!(
+            config.console_args,
+            ["-rs_v_sync", "on", "-vid_mode", "1280x720"]
+        );
+
+        assert_eq!(
+            parse("-fsltx -nointro").unwrap_err().to_string(),
+            "-fsltx needs a value"
+        );
+    }
+}
@@ -0,0 +1,159 @@
+use std::sync::Arc;
+
+use log::LevelFilter;
+use xray_oxide_core::{filesystem::Filesystem, xml::XmlLoader};
+
+use crate::{
+    console::{Command, Console, CvarValue},
+    string_table::StringTable,
+};
+
+pub const VID_MODES: &[&str] = &[
+    "800x600",
+    "1024x768",
+    "1280x720",
+    "1280x1024",
+    "1366x768",
+    "1600x900",
+    "1920x1080",
+    "2560x1440",
+    "3840x2160",
+];
+
+const LOG_LEVELS: &[&str] = &["off", "error", "warn", "info", "debug", "trace"];
+
+/// Where `dxml_dump` writes the patched XML files.
+pub const DXML_DUMP_DIR: &str = "$app_data_root$\\dxml";
+
+/// Registers the commands and variables of the engine itself.
+///
+/// `cfg_save` and `cfg_load` use `user_ltx` in `$app_data_root$` when not given a name.
+pub fn register(
+    console: &Console,
+    filesystem: Arc<Filesystem>,
+    xml: Arc<XmlLoader<Filesystem>>,
+    string_table: Arc<StringTable>,
+    user_ltx: &str,
+) {
+    console.register(Command::bool(
+        "rs_v_sync",
+        false,
+        "Wait for vertical sync before presenting frames",
+    ));
+
+    console.register(Command::integer(
+        "texture_lod",
+        0,
+        0..=4,
+        "Number of the largest mip levels to skip when loading textures, applied on restart",
+    ));
+
+    console.register(Command::token(
+        "vid_mode",
+        "1920x1080",
+        VID_MODES,
+        "Window size",
+    ));
+
+    console.register(
+        Command::token("log_level", "info", LOG_LEVELS, "Most verbose level to log").on_change(
+            |value| {
+                if let CvarValue::Token(level) = value {
+                    log::set_max_level(level.parse().unwrap_or(LevelFilter::Info));
+                }
+
+                Ok(())
+            },
+        ),
+    );
+
+    console.register(
+        Command::bool(
+            "dxml_dump",
+            false,
+            "Write XML files changed by DXML patchers to the app data folder",
+        )
+        .no_save()
+        .on_change({
+            let filesystem = filesystem.clone();
+            move |value| {
+                let dir = match value {
+                    CvarValue::Bool(true) => match filesystem.update_path(DXML_DUMP_DIR) {
+                        Ok(dir) => Some(dir),
+                        Err(e) => {
+                            log::warn!("Failed to resolve {DXML_DUMP_DIR}: {e}");
+                            None
+                        }
+                    },
+                    _ => None,
+                };
+
+                xml.set_dump_dir(dir);
+
+                Ok(())
+            }
+        }),
+    );
+
+    let language = string_table.default_language();
+    let mut languages = string_table.languages();
+    if !languages.contains(&language) {
+        languages.push(language.clone());
+    }
+    let languages = languages.iter().map(String::as_str).collect::<Vec<_>>();
+
+    console.register(
+        Command::token(
+            "g_language",
+            &language,
+            &languages,
+            "Language of the game text",
+        )
+        .on_change(move |value| match value {
+            CvarValue::Token(language) => string_table.set_language(language),
+            _ => Ok(()),
+        }),
+    );
+
+    console.register(Command::action(
+        "help",
+        "Lists the console commands, or describes one",
+        |console, args| {
+            for (name, status, help) in console.help() {
+                if args.is_empty() || args == name {
+                    log::info!("{name} {status} - {help}");
+                }
+            }
+
+            Ok(())
+        },
+    ));
+
+    console.register(Command::action("cfg_save", "Saves the console config", {
+        let filesystem = filesystem.clone();
+        let user_ltx = user_ltx.to_owned();
+        move |console, args| console.save(&filesystem, config_path(&filesystem, &user_ltx, args)?)
+    }));
+
+    console.register(Command::action("cfg_load", "Loads a console config", {
+        let user_ltx = user_ltx.to_owned();
+        move |console, args| console.load(&filesystem, config_path(&filesystem, &user_ltx, args)?)
+    }));
+}
+
+/// Parses a `vid_mode` value like `1920x1080`.
+pub fn parse_vid_mode(mode: &str) -> Option<(u32, u32)> {
+    let (width, height) = mode.split_once('x')?;
+
+    Some((width.parse().ok()?, height.parse().ok()?))
+}
+
+fn config_path(
+    filesystem: &Filesystem,
+    user_ltx: &str,
+    args: &str,
+) -> anyhow::Result<std::path::PathBuf> {
+    let name = if args.is_empty() { user_ltx } else { args };
+
+    filesystem.update_path(&format!("$app_data_root$\\{name}"))
+}
@@ -0,0 +1,592 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{Display, Formatter},
+    ops::RangeInclusive,
+    path::Path,
+    sync::{Arc, RwLock},
+};
+
+use cgmath::Vector3;
+use thiserror::Error;
+use xray_oxide_core::{ext::StrExt, filesystem::Filesystem, ltx::LtxDocument};
+
+pub mod commands;
+
+type Action = Arc<dyn Fn(&Console, &str) -> anyhow::Result<()> + Send + Sync>;
+type OnChange = Arc<dyn Fn(&CvarValue) -> anyhow::Result<()> + Send + Sync>;
+
+/// The registry of console commands and variables, like XRay's `CConsole`.
+///
+/// Commands are executed as lines of text, `name arguments`. Variables print
+/// their value when executed without arguments and set it otherwise.
+#[derive(Default)]
+pub struct Console {
+    commands: RwLock<BTreeMap<String, Command>>,
+}
+
+impl Console {
+    pub fn new() -> Console {
+        Console::default()
+    }
+
+    /// Adds a command, replacing one with the same name.
+    pub fn register(&self, command: Command) {
+        log::trace!("Console::register({})", command.name);
+
+        self.commands
+            .write()
+            .unwrap()
+            .insert(command.name.clone(), command);
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.commands.read().unwrap().contains_key(name)
+    }
+
+    pub fn execute(&self, line: &str) -> anyhow::Result<()> {
+        let line = line.trim();
+
+        if line.is_empty() {
+            return Ok(());
+        }
+
+        let (name, args) = line
+            .split_once(char::is_whitespace)
+            .map(|(name, args)| (name, args.trim()))
+            .unwrap_or((line, ""));
+
+        let mut commands = self.commands.write().unwrap();
+
+        let command = commands
+            .get_mut(name)
+            .ok_or_else(|| ConsoleError::UnknownCommand {
+                name: name.to_owned(),
+            })?;
+
+        if let CommandKind::Action(action) = &command.kind {
+            let action = action.clone();
+            drop(commands);
+
+            return action(self, args);
+        }
+
+        if args.is_empty() {
+            log::info!("{name} = {}", command.status());
+            return Ok(());
+        }
+
+        let previous = command.value();
+
+        if !command.set(args)? {
+            return Ok(());
+        }
+
+        let on_change = command.on_change.clone();
+        let value = command.value();
+        drop(commands);
+
+        if let (Some(on_change), Some(value)) = (on_change, value) {
+            if let Err(e) = on_change(&value) {
+                let mut commands = self.commands.write().unwrap();
+
+                if let (Some(command), Some(previous)) = (commands.get_mut(name), previous) {
+                    command.store(previous);
+                }
+
+                return Err(e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs the commands given on the command line as `-name arguments`.
+    ///
+    /// Flags that aren't console commands are skipped, so this can be given all arguments.
+    pub fn execute_args<I: IntoIterator<Item = String>>(&self, args: I) {
+        let mut args = args.into_iter().peekable();
+
+        while let Some(arg) = args.next() {
+            let Some(name) = arg.strip_prefix('-') else {
+                continue;
+            };
+
+            if !self.contains(name) {
+                continue;
+            }
+
+            let mut line = name.to_owned();
+
+            while let Some(arg) =
+                args.next_if(|arg| !arg.starts_with('-') || arg.parse::<f64>().is_ok())
+            {
+                line.push(' ');
+                line.push_str(&arg);
+            }
+
+            if let Err(e) = self.execute(&line) {
+                log::warn!("{line}: {e}");
+            }
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<CvarValue> {
+        self.commands.read().unwrap().get(name)?.value()
+    }
+
+    pub fn get_bool(&self, name: &str) -> Option<bool> {
+        match self.get(name)? {
+            CvarValue::Bool(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn get_integer(&self, name: &str) -> Option<i32> {
+        match self.get(name)? {
+            CvarValue::Integer(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn get_float(&self, name: &str) -> Option<f32> {
+        match self.get(name)? {
+            CvarValue::Float(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn get_token(&self, name: &str) -> Option<String> {
+        match self.get(name)? {
+            CvarValue::Token(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn get_vector(&self, name: &str) -> Option<Vector3<f32>> {
+        match self.get(name)? {
+            CvarValue::Vector(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Lists every command with its status and help text, sorted by name.
+    pub fn help(&self) -> Vec<(String, String, String)> {
+        self.commands
+            .read()
+            .unwrap()
+            .values()
+            .map(|command| (command.name.clone(), command.status(), command.help.clone()))
+            .collect()
+    }
+
+    /// Writes the variables that are saved as `name value` lines, like XRay's `cfg_save`.
+    ///
+    /// An existing config is updated in place, keeping its comments, key bindings
+    /// and the lines of commands this console doesn't know.
+    pub fn save<P: AsRef<Path>>(&self, filesystem: &Filesystem, path: P) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        log::info!("Saving console config to {}", path.display());
+
+        if !filesystem.exists(path) && path.exists() {
+            filesystem.register_file(path)?;
+        }
+
+        let exists = filesystem.exists(path);
+        let mut document = if exists {
+            LtxDocument::load_console(filesystem, path)?
+        } else {
+            LtxDocument::parse_console("")
+        };
+
+        for command in self.commands.read().unwrap().values() {
+            if let (true, Some(value)) = (command.save, command.value()) {
+                document.set_value("", &command.name, &value.to_string());
+            }
+        }
+
+        if exists && !document.is_modified() {
+            return Ok(());
+        }
+
+        document.save(filesystem, path)
+    }
+
+    /// Executes every line of a config file, like XRay's `cfg_load`.
+    ///
+    /// Lines that fail are logged and skipped.
+    pub fn load<P: AsRef<Path>>(&self, filesystem: &Filesystem, path: P) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        log::info!("Loading console config from {}", path.display());
+
+        if !filesystem.exists(path) && path.exists() {
+            filesystem.register_file(path)?;
+        }
+
+        let text = filesystem.read_to_string(path)?;
+
+        for (line_idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+
+            if let Err(e) = self.execute(line) {
+                log::warn!("{}:{}: {e}", path.display(), line_idx + 1);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A console command or variable.
+pub struct Command {
+    name: String,
+    help: String,
+    kind: CommandKind,
+    save: bool,
+    on_change: Option<OnChange>,
+}
+
+enum CommandKind {
+    Bool(bool),
+    Integer {
+        value: i32,
+        range: RangeInclusive<i32>,
+    },
+    Float {
+        value: f32,
+        range: RangeInclusive<f32>,
+    },
+    Token {
+        value: String,
+        tokens: Vec<String>,
+    },
+    Vector {
+        value: Vector3<f32>,
+        min: Vector3<f32>,
+        max: Vector3<f32>,
+    },
+    Action(Action),
+}
+
+impl Command {
+    fn new(name: &str, help: &str, kind: CommandKind, save: bool) -> Command {
+        Command {
+            name: name.to_owned(),
+            help: help.to_owned(),
+            kind,
+            save,
+            on_change: None,
+        }
+    }
+
+    pub fn bool(name: &str, value: bool, help: &str) -> Command {
+        Command::new(name, help, CommandKind::Bool(value), true)
+    }
+
+    pub fn integer(name: &str, value: i32, range: RangeInclusive<i32>, help: &str) -> Command {
+        Command::new(name, help, CommandKind::Integer { value, range }, true)
+    }
+
+    pub fn float(name: &str, value: f32, range: RangeInclusive<f32>, help: &str) -> Command {
+        Command::new(name, help, CommandKind::Float { value, range }, true)
+    }
+
+    /// A variable that takes one of a fixed list of values.
+    pub fn token(name: &str, value: &str, tokens: &[&str], help: &str) -> Command {
+        let kind = CommandKind::Token {
+            value: value.to_owned(),
+            tokens: tokens.iter().map(|token| token.to_string()).collect(),
+        };
+
+        Command::new(name, help, kind, true)
+    }
+
+    pub fn vector(
+        name: &str,
+        value: Vector3<f32>,
+        min: Vector3<f32>,
+        max: Vector3<f32>,
+        help: &str,
+    ) -> Command {
+        Command::new(name, help, CommandKind::Vector { value, min, max }, true)
+    }
+
+    /// A command that runs `action` with its arguments.
+    pub fn action<F>(name: &str, help: &str, action: F) -> Command
+    where
+        F: Fn(&Console, &str) -> anyhow::Result<()> + Send + Sync + 'static,
+    {
+        Command::new(name, help, CommandKind::Action(Arc::new(action)), false)
+    }
+
+    /// Keeps the variable out of `cfg_save`.
+    pub fn no_save(mut self) -> Command {
+        self.save = false;
+        self
+    }
+
+    /// Calls `on_change` whenever the variable is set to a new value.
+    ///
+    /// If `on_change` fails, the variable goes back to its previous value.
+    pub fn on_change<F>(mut self, on_change: F) -> Command
+    where
+        F: Fn(&CvarValue) -> anyhow::Result<()> + Send + Sync + 'static,
+    {
+        self.on_change = Some(Arc::new(on_change));
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn help(&self) -> &str {
+        &self.help
+    }
+
+    pub fn value(&self) -> Option<CvarValue> {
+        Some(match &self.kind {
+            CommandKind::Bool(value) => CvarValue::Bool(*value),
+            CommandKind::Integer { value, .. } => CvarValue::Integer(*value),
+            CommandKind::Float { value, .. } => CvarValue::Float(*value),
+            CommandKind::Token { value, .. } => CvarValue::Token(value.clone()),
+            CommandKind::Vector { value, .. } => CvarValue::Vector(*value),
+            CommandKind::Action(_) => return None,
+        })
+    }
+
+    /// The value and what it can be set to, like XRay's `Status` and `Info`.
+    fn status(&self) -> String {
+        match &self.kind {
+            CommandKind::Bool(value) => format!("{} (on/off)", CvarValue::Bool(*value)),
+            CommandKind::Integer { value, range } => {
+                format!("{value} ({}..{})", range.start(), range.end())
+            }
+            CommandKind::Float { value, range } => {
+                format!("{value} ({}..{})", range.start(), range.end())
+            }
+            CommandKind::Token { value, tokens } => format!("{value} ({})", tokens.join("/")),
+            CommandKind::Vector { value, min, max } => format!(
+                "{} ({}..{})",
+                CvarValue::Vector(*value),
+                CvarValue::Vector(*min),
+                CvarValue::Vector(*max)
+            ),
+            CommandKind::Action(_) => String::new(),
+        }
+    }
+
+    /// Parses and sets the value. Returns whether it changed.
+    fn set(&mut self, args: &str) -> Result<bool, ConsoleError> {
+        let invalid = |reason: String| ConsoleError::InvalidArgument {
+            name: self.name.clone(),
+            argument: args.to_owned(),
+            reason,
+        };
+
+        let new = match &self.kind {
+            CommandKind::Bool(_) => CvarValue::Bool(
+                args.parse_bool()
+                    .ok_or_else(|| invalid("expected on or off".to_owned()))?,
+            ),
+            CommandKind::Integer { range, .. } => {
+                let value = args
+                    .parse()
+                    .map_err(|_| invalid("expected an integer".to_owned()))?;
+
+                if !range.contains(&value) {
+                    return Err(invalid(format!(
+                        "out of range {}..{}",
+                        range.start(),
+                        range.end()
+                    )));
+                }
+
+                CvarValue::Integer(value)
+            }
+            CommandKind::Float { range, .. } => {
+                let value = args
+                    .parse()
+                    .map_err(|_| invalid("expected a number".to_owned()))?;
+
+                if !range.contains(&value) {
+                    return Err(invalid(format!(
+                        "out of range {}..{}",
+                        range.start(),
+                        range.end()
+                    )));
+                }
+
+                CvarValue::Float(value)
+            }
+            CommandKind::Token { tokens, .. } => {
+                if !tokens.iter().any(|token| token == args) {
+                    return Err(invalid(format!("expected one of {}", tokens.join(", "))));
+                }
+
+                CvarValue::Token(args.to_owned())
+            }
+            CommandKind::Vector { min, max, .. } => {
+                let value =
+                    parse_vector(args).ok_or_else(|| invalid("expected (x, y, z)".to_owned()))?;
+
+                let in_range = (0..3).all(|i| min[i] <= value[i] && value[i] <= max[i]);
+
+                if !in_range {
+                    return Err(invalid(format!(
+                        "out of range {}..{}",
+                        CvarValue::Vector(*min),
+                        CvarValue::Vector(*max)
+                    )));
+                }
+
+                CvarValue::Vector(value)
+            }
+            CommandKind::Action(_) => unreachable!(),
+        };
+
+        if self.value().as_ref() == Some(&new) {
+            return Ok(false);
+        }
+
+        self.store(new);
+
+        Ok(true)
+    }
+
+    fn store(&mut self, new: CvarValue) {
+        match (&mut self.kind, new) {
+            (CommandKind::Bool(value), CvarValue::Bool(new)) => *value = new,
+            (CommandKind::Integer { value, .. }, CvarValue::Integer(new)) => *value = new,
+            (CommandKind::Float { value, .. }, CvarValue::Float(new)) => *value = new,
+            (CommandKind::Token { value, .. }, CvarValue::Token(new)) => *value = new,
+            (CommandKind::Vector { value, .. }, CvarValue::Vector(new)) => *value = new,
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CvarValue {
+    Bool(bool),
+    Integer(i32),
+    Float(f32),
+    Token(String),
+    Vector(Vector3<f32>),
+}
+
+impl Display for CvarValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CvarValue::Bool(true) => write!(f, "on"),
+            CvarValue::Bool(false) => write!(f, "off"),
+            CvarValue::Integer(value) => write!(f, "{value}"),
+            CvarValue::Float(value) => write!(f, "{value}"),
+            CvarValue::Token(value) => write!(f, "{value}"),
+            CvarValue::Vector(value) => write!(f, "({}, {}, {})", value.x, value.y, value.z),
+        }
+    }
+}
+
+/// Parses `(x, y, z)`, with or without the parentheses.
+fn parse_vector(args: &str) -> Option<Vector3<f32>> {
+    let args = args.trim();
+    let args = args
+        .strip_prefix('(')
+        .and_then(|args| args.strip_suffix(')'))
+        .unwrap_or(args);
+
+    let mut values = args.split(',').map(|value| value.trim().parse::<f32>());
+
+    let vector = Vector3::new(
+        values.next()?.ok()?,
+        values.next()?.ok()?,
+        values.next()?.ok()?,
+    );
+
+    values.next().is_none().then_some(vector)
+}
+
+#[derive(Error, Debug)]
+pub enum ConsoleError {
+    #[error("unknown command {name}")]
+    UnknownCommand { name: String },
+    #[error("invalid argument \"{argument}\" for {name}: {reason}")]
+    InvalidArgument {
+        name: String,
+        argument: String,
+        reason: String,
+    },
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[test]
+    fn test_cvars() {
+        let console = Console::new();
+        let changes = Arc::new(AtomicUsize::new(0));
+
+        console.register(Command::bool("rs_v_sync", false, "vsync"));
+        console.register(
+            Command::integer("fps_limit", 60, 30..=240, "fps").on_change({
+                let changes = changes.clone();
+                move |_| {
+                    changes.fetch_add(1, Ordering::SeqCst);
+                    Ok(())
+                }
+            }),
+        );
+        console.register(Command::float("snd_volume", 1.0, 0.0..=1.0, "volume"));
+        console.register(Command::token(
+            "quality",
+            "high",
+            &["low", "high"],
+            "quality",
+        ));
+        console.register(Command::vector(
+            "offset",
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(-1.0, -1.0, -1.0),
+            Vector3::new(1.0, 1.0, 1.0),
+            "offset",
+        ));
+
+        console.execute("rs_v_sync on").unwrap();
+        console.execute("fps_limit 144").unwrap();
+        console.execute("fps_limit 144").unwrap();
+        console.execute("snd_volume 0.5").unwrap();
+        console.execute("quality low").unwrap();
+        console.execute("offset (0.5, -1, 1)").unwrap();
+
+        assert_eq!(console.get_bool("rs_v_sync"), Some(true));
+        assert_eq!(console.get_integer("fps_limit"), Some(144));
+        assert_eq!(changes.load(Ordering::SeqCst), 1);
+        assert_eq!(console.get_float("snd_volume"), Some(0.5));
+        assert_eq!(console.get_token("quality").as_deref(), Some("low"));
+        assert_eq!(console.get("offset").unwrap().to_string(), "(0.5, -1, 1)");
+
+        console.register(
+            Command::token("language", "eng", &["eng", "rus"], "language")
+                .on_change(|_| anyhow::bail!("no string tables")),
+        );
+        assert!(console.execute("language rus").is_err());
+        assert_eq!(console.get_token("language").as_deref(), Some("eng"));
+
+        assert!(console.execute("fps_limit 1000").is_err());
+        assert!(console.execute("quality ultra").is_err());
+        assert!(console.execute("offset 1, 2").is_err());
+        assert!(console.execute("nope").is_err());
+
+        console
+            .execute_args(["-nointro", "-fps_limit", "75", "-snd_volume", "0"].map(str::to_owned));
+        assert_eq!(console.get_integer("fps_limit"), Some(75));
+        assert_eq!(console.get_float("snd_volume"), Some(0.0));
+    }
+}
@@ -9,23 +9,43 @@ use winit::{
     event_loop::EventLoop,
     window::{Window, WindowBuilder},
 };
-use xray_oxide_core::filesystem::Filesystem;
+use xray_oxide_core::{
+    filesystem::Filesystem,
+    level::{Level, LEVELS_ROOT},
+    xml::XmlLoader,
+};
 use xray_oxide_render::Renderer;
 use xray_oxide_render_wgpu::WgpuRenderer;
 
-use crate::ext::WindowExt;
+use crate::{
+    args::StartupConfig,
+    console::{commands, Console},
+    ext::WindowExt,
+    string_table::StringTable,
+};
 
+pub mod args;
+pub mod console;
 pub mod ext;
 pub mod splash;
+pub mod string_table;
 
-pub fn entry_point() -> anyhow::Result<()> {
+pub fn entry_point(config: StartupConfig) -> anyhow::Result<()> {
+    // Everything is passed to the logger, `log_level` lowers it once the console is loaded
     SimpleLogger::new()
-        .with_level(log::LevelFilter::Info)
+        .with_level(log::LevelFilter::Trace)
         // .with_module_level("xray_oxide::core::filesystem", log::LevelFilter::Trace)
         .init()?;
+    log::set_max_level(log::LevelFilter::Info);
 
     let start = Instant::now();
 
+    if config.dedicated {
+        log::warn!("-dedicated isn't supported yet, starting with a window");
+    }
+
+    let no_splash = config.no_splash;
+
     let mut event_loop = EventLoop::new()?;
 
     let window = WindowBuilder::new()
@@ -51,13 +71,15 @@ pub fn entry_point() -> anyhow::Result<()> {
                 ));
             }
 
-            let app = XRay::new(window);
+            let app = XRay::new(window, config);
             proxy.send_event(()).unwrap();
             app
         })
     };
 
-    splash::show_splash(&mut event_loop)?;
+    if !no_splash {
+        splash::show_splash(&mut event_loop)?;
+    }
 
     let mut xray = prepare_thread.join().unwrap()?;
 
@@ -77,6 +99,14 @@ pub fn entry_point() -> anyhow::Result<()> {
             if window_id == xray.renderer.window().id() {
                 match event {
                     WindowEvent::CloseRequested => {
+                        if let Err(e) = xray.console.execute("cfg_save") {
+                            log::error!("Failed to save console config: {e}");
+                        }
+
+                        if let Err(e) = xray.filesystem.dump_access_log() {
+                            log::error!("Failed to write file access log: {e}");
+                        }
+
                         target.exit();
                     }
                     WindowEvent::RedrawRequested => {
@@ -101,6 +131,39 @@ pub struct LevelInfo {
     name: String,
 }
 
+impl LevelInfo {
+    /// Finds the levels in `$game_levels$`, the folders with a `level` file,
+    /// like XRay's `Level_Scan`. Until the game graph names them, a level's
+    /// name is its folder.
+    pub fn scan(filesystem: &Filesystem) -> anyhow::Result<Vec<LevelInfo>> {
+        let root = filesystem.update_path(LEVELS_ROOT)?;
+
+        Ok(filesystem
+            .directory_list(root)
+            .iter()
+            .filter(|folder| filesystem.exists(folder.join("level")))
+            .filter_map(|folder| folder.file_name()?.to_str())
+            .map(|folder| LevelInfo {
+                folder: folder.to_owned(),
+                name: folder.to_owned(),
+            })
+            .collect())
+    }
+
+    pub fn folder(&self) -> &str {
+        &self.folder
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Loads the level's visuals, shaders and sectors from `$game_levels$`.
+    pub fn load(&self, filesystem: &Filesystem) -> anyhow::Result<Level> {
+        Level::load(filesystem, &self.folder)
+    }
+}
+
 pub struct XRay {
     loaded: bool,
     ll_dwReference: u32,
@@ -109,12 +172,51 @@ pub struct XRay {
     pub current_level: Option<usize>,
     loading_screen: Option<()>,
     filesystem: Arc<Filesystem>,
+    console: Arc<Console>,
+    string_table: Arc<StringTable>,
+    config: StartupConfig,
     renderer: Box<dyn Renderer + Send>,
 }
 
 impl XRay {
-    pub fn new(window: Window) -> anyhow::Result<XRay> {
-        let filesystem = Arc::new(Filesystem::new()?);
+    pub fn new(window: Window, config: StartupConfig) -> anyhow::Result<XRay> {
+        let filesystem = Arc::new(Filesystem::with_config(
+            &config.fs_ltx,
+            config.filesystem.clone(),
+        )?);
+
+        let xml = Arc::new(XmlLoader::new(filesystem.clone()));
+        let string_table = Arc::new(StringTable::new(filesystem.clone(), xml.clone()));
+
+        let console = Arc::new(Console::new());
+        commands::register(
+            &console,
+            filesystem.clone(),
+            xml,
+            string_table.clone(),
+            &config.user_ltx,
+        );
+
+        if let Err(e) = console.execute("cfg_load") {
+            log::warn!("Failed to load console config: {e}");
+        }
+
+        console.execute_args(config.console_args.iter().cloned());
+
+        // Loaded once the console config had its say about the language. If it
+        // falls back to English, `g_language` keeps the setting for next time.
+        let language = console.get_token("g_language").unwrap_or_default();
+        string_table.load(&language);
+
+        if let Some((width, height)) = console
+            .get_token("vid_mode")
+            .and_then(|mode| commands::parse_vid_mode(&mode))
+        {
+            let _ = window.request_inner_size(LogicalSize::new(width, height));
+        }
+
+        let vsync = console.get_bool("rs_v_sync").unwrap_or(false);
+        let texture_lod = console.get_integer("texture_lod").unwrap_or(0) as u32;
 
         let mut app = XRay {
             loaded: false,
@@ -123,25 +225,95 @@ impl XRay {
             levels: Vec::new(),
             current_level: None,
             loading_screen: None,
-            renderer: select_renderer(window, filesystem.clone())?,
+            renderer: select_renderer(window, filesystem.clone(), vsync, texture_lod)?,
             filesystem,
+            console,
+            string_table,
+            config,
         };
 
-        app.level_scan();
+        app.level_scan()?;
 
         Ok(app)
     }
 
-    pub fn level_scan(&mut self) {
-        self.levels.clear();
+    /// The settings the engine was started with.
+    pub fn config(&self) -> &StartupConfig {
+        &self.config
+    }
+
+    pub fn string_table(&self) -> &Arc<StringTable> {
+        &self.string_table
+    }
+
+    /// Finds the levels in `$game_levels$` again.
+    pub fn level_scan(&mut self) -> anyhow::Result<()> {
+        self.levels = LevelInfo::scan(&self.filesystem)?;
+        self.current_level = None;
+
+        log::info!("Found {} levels", self.levels.len());
+
+        Ok(())
     }
 }
 
 fn select_renderer(
     window: Window,
     filesystem: Arc<Filesystem>,
+    vsync: bool,
+    texture_lod: u32,
 ) -> anyhow::Result<Box<dyn Renderer + Send>> {
-    let renderer = Box::new(pollster::block_on(WgpuRenderer::new(window, filesystem))?);
+    let renderer = Box::new(pollster::block_on(WgpuRenderer::new(
+        window,
+        filesystem,
+        vsync,
+        texture_lod,
+    ))?);
 
     Ok(renderer)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_level_scan() {
+        let root =
+            std::env::temp_dir().join(format!("xray-oxide-level-scan-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+
+        for path in [
+            "l01_escape/level",
+            "l01_escape/level.geom",
+            "l02_garbage/level.ltx",
+        ] {
+            let path = root.join("gamedata/levels").join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, []).unwrap();
+        }
+
+        let fs_ltx = root.join("fsgame.ltx");
+        std::fs::write(
+            &fs_ltx,
+            "$game_data$ = true| false| $fs_root$| gamedata\n\
+             $game_levels$ = true| false| $game_data$| levels\n",
+        )
+        .unwrap();
+
+        let filesystem = Filesystem::with_fs_ltx(fs_ltx.to_str().unwrap()).unwrap();
+        let levels = LevelInfo::scan(&filesystem).unwrap();
+        assert_eq!(
+            levels
+                .iter()
+                .map(|level| (level.folder(), level.name()))
+                .collect::<Vec<_>>(),
+            [("l01_escape", "l01_escape")]
+        );
+
+        // The level file is empty, so it's found but fails to load
+        assert!(levels[0].load(&filesystem).is_err());
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
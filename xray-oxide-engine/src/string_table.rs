@@ -0,0 +1,343 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use xray_oxide_core::{
+    filesystem::Filesystem,
+    ltx::Ltx,
+    xml::{Xml, XmlLoader},
+};
+
+/// The language whose strings are used when the current language lacks an id.
+pub const FALLBACK_LANGUAGE: &str = "eng";
+
+/// Where the language is set when there's no `g_language` in the console config.
+pub const LOCALIZATION_LTX: &str = "$game_config$\\localization.ltx";
+
+const TEXT_DIR: &str = "$game_config$\\text";
+
+type OnChange = Box<dyn Fn(&str) + Send + Sync>;
+
+/// Localized text by id, like XRay's `CStringTable`.
+///
+/// The strings of a language come from every XML file in
+/// `$game_config$\text\<language>`, decoded with the filesystem's codepage,
+/// so CP1251 tables end up as UTF-8. Ids missing from the current language
+/// are looked up in English.
+///
+/// Nothing is loaded until the language is set, so the console config can
+/// pick it before any tables are read.
+pub struct StringTable {
+    filesystem: Arc<Filesystem>,
+    xml: Arc<XmlLoader<Filesystem>>,
+    tables: RwLock<Tables>,
+    on_change: RwLock<Vec<OnChange>>,
+}
+
+#[derive(Default)]
+struct Tables {
+    language: String,
+    strings: HashMap<String, String>,
+    fallback: HashMap<String, String>,
+}
+
+impl StringTable {
+    pub fn new(filesystem: Arc<Filesystem>, xml: Arc<XmlLoader<Filesystem>>) -> StringTable {
+        StringTable {
+            filesystem,
+            xml,
+            tables: RwLock::new(Tables::default()),
+            on_change: RwLock::new(Vec::new()),
+        }
+    }
+
+    /// Loads the string tables of `language` at startup, falling back to English.
+    ///
+    /// Missing tables only leave the ids untranslated, they never stop the game
+    /// from starting.
+    pub fn load(&self, language: &str) {
+        let mut languages = vec![language];
+        if language != FALLBACK_LANGUAGE {
+            languages.push(FALLBACK_LANGUAGE);
+        }
+
+        for language in languages {
+            match self.set_language(language) {
+                Ok(()) => return,
+                Err(e) => log::warn!("Failed to load {language} string tables: {e}"),
+            }
+        }
+
+        log::warn!("No string tables, text is shown as ids");
+    }
+
+    /// The `language` of the `[string_table]` section in `localization.ltx`.
+    pub fn default_language(&self) -> String {
+        let language = self
+            .filesystem
+            .update_path(LOCALIZATION_LTX)
+            .and_then(|path| Ltx::load_lenient(self.filesystem.as_ref(), path))
+            .and_then(|ltx| Ok(ltx.r_string("string_table", "language")?.to_owned()));
+
+        match language {
+            Ok(language) if !language.is_empty() => language,
+            Ok(_) => FALLBACK_LANGUAGE.to_owned(),
+            Err(e) => {
+                log::warn!("No language in {LOCALIZATION_LTX}, using {FALLBACK_LANGUAGE}: {e}");
+                FALLBACK_LANGUAGE.to_owned()
+            }
+        }
+    }
+
+    /// The languages there are string tables for.
+    pub fn languages(&self) -> Vec<String> {
+        let Ok(dir) = self.filesystem.update_path(TEXT_DIR) else {
+            return Vec::new();
+        };
+
+        self.filesystem
+            .directory_list(dir)
+            .iter()
+            .filter_map(|language| language.file_name()?.to_str())
+            .map(str::to_owned)
+            .collect()
+    }
+
+    /// The language whose tables are loaded, which is English when the one
+    /// asked for has none, and empty if nothing is loaded.
+    pub fn language(&self) -> String {
+        self.tables.read().unwrap().language.clone()
+    }
+
+    /// Loads the string tables of another language and tells the listeners
+    /// registered with [`StringTable::on_change`]. Does nothing if it's the
+    /// current language, and keeps the current one if there are no tables.
+    pub fn set_language(&self, language: &str) -> anyhow::Result<()> {
+        if self.tables.read().unwrap().language == language {
+            return Ok(());
+        }
+
+        log::info!("Loading string tables for {language}");
+
+        let strings = self.load_language(language)?;
+
+        let fallback = if language == FALLBACK_LANGUAGE {
+            HashMap::new()
+        } else {
+            self.load_language(FALLBACK_LANGUAGE).unwrap_or_else(|e| {
+                log::warn!("Failed to load {FALLBACK_LANGUAGE} string tables: {e}");
+                HashMap::new()
+            })
+        };
+
+        *self.tables.write().unwrap() = Tables {
+            language: language.to_owned(),
+            strings,
+            fallback,
+        };
+
+        for on_change in self.on_change.read().unwrap().iter() {
+            on_change(language);
+        }
+
+        Ok(())
+    }
+
+    /// Calls `on_change` with the new language whenever it changes, so text
+    /// can be looked up again and laid out.
+    pub fn on_change<F>(&self, on_change: F)
+    where
+        F: Fn(&str) + Send + Sync + 'static,
+    {
+        self.on_change.write().unwrap().push(Box::new(on_change));
+    }
+
+    /// The text of an id, in the current language or else in English.
+    pub fn get(&self, id: &str) -> Option<String> {
+        let tables = self.tables.read().unwrap();
+
+        tables
+            .strings
+            .get(id)
+            .or_else(|| tables.fallback.get(id))
+            .cloned()
+    }
+
+    /// The text of an id, or the id itself if there's none, like XRay's `translate`.
+    pub fn translate(&self, id: &str) -> String {
+        self.get(id).unwrap_or_else(|| id.to_owned())
+    }
+
+    fn load_language(&self, language: &str) -> anyhow::Result<HashMap<String, String>> {
+        let dir = self.filesystem.update_path(TEXT_DIR)?.join(language);
+
+        let files = self
+            .filesystem
+            .file_list(&dir)
+            .into_iter()
+            .filter(|file| {
+                file.extension()
+                    .is_some_and(|extension| extension.eq_ignore_ascii_case("xml"))
+            })
+            .collect::<Vec<_>>();
+
+        if files.is_empty() {
+            anyhow::bail!("no string tables in {}", dir.display());
+        }
+
+        let mut strings = HashMap::new();
+
+        for file in files {
+            let xml = self.xml.load(&file)?;
+            read_strings(&xml, &mut strings);
+        }
+
+        Ok(strings)
+    }
+}
+
+/// Reads the `<string id="..."><text>...</text></string>` entries of a table.
+fn read_strings(xml: &Xml, strings: &mut HashMap<String, String>) {
+    let Some(table) = xml.navigate_to_node("string_table", 0) else {
+        log::warn!("{} has no <string_table>", xml.path().display());
+        return;
+    };
+
+    for string in table.children_named("string") {
+        let Some(id) = string.attribute("id") else {
+            log::warn!("{}: <string> without an id", string.location());
+            continue;
+        };
+
+        let text = string.navigate("text", 0).map_or("", |text| text.text());
+
+        if strings.insert(id.to_owned(), text.to_owned()).is_some() {
+            log::warn!("{}: duplicate string table id {id}", string.location());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        path::PathBuf,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use super::*;
+
+    fn string_table(name: &str, files: &[(&str, &str)]) -> (PathBuf, StringTable) {
+        let root = std::env::temp_dir().join(format!(
+            "xray-oxide-string-table-{name}-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+
+        for (path, text) in files {
+            let path = root.join("gamedata/configs").join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, text).unwrap();
+        }
+
+        let fs_ltx = root.join("fsgame.ltx");
+        std::fs::write(
+            &fs_ltx,
+            "$game_data$ = true| false| $fs_root$| gamedata\n\
+             $game_config$ = true| false| $game_data$| configs\n",
+        )
+        .unwrap();
+
+        let filesystem = Arc::new(Filesystem::with_fs_ltx(fs_ltx.to_str().unwrap()).unwrap());
+        let xml = Arc::new(XmlLoader::new(filesystem.clone()));
+
+        (root, StringTable::new(filesystem, xml))
+    }
+
+    fn table(strings: &[(&str, &str)]) -> String {
+        let strings = strings
+            .iter()
+            .map(|(id, text)| format!("<string id=\"{id}\"><text>{text}</text></string>"))
+            .collect::<String>();
+
+        format!("<string_table>{strings}</string_table>")
+    }
+
+    #[test]
+    fn test_languages() {
+        let (root, string_table) = string_table(
+            "languages",
+            &[
+                ("localization.ltx", "[string_table]\nlanguage = rus\n"),
+                (
+                    "text/eng/st_ui.xml",
+                    &table(&[("ui_quit", "Quit"), ("ui_load", "Load")]),
+                ),
+                ("text/rus/st_ui.xml", &table(&[("ui_quit", "Выход")])),
+            ],
+        );
+
+        let changes = Arc::new(AtomicUsize::new(0));
+        string_table.on_change({
+            let changes = changes.clone();
+            move |_| {
+                changes.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+
+        assert_eq!(string_table.default_language(), "rus");
+        assert_eq!(string_table.languages().len(), 2);
+
+        string_table.load("rus");
+        assert_eq!(string_table.translate("ui_quit"), "Выход");
+        assert_eq!(string_table.translate("ui_load"), "Load");
+        assert_eq!(string_table.translate("ui_missing"), "ui_missing");
+
+        string_table.set_language("eng").unwrap();
+        assert_eq!(string_table.translate("ui_quit"), "Quit");
+        assert_eq!(changes.load(Ordering::SeqCst), 2);
+
+        assert!(string_table.set_language("ger").is_err());
+        assert_eq!(string_table.language(), "eng");
+
+        string_table.set_language("rus").unwrap();
+        string_table.load("ger");
+        assert_eq!(string_table.language(), "eng");
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_no_tables() {
+        let (root, string_table) = string_table("no-tables", &[("system.ltx", "")]);
+
+        assert_eq!(string_table.default_language(), FALLBACK_LANGUAGE);
+
+        string_table.load("rus");
+        assert_eq!(string_table.language(), "");
+        assert_eq!(string_table.translate("ui_quit"), "ui_quit");
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_read_strings() {
+        let xml = Xml::parse(
+            "st_ui.xml",
+            "<?xml version=\"1.0\" encoding=\"windows-1251\"?>\n\
+             <string_table>\n\
+             <string id=\"ui_mm_quit\"><text>Выход</text></string>\n\
+             <string id=\"ui_mm_empty\"></string>\n\
+             <string id=\"ui_mm_quit\"><text>Quit</text></string>\n\
+             </string_table>\n",
+        )
+        .unwrap();
+
+        let mut strings = HashMap::new();
+        read_strings(&xml, &mut strings);
+
+        assert_eq!(strings.len(), 2);
+        assert_eq!(strings["ui_mm_quit"], "Quit");
+        assert_eq!(strings["ui_mm_empty"], "");
+    }
+}
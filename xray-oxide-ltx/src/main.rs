@@ -0,0 +1,236 @@
+use std::{io::Write, process::ExitCode};
+
+use clap::{Parser, Subcommand};
+use simple_logger::SimpleLogger;
+use xray_oxide_core::{
+    filesystem::{Filesystem, DEFAULT_FS_LTX},
+    ltx::{Ltx, Severity},
+};
+
+const DEFAULT_LTX: &str = "$game_config$\\system.ltx";
+
+/// Checks and inspects the LTX configs of a gamedata stack, with the mod
+/// archives and DLTX mod files applied the same way the engine does.
+#[derive(Parser)]
+#[command(version)]
+struct Args {
+    /// The fsgame.ltx describing the gamedata stack.
+    #[arg(long, default_value = DEFAULT_FS_LTX)]
+    fsltx: String,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Reports unresolved includes, missing parent sections, inheritance cycles,
+    /// duplicate keys and DLTX patches of missing sections.
+    Lint {
+        /// The root LTX files, as virtual paths.
+        #[arg(default_value = DEFAULT_LTX)]
+        files: Vec<String>,
+
+        /// Don't report warnings.
+        #[arg(long)]
+        errors_only: bool,
+    },
+    /// Prints a section with inheritance and DLTX patches applied, and where each value comes from.
+    Resolve {
+        sections: Vec<String>,
+
+        /// The root LTX file, as a virtual path.
+        #[arg(long, default_value = DEFAULT_LTX)]
+        file: String,
+    },
+}
+
+fn main() -> anyhow::Result<ExitCode> {
+    SimpleLogger::new()
+        .with_level(log::LevelFilter::Warn)
+        .init()?;
+
+    let args = Args::parse();
+
+    let filesystem = Filesystem::with_fs_ltx(&args.fsltx)?;
+
+    let mut out = std::io::stdout().lock();
+
+    match args.command {
+        Command::Lint { files, errors_only } => lint(&filesystem, &files, errors_only, &mut out),
+        Command::Resolve { sections, file } => resolve(&filesystem, &sections, &file, &mut out),
+    }
+}
+
+fn lint<W: Write>(
+    filesystem: &Filesystem,
+    files: &[String],
+    errors_only: bool,
+    out: &mut W,
+) -> anyhow::Result<ExitCode> {
+    let mut errors = 0;
+    let mut warnings = 0;
+
+    for file in files {
+        let ltx = Ltx::load_lenient(filesystem, filesystem.update_path(file)?)?;
+
+        for diagnostic in ltx.diagnostics() {
+            match diagnostic.severity() {
+                Severity::Error => {
+                    errors += 1;
+                    writeln!(out, "error: {diagnostic}")?;
+                }
+                Severity::Warning => {
+                    warnings += 1;
+
+                    if !errors_only {
+                        writeln!(out, "warning: {diagnostic}")?;
+                    }
+                }
+            }
+        }
+    }
+
+    writeln!(out, "{errors} errors, {warnings} warnings")?;
+
+    Ok(if errors > 0 {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+fn resolve<W: Write>(
+    filesystem: &Filesystem,
+    sections: &[String],
+    file: &str,
+    out: &mut W,
+) -> anyhow::Result<ExitCode> {
+    let ltx = Ltx::load_lenient(filesystem, filesystem.update_path(file)?)?;
+
+    for name in sections {
+        let section = ltx.r_section(name)?;
+
+        write!(out, "[{}]", section.name())?;
+        if !section.parents().is_empty() {
+            write!(out, ":{}", section.parents().join(", "))?;
+        }
+        writeln!(out, " ; {}", section.location())?;
+
+        for item in section.items() {
+            match item.value() {
+                Some(value) => write!(out, "{} = {value}", item.key())?,
+                None => write!(out, "{}", item.key())?,
+            }
+
+            write!(out, " ; {}", item.location())?;
+
+            if item.is_patched() {
+                let patches = item
+                    .patches()
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>();
+
+                write!(out, ", patched by {}", patches.join(", "))?;
+            }
+
+            writeln!(out)?;
+        }
+
+        writeln!(out)?;
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn gamedata(name: &str, files: &[(&str, &str)]) -> (PathBuf, Filesystem) {
+        let root =
+            std::env::temp_dir().join(format!("xray-oxide-ltx-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+
+        for (path, text) in files {
+            let path = root.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, text).unwrap();
+        }
+
+        let fs_ltx = root.join(DEFAULT_FS_LTX);
+        std::fs::write(
+            &fs_ltx,
+            "$game_data$ = true| false| $fs_root$| gamedata\n\
+             $game_config$ = true| false| $game_data$| configs\n",
+        )
+        .unwrap();
+
+        let filesystem = Filesystem::with_fs_ltx(fs_ltx.to_str().unwrap()).unwrap();
+
+        (root, filesystem)
+    }
+
+    #[test]
+    fn test_lint_and_resolve() {
+        let (root, filesystem) = gamedata(
+            "lint",
+            &[
+                (
+                    "gamedata/configs/system.ltx",
+                    "#include \"missing.ltx\"\n\
+                     [base]\ncost = 1\ncost = 2\n\
+                     [weapon]:base\nammo = 5\n",
+                ),
+                (
+                    "gamedata/configs/mod_system_a.ltx",
+                    "![weapon]\nammo = 10\n",
+                ),
+            ],
+        );
+
+        let run = |command: &dyn Fn(&mut Vec<u8>) -> anyhow::Result<ExitCode>| {
+            let mut out = Vec::new();
+            let code = command(&mut out).unwrap();
+            (code, String::from_utf8(out).unwrap())
+        };
+
+        let system = root.join("gamedata/configs/system.ltx");
+        let patch = root.join("gamedata/configs/mod_system_a.ltx");
+        let files = [DEFAULT_LTX.to_owned()];
+
+        let (code, out) = run(&|out| lint(&filesystem, &files, false, out));
+        assert_eq!(code, ExitCode::FAILURE);
+        assert_eq!(
+            out,
+            format!(
+                "error: {0}:1: unresolved #include \"missing.ltx\"\n\
+                 warning: {0}:4: duplicate key \"cost\" in [base], first defined at {0}:3\n\
+                 1 errors, 1 warnings\n",
+                system.display()
+            )
+        );
+
+        let (_, out) = run(&|out| lint(&filesystem, &files, true, out));
+        assert!(!out.contains("warning:"));
+
+        let sections = ["weapon".to_owned()];
+        let (code, out) = run(&|out| resolve(&filesystem, &sections, DEFAULT_LTX, out));
+        assert_eq!(code, ExitCode::SUCCESS);
+        assert_eq!(
+            out,
+            format!(
+                "[weapon]:base ; {0}:5\n\
+                 cost = 2 ; {0}:4\n\
+                 ammo = 10 ; {1}:2, patched by {1}:2\n\n",
+                system.display(),
+                patch.display()
+            )
+        );
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -1,6 +1,6 @@
 #[cfg(desktop)]
 fn main() -> anyhow::Result<()> {
-    xray_oxide_engine::entry_point()
+    xray_oxide_engine::entry_point(xray_oxide_engine::args::StartupConfig::from_env()?)
 }
 
 #[cfg(not(desktop))]
@@ -6,8 +6,11 @@ use winit::{dpi::PhysicalSize, window::Window};
 use xray_oxide_core::filesystem::Filesystem;
 use xray_oxide_render::Renderer;
 use crate::shaders::ShaderModule;
+use crate::texture::TextureManager;
 
+pub mod mesh;
 pub mod shaders;
+pub mod texture;
 
 #[derive(Debug, Error)]
 pub enum RendererError {
@@ -79,10 +82,23 @@ pub struct WgpuRenderer {
     dynamic_transforms: DynamicTransforms,
     dynamic_transforms_buffer: wgpu::Buffer,
     dynamic_transforms_bind_group: wgpu::BindGroup,
+    bind_group_layout: wgpu::BindGroupLayout,
+    diffuse_sampler: wgpu::Sampler,
+    diffuse_texture: Option<String>,
+    textures: TextureManager,
 }
 
 impl WgpuRenderer {
-    pub async fn new(window: Window, filesystem: Arc<Filesystem>) -> anyhow::Result<WgpuRenderer> {
+    /// Creates a renderer for `window`. Without `vsync`, frames are presented
+    /// as soon as they are ready if the surface supports it.
+    ///
+    /// `texture_lod` is how many of the largest mip levels to skip when loading textures.
+    pub async fn new(
+        window: Window,
+        filesystem: Arc<Filesystem>,
+        vsync: bool,
+        texture_lod: u32,
+    ) -> anyhow::Result<WgpuRenderer> {
         let size = window.inner_size();
 
         let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
@@ -103,11 +119,14 @@ impl WgpuRenderer {
             .await
             .ok_or(RendererError::NoGPUFound)?;
 
+        // Block compressed textures are decoded on the CPU when this isn't supported
+        let features = adapter.features() & wgpu::Features::TEXTURE_COMPRESSION_BC;
+
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
                     label: None,
-                    features: wgpu::Features::empty(),
+                    features,
                     limits: wgpu::Limits::default(),
                 },
                 None,
@@ -123,53 +142,28 @@ impl WgpuRenderer {
             .find(|format| format.is_srgb())
             .unwrap_or(surface_caps.formats[0]);
 
+        let present_mode = if vsync {
+            wgpu::PresentMode::Fifo
+        } else {
+            [wgpu::PresentMode::Immediate, wgpu::PresentMode::Mailbox]
+                .into_iter()
+                .find(|mode| surface_caps.present_modes.contains(mode))
+                .unwrap_or(wgpu::PresentMode::Fifo)
+        };
+
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
             format: surface_format,
             width: size.width,
             height: size.height,
-            present_mode: surface_caps.present_modes[0],
+            present_mode,
             alpha_mode: surface_caps.alpha_modes[0],
             view_formats: vec![],
         };
 
         surface.configure(&device, &config);
 
-        let texture_size = wgpu::Extent3d {
-            width: 1,
-            height: 1,
-            depth_or_array_layers: 1,
-        };
-
-        let diffuse_texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("texture"),
-            size: texture_size,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-            view_formats: &[],
-        });
-
-        queue.write_texture(
-            wgpu::ImageCopyTexture {
-                texture: &diffuse_texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
-            },
-            &[0, 0, 0, 0],
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: Some(4),
-                rows_per_image: Some(1),
-            },
-            texture_size,
-        );
-
-        let diffuse_texture_view =
-            diffuse_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let textures = TextureManager::new(&device, &queue, filesystem.clone(), texture_lod)?;
 
         let diffuse_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             address_mode_u: wgpu::AddressMode::ClampToEdge,
@@ -223,24 +217,13 @@ impl WgpuRenderer {
                 ],
             });
 
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("bind_group"),
-            layout: &bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: dynamic_transforms_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 6,
-                    resource: wgpu::BindingResource::Sampler(&diffuse_sampler),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 7,
-                    resource: wgpu::BindingResource::TextureView(&diffuse_texture_view),
-                },
-            ],
-        });
+        let bind_group = create_bind_group(
+            &device,
+            &bind_group_layout,
+            &dynamic_transforms_buffer,
+            &diffuse_sampler,
+            &textures.placeholder().view,
+        );
 
         let vertex_shader_module =
             ShaderModule::create_vertex(&device, &filesystem, "stub_default")?;
@@ -289,10 +272,59 @@ impl WgpuRenderer {
             dynamic_transforms,
             dynamic_transforms_buffer,
             dynamic_transforms_bind_group: bind_group,
+            bind_group_layout,
+            diffuse_sampler,
+            diffuse_texture: None,
+            textures,
         })
     }
 
+    /// Sets the texture sampled at binding 7, by its name under `$game_textures$`.
+    ///
+    /// The placeholder is bound until it's loaded.
+    pub fn set_diffuse_texture(&mut self, name: &str) -> anyhow::Result<()> {
+        self.diffuse_texture = Some(texture::normalize_name(name));
+        self.bind_diffuse_texture()
+    }
+
+    fn bind_diffuse_texture(&mut self) -> anyhow::Result<()> {
+        let texture = match &self.diffuse_texture {
+            Some(name) => self.textures.get(name)?,
+            None => self.textures.placeholder().clone(),
+        };
+
+        let view = if texture.view_dimension == wgpu::TextureViewDimension::D2 {
+            &texture.view
+        } else {
+            log::warn!(
+                "Can't bind {:?} texture {:?} as a 2D one",
+                texture.view_dimension,
+                self.diffuse_texture
+            );
+            &self.textures.placeholder().view
+        };
+
+        self.dynamic_transforms_bind_group = create_bind_group(
+            &self.device,
+            &self.bind_group_layout,
+            &self.dynamic_transforms_buffer,
+            &self.diffuse_sampler,
+            view,
+        );
+
+        Ok(())
+    }
+
     fn render_impl(&mut self) -> anyhow::Result<()> {
+        let loaded = self.textures.update(&self.device, &self.queue);
+        if self
+            .diffuse_texture
+            .as_ref()
+            .is_some_and(|name| loaded.contains(name))
+        {
+            self.bind_diffuse_texture()?;
+        }
+
         let output = self.surface.get_current_texture()?;
 
         let view = output
@@ -339,6 +371,33 @@ impl WgpuRenderer {
     }
 }
 
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    dynamic_transforms_buffer: &wgpu::Buffer,
+    sampler: &wgpu::Sampler,
+    view: &wgpu::TextureView,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("bind_group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: dynamic_transforms_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 6,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 7,
+                resource: wgpu::BindingResource::TextureView(view),
+            },
+        ],
+    })
+}
+
 fn create_render_pipeline(
     device: &wgpu::Device,
     label: Option<&str>,
@@ -0,0 +1,58 @@
+use wgpu::util::DeviceExt;
+use xray_oxide_core::ogf;
+
+use crate::Vertex;
+
+/// A mesh in GPU buffers, in the layout of the render pipeline.
+pub struct Mesh {
+    pub vertex_buffer: wgpu::Buffer,
+    pub index_buffer: wgpu::Buffer,
+    pub index_count: u32,
+}
+
+impl Mesh {
+    pub fn new(device: &wgpu::Device, mesh: &ogf::Mesh, label: Option<&str>) -> Mesh {
+        let vertices: Vec<Vertex> = mesh.vertices.iter().map(convert_vertex).collect();
+
+        // Buffer copies must be a multiple of 4 bytes
+        let mut indices = mesh.indices.clone();
+        if !indices.len().is_multiple_of(2) {
+            indices.push(0);
+        }
+
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label,
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label,
+            contents: bytemuck::cast_slice(&indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        Mesh {
+            vertex_buffer,
+            index_buffer,
+            index_count: mesh.indices.len() as u32,
+        }
+    }
+
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+        render_pass.draw_indexed(0..self.index_count, 0, 0..1);
+    }
+}
+
+fn convert_vertex(vertex: &ogf::MeshVertex) -> Vertex {
+    let [x, y, z] = vertex.position;
+    let [a, r, g, b] = vertex.color.to_be_bytes().map(|c| c as f32 / 255.0);
+
+    Vertex {
+        position: [x, y, z, 1.0],
+        tex_coords: vertex.uv,
+        color: [r, g, b, a],
+    }
+}
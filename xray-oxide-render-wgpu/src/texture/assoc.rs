@@ -0,0 +1,312 @@
+use std::collections::HashMap;
+
+use xray_oxide_core::{filesystem::Filesystem, ltx::Ltx};
+
+use super::{
+    normalize_name,
+    thm::{BumpMode, Material, TextureDescription},
+};
+
+/// Detail textures and bump maps of textures without a `.thm`, as SoC ships them.
+pub const TEXTURES_LTX: &str = "$game_textures$\\textures.ltx";
+
+/// A texture drawn over another one up close.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DetailTexture {
+    pub name: String,
+    /// How many times it repeats over the texture it details.
+    pub scale: f32,
+    /// Whether it details the diffuse texture.
+    pub diffuse: bool,
+    /// Whether it details the bump map.
+    pub bump: bool,
+}
+
+/// What a diffuse texture is drawn with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextureInfo {
+    pub bump: Option<String>,
+    /// `bump#`, which holds what compressing the bump map lost, and its height.
+    pub bump_x: Option<String>,
+    pub detail: Option<DetailTexture>,
+    pub material: Material,
+    /// How much of the next material is blended in.
+    pub material_weight: f32,
+    pub steep_parallax: bool,
+}
+
+/// The `[specification]` of a texture, or what its `.thm` says instead.
+#[derive(Debug, Clone, PartialEq)]
+struct Specification {
+    bump: Option<String>,
+    /// The material index, with the weight as the fraction, like XRay stores it.
+    material: f32,
+    steep_parallax: bool,
+}
+
+/// Finds the bump map, detail texture and material of diffuse textures, like
+/// XRay's `CTextureDescrMngr`.
+///
+/// A texture's `.thm` wins over `textures.ltx`, though its detail texture is
+/// only used if the `.thm` enables one.
+pub struct TextureAssociations {
+    associations: HashMap<String, DetailTexture>,
+    specifications: HashMap<String, Specification>,
+}
+
+impl TextureAssociations {
+    /// Reads `textures.ltx`, if there is one.
+    pub fn load(filesystem: &Filesystem) -> anyhow::Result<TextureAssociations> {
+        let path = filesystem.update_path(TEXTURES_LTX)?;
+
+        let ltx = if filesystem.exists(&path) {
+            Some(Ltx::load(filesystem, path)?)
+        } else {
+            None
+        };
+
+        Ok(TextureAssociations::new(ltx.as_ref()))
+    }
+
+    pub fn new(textures_ltx: Option<&Ltx>) -> TextureAssociations {
+        let mut associations = HashMap::new();
+        let mut specifications = HashMap::new();
+
+        let section = |name| textures_ltx.and_then(|ltx| ltx.section(name));
+
+        for item in section("association").iter().flat_map(|s| s.items()) {
+            let value = item.value().unwrap_or_default();
+
+            match parse_association(value) {
+                Some(detail) => {
+                    associations.insert(normalize_name(item.key()), detail);
+                }
+                None => log::warn!("{}: invalid association {value:?}", item.location()),
+            }
+        }
+
+        for item in section("specification").iter().flat_map(|s| s.items()) {
+            let value = item.value().unwrap_or_default();
+
+            match parse_specification(value) {
+                Some(specification) => {
+                    specifications.insert(normalize_name(item.key()), specification);
+                }
+                None => log::warn!("{}: invalid specification {value:?}", item.location()),
+            }
+        }
+
+        TextureAssociations {
+            associations,
+            specifications,
+        }
+    }
+
+    /// Resolves what a diffuse texture is drawn with. `description` is its
+    /// `.thm`, as [`TextureManager::description`](super::TextureManager::description)
+    /// has it once the texture is loaded.
+    pub fn resolve(&self, name: &str, description: Option<&TextureDescription>) -> TextureInfo {
+        let name = normalize_name(name);
+
+        let detail = match description {
+            Some(description)
+                if description.has_diffuse_detail() || description.has_bump_detail() =>
+            {
+                description
+                    .detail_name
+                    .as_ref()
+                    .map(|detail| DetailTexture {
+                        name: normalize_name(detail),
+                        scale: description.detail_scale,
+                        diffuse: description.has_diffuse_detail(),
+                        bump: description.has_bump_detail(),
+                    })
+            }
+            _ => self.associations.get(&name).cloned(),
+        };
+
+        let specification = match description {
+            Some(description) => Some(Specification {
+                bump: match description.bump_mode {
+                    BumpMode::Use | BumpMode::UseParallax => description.bump_name.clone(),
+                    BumpMode::None => None,
+                },
+                material: description.material as u32 as f32 + description.material_weight,
+                steep_parallax: description.bump_mode == BumpMode::UseParallax,
+            }),
+            None => self.specifications.get(&name).cloned(),
+        };
+
+        // XRay falls back to Blinn-Phong for textures it knows nothing about
+        let (bump, material, steep_parallax) = match specification {
+            Some(specification) => (
+                specification.bump.map(|bump| normalize_name(&bump)),
+                specification.material,
+                specification.steep_parallax,
+            ),
+            None => (None, 1.0, false),
+        };
+
+        TextureInfo {
+            bump_x: bump.as_ref().map(|bump| format!("{bump}#")),
+            bump,
+            detail,
+            material: Material::from_index(material.trunc() as u32),
+            material_weight: material.fract(),
+            steep_parallax,
+        }
+    }
+}
+
+/// Parses `detail\name, scale` with an optional `usage[diffuse|bump|diffuse_or_bump]`,
+/// detailing the diffuse texture without one.
+fn parse_association(value: &str) -> Option<DetailTexture> {
+    let mut parts = value.split(',').map(str::trim);
+
+    let name = parts.next().filter(|name| !name.is_empty())?;
+    let scale = parts.next()?.parse().ok()?;
+
+    let (diffuse, bump) = if value.contains("usage[diffuse_or_bump]") {
+        (true, true)
+    } else if value.contains("usage[bump]") {
+        (false, true)
+    } else {
+        (true, false)
+    };
+
+    Some(DetailTexture {
+        name: normalize_name(name),
+        scale,
+        diffuse,
+        bump,
+    })
+}
+
+/// Parses `bump_mode[use:name], material[index.weight]` with an optional
+/// `parallax[yes|no]`. The bump mode can also be `use_parallax:name`, or
+/// anything else for no bump map.
+fn parse_specification(value: &str) -> Option<Specification> {
+    let (bump_mode, rest) = value.trim().strip_prefix("bump_mode[")?.split_once(']')?;
+    let (material, rest) = rest
+        .trim_start_matches([',', ' ', '\t'])
+        .strip_prefix("material[")?
+        .split_once(']')?;
+    let parallax = rest
+        .trim_start_matches([',', ' ', '\t'])
+        .strip_prefix("parallax[")
+        .and_then(|parallax| parallax.split_once(']'))
+        .is_some_and(|(parallax, _)| parallax.trim() == "yes");
+
+    let (bump, steep_parallax) = match bump_mode.split_once(':') {
+        Some(("use", bump)) => (Some(bump), parallax),
+        Some(("use_parallax", bump)) => (Some(bump), true),
+        _ => (None, false),
+    };
+
+    Some(Specification {
+        bump: bump.filter(|bump| !bump.is_empty()).map(str::to_owned),
+        material: material.trim().parse().ok()?,
+        steep_parallax,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse_association("detail\\detail_grnd_earth, 4.5, usage[diffuse_or_bump]"),
+            Some(DetailTexture {
+                name: "detail\\detail_grnd_earth".to_owned(),
+                scale: 4.5,
+                diffuse: true,
+                bump: true,
+            })
+        );
+        assert_eq!(
+            parse_association("detail\\detail_grnd_asphalt,3").map(|d| (d.diffuse, d.bump)),
+            Some((true, false))
+        );
+        assert_eq!(parse_association("detail\\detail_grnd_asphalt"), None);
+
+        assert_eq!(
+            parse_specification("bump_mode[use:act\\act_stalker_bump], material[2.25]"),
+            Some(Specification {
+                bump: Some("act\\act_stalker_bump".to_owned()),
+                material: 2.25,
+                steep_parallax: false,
+            })
+        );
+        assert_eq!(
+            parse_specification("bump_mode[none], material[1]").map(|s| s.bump),
+            Some(None)
+        );
+        assert_eq!(
+            parse_specification("bump_mode[use:act\\act_stalker_bump], material[1], parallax[yes]")
+                .map(|s| s.steep_parallax),
+            Some(true)
+        );
+        assert_eq!(
+            parse_specification("bump_mode[use_parallax:act\\act_stalker_bump], material[1]")
+                .map(|s| (s.bump, s.steep_parallax)),
+            Some((Some("act\\act_stalker_bump".to_owned()), true))
+        );
+        assert_eq!(parse_specification("material[1]"), None);
+    }
+
+    #[test]
+    fn test_resolve() {
+        let ltx = Ltx::parse(
+            "textures.ltx",
+            "[association]\n\
+             terrain\\grass = detail\\detail_grnd_grass, 4\n\
+             wood\\wood_plank = detail\\detail_wood, 2, usage[bump]\n\
+             [specification]\n\
+             terrain\\grass = bump_mode[use:terrain\\grass_bump], material[2.25], parallax[yes]\n\
+             wood\\wood_plank = bump_mode[none], material[0.5]\n",
+        )
+        .unwrap();
+        let associations = TextureAssociations::new(Some(&ltx));
+
+        assert_eq!(
+            associations.resolve("Terrain/Grass.dds", None),
+            TextureInfo {
+                bump: Some("terrain\\grass_bump".to_owned()),
+                bump_x: Some("terrain\\grass_bump#".to_owned()),
+                detail: Some(DetailTexture {
+                    name: "detail\\detail_grnd_grass".to_owned(),
+                    scale: 4.0,
+                    diffuse: true,
+                    bump: false,
+                }),
+                material: Material::PhongMetal,
+                material_weight: 0.25,
+                steep_parallax: true,
+            }
+        );
+
+        // A .thm without a detail texture keeps the association, but replaces the specification
+        let description = TextureDescription {
+            bump_mode: BumpMode::UseParallax,
+            bump_name: Some("wood\\wood_plank_bump".to_owned()),
+            material: Material::BlinnPhong,
+            material_weight: 0.0,
+            ..Default::default()
+        };
+        let info = associations.resolve("wood\\wood_plank", Some(&description));
+        assert_eq!(info.bump.as_deref(), Some("wood\\wood_plank_bump"));
+        assert!(info.steep_parallax);
+        assert_eq!(info.material, Material::BlinnPhong);
+        assert_eq!(
+            info.detail
+                .map(|detail| (detail.name, detail.diffuse, detail.bump)),
+            Some(("detail\\detail_wood".to_owned(), false, true))
+        );
+
+        let info = associations.resolve("unknown", None);
+        assert_eq!((info.bump, info.detail), (None, None));
+        assert_eq!(info.material, Material::BlinnPhong);
+    }
+}
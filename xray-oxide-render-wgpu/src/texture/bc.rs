@@ -0,0 +1,433 @@
+//! CPU decoding of block compressed surfaces, for devices without BC support.
+
+use super::dds::DdsFormat;
+
+/// Decodes a `width` x `height` surface to RGBA8.
+///
+/// BC4 and BC5 decode to red and red-green like the GPU samples them.
+pub(crate) fn decode(format: DdsFormat, data: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let (width, height) = (width as usize, height as usize);
+    let block_size = format.block_size().unwrap();
+    let blocks_wide = width.div_ceil(4);
+
+    let mut pixels = vec![0u8; width * height * 4];
+
+    for (idx, block) in data.chunks_exact(block_size).enumerate() {
+        let (block_x, block_y) = ((idx % blocks_wide) * 4, (idx / blocks_wide) * 4);
+
+        if block_y >= height {
+            break;
+        }
+
+        let texels = match format {
+            DdsFormat::Bc1 => decode_bc1(block),
+            DdsFormat::Bc2 => decode_bc2(block),
+            DdsFormat::Bc3 => decode_bc3(block),
+            DdsFormat::Bc4 => decode_bc4(block),
+            DdsFormat::Bc5 => decode_bc5(block),
+            DdsFormat::Bc7 => decode_bc7(block),
+            _ => unreachable!(),
+        };
+
+        for (texel_idx, texel) in texels.iter().enumerate() {
+            let (x, y) = (block_x + texel_idx % 4, block_y + texel_idx / 4);
+
+            if x < width && y < height {
+                let offset = (y * width + x) * 4;
+                pixels[offset..offset + 4].copy_from_slice(texel);
+            }
+        }
+    }
+
+    pixels
+}
+
+type Block = [[u8; 4]; 16];
+
+fn rgb565(color: u16) -> [u8; 4] {
+    let r = ((color >> 11) & 0x1F) as u32;
+    let g = ((color >> 5) & 0x3F) as u32;
+    let b = (color & 0x1F) as u32;
+
+    [
+        ((r << 3) | (r >> 2)) as u8,
+        ((g << 2) | (g >> 4)) as u8,
+        ((b << 3) | (b >> 2)) as u8,
+        255,
+    ]
+}
+
+/// The colour half of BC1, BC2 and BC3 blocks. Only BC1 has the 3 colour
+/// mode with transparent black.
+fn decode_color(block: &[u8], allow_transparent: bool) -> Block {
+    let color0 = u16::from_le_bytes([block[0], block[1]]);
+    let color1 = u16::from_le_bytes([block[2], block[3]]);
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+
+    let (c0, c1) = (rgb565(color0), rgb565(color1));
+    let mix = |w0: u32, w1: u32, d: u32| {
+        let mut color = [0u8; 4];
+        for i in 0..3 {
+            color[i] = ((c0[i] as u32 * w0 + c1[i] as u32 * w1) / d) as u8;
+        }
+        color[3] = 255;
+        color
+    };
+
+    let palette = if color0 > color1 || !allow_transparent {
+        [c0, c1, mix(2, 1, 3), mix(1, 2, 3)]
+    } else {
+        [c0, c1, mix(1, 1, 2), [0, 0, 0, 0]]
+    };
+
+    std::array::from_fn(|i| palette[((indices >> (i * 2)) & 0x3) as usize])
+}
+
+/// A BC3 alpha block or a BC4/BC5 channel.
+fn decode_channel(block: &[u8]) -> [u8; 16] {
+    let (a0, a1) = (block[0] as u32, block[1] as u32);
+    let indices = u64::from_le_bytes([
+        block[2], block[3], block[4], block[5], block[6], block[7], 0, 0,
+    ]);
+
+    let palette: [u8; 8] = std::array::from_fn(|i| {
+        let i = i as u32;
+        match i {
+            0 => a0 as u8,
+            1 => a1 as u8,
+            _ if a0 > a1 => ((a0 * (8 - i) + a1 * (i - 1)) / 7) as u8,
+            6 => 0,
+            7 => 255,
+            _ => ((a0 * (6 - i) + a1 * (i - 1)) / 5) as u8,
+        }
+    });
+
+    std::array::from_fn(|i| palette[((indices >> (i * 3)) & 0x7) as usize])
+}
+
+fn decode_bc1(block: &[u8]) -> Block {
+    decode_color(block, true)
+}
+
+fn decode_bc2(block: &[u8]) -> Block {
+    let mut texels = decode_color(&block[8..], false);
+
+    for (i, texel) in texels.iter_mut().enumerate() {
+        let alpha = (block[i / 2] >> ((i % 2) * 4)) & 0xF;
+        texel[3] = alpha | (alpha << 4);
+    }
+
+    texels
+}
+
+fn decode_bc3(block: &[u8]) -> Block {
+    let mut texels = decode_color(&block[8..], false);
+
+    for (texel, alpha) in texels.iter_mut().zip(decode_channel(block)) {
+        texel[3] = alpha;
+    }
+
+    texels
+}
+
+fn decode_bc4(block: &[u8]) -> Block {
+    decode_channel(block).map(|red| [red, 0, 0, 255])
+}
+
+fn decode_bc5(block: &[u8]) -> Block {
+    let red = decode_channel(block);
+    let green = decode_channel(&block[8..]);
+
+    std::array::from_fn(|i| [red[i], green[i], 0, 255])
+}
+
+struct Bc7Mode {
+    subsets: usize,
+    partition_bits: u32,
+    rotation_bits: u32,
+    index_selection_bits: u32,
+    color_bits: u32,
+    alpha_bits: u32,
+    endpoint_pbits: bool,
+    shared_pbits: bool,
+    index_bits: u32,
+    index_bits2: u32,
+}
+
+#[rustfmt::skip]
+const BC7_MODES: [Bc7Mode; 8] = [
+    Bc7Mode { subsets: 3, partition_bits: 4, rotation_bits: 0, index_selection_bits: 0, color_bits: 4, alpha_bits: 0, endpoint_pbits: true, shared_pbits: false, index_bits: 3, index_bits2: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 6, alpha_bits: 0, endpoint_pbits: false, shared_pbits: true, index_bits: 3, index_bits2: 0 },
+    Bc7Mode { subsets: 3, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 5, alpha_bits: 0, endpoint_pbits: false, shared_pbits: false, index_bits: 2, index_bits2: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 7, alpha_bits: 0, endpoint_pbits: true, shared_pbits: false, index_bits: 2, index_bits2: 0 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 2, index_selection_bits: 1, color_bits: 5, alpha_bits: 6, endpoint_pbits: false, shared_pbits: false, index_bits: 2, index_bits2: 3 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 2, index_selection_bits: 0, color_bits: 7, alpha_bits: 8, endpoint_pbits: false, shared_pbits: false, index_bits: 2, index_bits2: 2 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 0, index_selection_bits: 0, color_bits: 7, alpha_bits: 7, endpoint_pbits: true, shared_pbits: false, index_bits: 4, index_bits2: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 5, alpha_bits: 5, endpoint_pbits: true, shared_pbits: false, index_bits: 2, index_bits2: 0 },
+];
+
+/// The texels in subset 1 of every 2 subset partition, one bit per texel.
+#[rustfmt::skip]
+const BC7_PARTITIONS2: [u16; 64] = [
+    0xCCCC, 0x8888, 0xEEEE, 0xECC8, 0xC880, 0xFEEC, 0xFEC8, 0xEC80,
+    0xC800, 0xFFEC, 0xFE80, 0xE800, 0xFFE8, 0xFF00, 0xFFF0, 0xF000,
+    0xF710, 0x008E, 0x7100, 0x08CE, 0x008C, 0x7310, 0x3100, 0x8CCE,
+    0x088C, 0x3110, 0x6666, 0x366C, 0x17E8, 0x0FF0, 0x718E, 0x399C,
+    0xAAAA, 0xF0F0, 0x5A5A, 0x33CC, 0x3C3C, 0x55AA, 0x9696, 0xA55A,
+    0x73CE, 0x13C8, 0x324C, 0x3BDC, 0x6996, 0xC33C, 0x9966, 0x0660,
+    0x0272, 0x04E4, 0x4E40, 0x2720, 0xC936, 0x936C, 0x39C6, 0x639C,
+    0x9336, 0x9CC6, 0x817E, 0xE718, 0xCCF0, 0x0FCC, 0x7744, 0xEE22,
+];
+
+/// The subset of every texel of every 3 subset partition, two bits per texel.
+#[rustfmt::skip]
+const BC7_PARTITIONS3: [u32; 64] = [
+    0xAA685050, 0x6A5A5040, 0x5A5A4200, 0x5450A0A8, 0xA5A50000, 0xA0A05050, 0x5555A0A0, 0x5A5A5050,
+    0xAA550000, 0xAA555500, 0xAAAA5500, 0x90909090, 0x94949494, 0xA4A4A4A4, 0xA9A59450, 0x2A0A4250,
+    0xA5945040, 0x0A425054, 0xA5A5A500, 0x55A0A0A0, 0xA8A85454, 0x6A6A4040, 0xA4A45000, 0x1A1A0500,
+    0x0050A4A4, 0xAAA59090, 0x14696914, 0x69691400, 0xA08585A0, 0xAA821414, 0x50A4A450, 0x6A5A0200,
+    0xA9A58000, 0x5090A0A8, 0xA8A09050, 0x24242424, 0x00AA5500, 0x24924924, 0x24499224, 0x50A50A50,
+    0x500AA550, 0xAAAA4444, 0x66660000, 0xA5A0A5A0, 0x50A050A0, 0x69286928, 0x44AAAA44, 0x66666600,
+    0xAA444444, 0x54A854A8, 0x95809580, 0x96969600, 0xA85454A8, 0x80959580, 0xAA141414, 0x96960000,
+    0xAAAA1414, 0xA05050A0, 0xA0A5A5A0, 0x96000000, 0x40804080, 0xA9A8A9A8, 0xAAAAAA44, 0x2A4A5254,
+];
+
+/// The anchor texel of the second subset of 2 subset partitions.
+#[rustfmt::skip]
+const BC7_ANCHORS2: [usize; 64] = [
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15,
+    15,  2,  8,  2,  2,  8,  8, 15,  2,  8,  2,  2,  8,  8,  2,  2,
+    15, 15,  6,  8,  2,  8, 15, 15,  2,  8,  2,  2,  2, 15, 15,  6,
+     6,  2,  6,  8, 15, 15,  2,  2, 15, 15, 15, 15, 15,  2,  2, 15,
+];
+
+/// The anchor texels of the second and third subsets of 3 subset partitions.
+#[rustfmt::skip]
+const BC7_ANCHORS3: [[usize; 2]; 64] = [
+    [3, 15], [3, 8], [15, 8], [15, 3], [8, 15], [3, 15], [15, 3], [15, 8],
+    [8, 15], [8, 15], [6, 15], [6, 15], [6, 15], [5, 15], [3, 15], [3, 8],
+    [3, 15], [3, 8], [8, 15], [15, 3], [3, 15], [3, 8], [6, 15], [10, 8],
+    [5, 3], [8, 15], [8, 6], [6, 10], [8, 15], [5, 15], [15, 10], [15, 8],
+    [8, 15], [15, 3], [3, 15], [5, 10], [6, 10], [10, 8], [8, 9], [15, 10],
+    [15, 6], [3, 15], [15, 8], [5, 15], [15, 3], [15, 6], [15, 6], [15, 8],
+    [3, 15], [15, 3], [5, 15], [5, 15], [5, 15], [8, 15], [5, 15], [10, 15],
+    [5, 15], [10, 15], [8, 15], [13, 15], [15, 3], [12, 15], [3, 15], [3, 8],
+];
+
+const BC7_WEIGHTS2: [u32; 4] = [0, 21, 43, 64];
+const BC7_WEIGHTS3: [u32; 8] = [0, 9, 18, 27, 37, 46, 55, 64];
+const BC7_WEIGHTS4: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl BitReader<'_> {
+    fn read(&mut self, bits: u32) -> u32 {
+        let mut value = 0;
+
+        for bit in 0..bits {
+            let byte = self.data[self.pos / 8];
+            value |= (((byte >> (self.pos % 8)) & 1) as u32) << bit;
+            self.pos += 1;
+        }
+
+        value
+    }
+}
+
+fn bc7_interpolate(e0: u32, e1: u32, index: u32, index_bits: u32) -> u8 {
+    let weight = match index_bits {
+        2 => BC7_WEIGHTS2[index as usize],
+        3 => BC7_WEIGHTS3[index as usize],
+        _ => BC7_WEIGHTS4[index as usize],
+    };
+
+    (((64 - weight) * e0 + weight * e1 + 32) >> 6) as u8
+}
+
+fn decode_bc7(block: &[u8]) -> Block {
+    let mut bits = BitReader {
+        data: block,
+        pos: 0,
+    };
+
+    let Some(mode_idx) = (0..8).find(|_| bits.read(1) == 1) else {
+        // Reserved mode, decoders return transparent black
+        return [[0; 4]; 16];
+    };
+    let mode = &BC7_MODES[mode_idx];
+
+    let partition = bits.read(mode.partition_bits) as usize;
+    let rotation = bits.read(mode.rotation_bits);
+    let index_selection = bits.read(mode.index_selection_bits);
+
+    // endpoints[subset * 2 + endpoint][channel]
+    let mut endpoints = [[0u32; 4]; 6];
+    let endpoint_count = mode.subsets * 2;
+
+    for channel in 0..3 {
+        for endpoint in endpoints.iter_mut().take(endpoint_count) {
+            endpoint[channel] = bits.read(mode.color_bits);
+        }
+    }
+
+    for endpoint in endpoints.iter_mut().take(endpoint_count) {
+        endpoint[3] = bits.read(mode.alpha_bits);
+    }
+
+    let (mut color_bits, mut alpha_bits) = (mode.color_bits, mode.alpha_bits);
+
+    if mode.endpoint_pbits || mode.shared_pbits {
+        let pbits = if mode.endpoint_pbits {
+            (0..endpoint_count)
+                .map(|_| bits.read(1))
+                .collect::<Vec<_>>()
+        } else {
+            (0..mode.subsets)
+                .flat_map(|_| [bits.read(1); 2])
+                .collect::<Vec<_>>()
+        };
+
+        for (endpoint, pbit) in endpoints.iter_mut().zip(pbits) {
+            for (channel, value) in endpoint.iter_mut().enumerate() {
+                if channel < 3 || mode.alpha_bits > 0 {
+                    *value = (*value << 1) | pbit;
+                }
+            }
+        }
+
+        color_bits += 1;
+        if alpha_bits > 0 {
+            alpha_bits += 1;
+        }
+    }
+
+    for endpoint in endpoints.iter_mut().take(endpoint_count) {
+        for (channel, value) in endpoint.iter_mut().enumerate() {
+            let bits = if channel < 3 { color_bits } else { alpha_bits };
+
+            *value = if bits == 0 {
+                255
+            } else {
+                let value = *value << (8 - bits);
+                value | (value >> bits)
+            };
+        }
+    }
+
+    let subset_of = |texel: usize| match mode.subsets {
+        2 => ((BC7_PARTITIONS2[partition] >> texel) & 1) as usize,
+        3 => ((BC7_PARTITIONS3[partition] >> (texel * 2)) & 3) as usize,
+        _ => 0,
+    };
+
+    let is_anchor = |texel: usize| {
+        texel == 0
+            || match mode.subsets {
+                2 => texel == BC7_ANCHORS2[partition],
+                3 => BC7_ANCHORS3[partition].contains(&texel),
+                _ => false,
+            }
+    };
+
+    let mut read_indices = |index_bits: u32, anchors: &dyn Fn(usize) -> bool| -> [u32; 16] {
+        std::array::from_fn(|texel| {
+            bits.read(if anchors(texel) {
+                index_bits - 1
+            } else {
+                index_bits
+            })
+        })
+    };
+
+    let indices = read_indices(mode.index_bits, &is_anchor);
+    let indices2 = if mode.index_bits2 > 0 {
+        Some(read_indices(mode.index_bits2, &|texel| texel == 0))
+    } else {
+        None
+    };
+
+    std::array::from_fn(|texel| {
+        let subset = subset_of(texel);
+        let (e0, e1) = (endpoints[subset * 2], endpoints[subset * 2 + 1]);
+
+        // Modes 4 and 5 index colour and alpha separately, mode 4 can swap them
+        let ((color_index, color_bits), (alpha_index, alpha_bits)) = match indices2 {
+            Some(indices2) if index_selection == 1 => (
+                (indices2[texel], mode.index_bits2),
+                (indices[texel], mode.index_bits),
+            ),
+            Some(indices2) => (
+                (indices[texel], mode.index_bits),
+                (indices2[texel], mode.index_bits2),
+            ),
+            None => (
+                (indices[texel], mode.index_bits),
+                (indices[texel], mode.index_bits),
+            ),
+        };
+
+        let mut texel = [0u8; 4];
+        for channel in 0..3 {
+            texel[channel] = bc7_interpolate(e0[channel], e1[channel], color_index, color_bits);
+        }
+        texel[3] = bc7_interpolate(e0[3], e1[3], alpha_index, alpha_bits);
+
+        match rotation {
+            1 => texel.swap(0, 3),
+            2 => texel.swap(1, 3),
+            3 => texel.swap(2, 3),
+            _ => {}
+        }
+
+        texel
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        // Red to blue with every index, opaque
+        let bc1 = [0x00, 0xF8, 0x1F, 0x00, 0b11_10_01_00, 0, 0, 0];
+        let texels = decode(DdsFormat::Bc1, &bc1, 4, 4);
+        assert_eq!(
+            &texels[..16],
+            &[255, 0, 0, 255, 0, 0, 255, 255, 170, 0, 85, 255, 85, 0, 170, 255]
+        );
+
+        // Blue before red picks the 3 colour mode with transparent black
+        let bc1 = [0x1F, 0x00, 0x00, 0xF8, 0b11_10_01_00, 0, 0, 0];
+        let texels = decode(DdsFormat::Bc1, &bc1, 4, 4);
+        assert_eq!(&texels[8..16], &[127, 0, 127, 255, 0, 0, 0, 0]);
+
+        // A 2x2 surface still takes a whole block
+        let bc4 = [200, 100, 0b001_000, 0, 0, 0, 0, 0];
+        assert_eq!(
+            decode(DdsFormat::Bc4, &bc4, 2, 2),
+            [200, 0, 0, 255, 100, 0, 0, 255, 200, 0, 0, 255, 200, 0, 0, 255]
+        );
+
+        // Mode 6 with both endpoints at 127 and a set pbit is opaque white
+        let mut bc7 = [0u8; 16];
+        let mut pos = 0;
+        let mut write = |value: u64, bits: u32| {
+            for bit in 0..bits {
+                bc7[pos / 8] |= (((value >> bit) & 1) as u8) << (pos % 8);
+                pos += 1;
+            }
+        };
+        write(1 << 6, 7);
+        for _ in 0..8 {
+            write(127, 7);
+        }
+        write(1, 1);
+        write(1, 1);
+        let texels = decode(DdsFormat::Bc7, &bc7, 4, 4);
+        assert!(texels.iter().all(|&channel| channel == 255));
+    }
+}
@@ -0,0 +1,497 @@
+use std::{
+    io::{Cursor, Read},
+    path::Path,
+};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use thiserror::Error;
+use xray_oxide_core::filesystem::Filesystem;
+
+use super::bc;
+
+const DDS_MAGIC: u32 = u32::from_le_bytes(*b"DDS ");
+const DDS_HEADER_SIZE: u32 = 124;
+
+const DDSD_DEPTH: u32 = 0x80_0000;
+const DDSD_MIPMAPCOUNT: u32 = 0x2_0000;
+
+const DDPF_ALPHAPIXELS: u32 = 0x1;
+const DDPF_ALPHA: u32 = 0x2;
+const DDPF_FOURCC: u32 = 0x4;
+const DDPF_RGB: u32 = 0x40;
+const DDPF_LUMINANCE: u32 = 0x2_0000;
+
+const DDSCAPS2_CUBEMAP: u32 = 0x200;
+const DDSCAPS2_VOLUME: u32 = 0x20_0000;
+
+const D3D10_RESOURCE_DIMENSION_TEXTURE3D: u32 = 4;
+const D3D10_RESOURCE_MISC_TEXTURECUBE: u32 = 0x4;
+
+/// The pixel formats the games ship textures in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DdsFormat {
+    /// DXT1
+    Bc1,
+    /// DXT2 and DXT3
+    Bc2,
+    /// DXT4 and DXT5
+    Bc3,
+    /// ATI1
+    Bc4,
+    /// ATI2
+    Bc5,
+    Bc7,
+    Rgba8,
+    Bgra8,
+    /// `Bgra8` with an unused alpha byte.
+    Bgrx8,
+    Rgbx8,
+    Bgr8,
+    L8,
+    A8,
+    A8L8,
+    Rgba16Float,
+}
+
+impl DdsFormat {
+    /// The size of a 4x4 block for block compressed formats.
+    pub fn block_size(self) -> Option<usize> {
+        match self {
+            DdsFormat::Bc1 | DdsFormat::Bc4 => Some(8),
+            DdsFormat::Bc2 | DdsFormat::Bc3 | DdsFormat::Bc5 | DdsFormat::Bc7 => Some(16),
+            _ => None,
+        }
+    }
+
+    pub fn bytes_per_pixel(self) -> Option<usize> {
+        match self {
+            DdsFormat::L8 | DdsFormat::A8 => Some(1),
+            DdsFormat::A8L8 => Some(2),
+            DdsFormat::Bgr8 => Some(3),
+            DdsFormat::Rgba8 | DdsFormat::Bgra8 | DdsFormat::Bgrx8 | DdsFormat::Rgbx8 => Some(4),
+            DdsFormat::Rgba16Float => Some(8),
+            _ => None,
+        }
+    }
+
+    pub fn is_compressed(self) -> bool {
+        self.block_size().is_some()
+    }
+
+    /// The size of a `width` x `height` image.
+    pub fn surface_size(self, width: u32, height: u32) -> usize {
+        let (width, height) = (width as usize, height as usize);
+
+        match (self.block_size(), self.bytes_per_pixel()) {
+            (Some(block_size), _) => {
+                width.div_ceil(4).max(1) * height.div_ceil(4).max(1) * block_size
+            }
+            (_, Some(bytes_per_pixel)) => width * height * bytes_per_pixel,
+            _ => unreachable!(),
+        }
+    }
+
+    /// The `wgpu` format with the same layout, if there is one.
+    pub fn wgpu_format(self, srgb: bool) -> Option<wgpu::TextureFormat> {
+        use wgpu::TextureFormat as F;
+
+        Some(match (self, srgb) {
+            (DdsFormat::Bc1, false) => F::Bc1RgbaUnorm,
+            (DdsFormat::Bc1, true) => F::Bc1RgbaUnormSrgb,
+            (DdsFormat::Bc2, false) => F::Bc2RgbaUnorm,
+            (DdsFormat::Bc2, true) => F::Bc2RgbaUnormSrgb,
+            (DdsFormat::Bc3, false) => F::Bc3RgbaUnorm,
+            (DdsFormat::Bc3, true) => F::Bc3RgbaUnormSrgb,
+            (DdsFormat::Bc4, _) => F::Bc4RUnorm,
+            (DdsFormat::Bc5, _) => F::Bc5RgUnorm,
+            (DdsFormat::Bc7, false) => F::Bc7RgbaUnorm,
+            (DdsFormat::Bc7, true) => F::Bc7RgbaUnormSrgb,
+            (DdsFormat::Rgba8, false) => F::Rgba8Unorm,
+            (DdsFormat::Rgba8, true) => F::Rgba8UnormSrgb,
+            (DdsFormat::Bgra8, false) => F::Bgra8Unorm,
+            (DdsFormat::Bgra8, true) => F::Bgra8UnormSrgb,
+            (DdsFormat::Rgba16Float, _) => F::Rgba16Float,
+            _ => return None,
+        })
+    }
+}
+
+/// How the layers of an image are used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DdsKind {
+    Texture2d,
+    /// Six faces per layer, in the order +X, -X, +Y, -Y, +Z, -Z.
+    Cube,
+    /// A 3D texture, like a colour grading LUT.
+    Volume,
+}
+
+/// A DDS file, with the surfaces as they are stored: every layer with its
+/// mip chain, one after the other.
+#[derive(Debug, Clone)]
+pub struct DdsImage {
+    pub format: DdsFormat,
+    pub srgb: bool,
+    pub kind: DdsKind,
+    pub width: u32,
+    pub height: u32,
+    pub depth: u32,
+    pub mip_levels: u32,
+    /// Array layers, counting every cube face.
+    pub layers: u32,
+    pub data: Vec<u8>,
+}
+
+impl DdsImage {
+    pub fn load<P: AsRef<Path>>(filesystem: &Filesystem, path: P) -> anyhow::Result<DdsImage> {
+        let path = path.as_ref();
+        log::debug!("Loading texture {}", path.display());
+
+        Ok(DdsImage::parse(&filesystem.read(path)?)?)
+    }
+
+    /// Parses a file with a DX9 header, or a DX10 one for BC7 and texture arrays.
+    pub fn parse(bytes: &[u8]) -> Result<DdsImage, DdsError> {
+        let mut reader = Cursor::new(bytes);
+
+        if reader.read_u32::<LittleEndian>()? != DDS_MAGIC {
+            return Err(DdsError::InvalidMagic);
+        }
+
+        let mut header = [0u32; 31];
+        reader.read_u32_into::<LittleEndian>(&mut header)?;
+
+        let [size, flags, height, width, _pitch, depth, mip_levels, ..] = header;
+
+        if size != DDS_HEADER_SIZE {
+            return Err(DdsError::InvalidHeader);
+        }
+
+        let [pf_flags, four_cc, bit_count, r_mask, g_mask, b_mask, a_mask] =
+            header[19..26].try_into().unwrap();
+        let caps2 = header[27];
+
+        let mip_levels = if flags & DDSD_MIPMAPCOUNT != 0 {
+            mip_levels.max(1)
+        } else {
+            1
+        };
+
+        let (format, srgb, kind, depth, layers) = if four_cc == u32::from_le_bytes(*b"DX10") {
+            let [dxgi_format, dimension, misc_flag, array_size, _] = {
+                let mut header10 = [0u32; 5];
+                reader.read_u32_into::<LittleEndian>(&mut header10)?;
+                header10
+            };
+
+            let (format, srgb) =
+                dxgi_format_of(dxgi_format).ok_or(DdsError::UnsupportedDxgiFormat(dxgi_format))?;
+
+            if dimension == D3D10_RESOURCE_DIMENSION_TEXTURE3D {
+                (format, srgb, DdsKind::Volume, depth.max(1), 1)
+            } else if misc_flag & D3D10_RESOURCE_MISC_TEXTURECUBE != 0 {
+                (format, srgb, DdsKind::Cube, 1, array_size.max(1) * 6)
+            } else {
+                (format, srgb, DdsKind::Texture2d, 1, array_size.max(1))
+            }
+        } else {
+            let format = if pf_flags & DDPF_FOURCC != 0 {
+                d3d_format_of(four_cc).ok_or(DdsError::UnsupportedFourCc(four_cc))?
+            } else {
+                masked_format_of(pf_flags, bit_count, [r_mask, g_mask, b_mask, a_mask])
+                    .ok_or(DdsError::UnsupportedPixelFormat)?
+            };
+
+            if caps2 & DDSCAPS2_VOLUME != 0 && flags & DDSD_DEPTH != 0 {
+                (format, false, DdsKind::Volume, depth.max(1), 1)
+            } else if caps2 & DDSCAPS2_CUBEMAP != 0 {
+                (format, false, DdsKind::Cube, 1, 6)
+            } else {
+                (format, false, DdsKind::Texture2d, 1, 1)
+            }
+        };
+
+        let mut image = DdsImage {
+            format,
+            srgb,
+            kind,
+            width: width.max(1),
+            height: height.max(1),
+            depth,
+            mip_levels,
+            layers,
+            data: Vec::new(),
+        };
+
+        let len = image.layer_len() * image.layers as usize;
+        reader.take(len as u64).read_to_end(&mut image.data)?;
+
+        if image.data.len() < len {
+            return Err(DdsError::Truncated {
+                expected: len,
+                found: image.data.len(),
+            });
+        }
+
+        Ok(image)
+    }
+
+    /// The width, height and depth of a mip level.
+    pub fn mip_size(&self, mip: u32) -> (u32, u32, u32) {
+        (
+            (self.width >> mip).max(1),
+            (self.height >> mip).max(1),
+            (self.depth >> mip).max(1),
+        )
+    }
+
+    fn mip_len(&self, mip: u32) -> usize {
+        let (width, height, depth) = self.mip_size(mip);
+
+        self.format.surface_size(width, height) * depth as usize
+    }
+
+    fn layer_len(&self) -> usize {
+        (0..self.mip_levels).map(|mip| self.mip_len(mip)).sum()
+    }
+
+    /// The data of one mip level of one layer, with every slice of a volume.
+    pub fn level(&self, layer: u32, mip: u32) -> &[u8] {
+        let start = self.layer_len() * layer as usize
+            + (0..mip).map(|mip| self.mip_len(mip)).sum::<usize>();
+
+        &self.data[start..start + self.mip_len(mip)]
+    }
+
+    /// Drops the largest mip levels, always keeping the smallest one.
+    pub fn skip_mips(&mut self, count: u32) {
+        let count = count.min(self.mip_levels - 1);
+        if count == 0 {
+            return;
+        }
+
+        let skipped = DdsImage {
+            width: self.mip_size(count).0,
+            height: self.mip_size(count).1,
+            depth: self.mip_size(count).2,
+            mip_levels: self.mip_levels - count,
+            data: Vec::new(),
+            ..*self
+        };
+
+        let mut data = Vec::with_capacity(skipped.layer_len() * self.layers as usize);
+        for layer in 0..self.layers {
+            for mip in count..self.mip_levels {
+                data.extend_from_slice(self.level(layer, mip));
+            }
+        }
+
+        *self = DdsImage { data, ..skipped };
+    }
+
+    /// Converts every surface to `Rgba8`, for formats the GPU can't sample as they are.
+    pub fn decode_rgba8(&self) -> DdsImage {
+        if self.format == DdsFormat::Rgba8 {
+            return self.clone();
+        }
+
+        let mut data = Vec::with_capacity(self.data.len());
+
+        for layer in 0..self.layers {
+            for mip in 0..self.mip_levels {
+                let (width, height, depth) = self.mip_size(mip);
+                let level = self.level(layer, mip);
+                let slice_len = self.format.surface_size(width, height);
+
+                for slice in level.chunks_exact(slice_len).take(depth as usize) {
+                    data.extend(decode_surface(self.format, slice, width, height));
+                }
+            }
+        }
+
+        DdsImage {
+            format: DdsFormat::Rgba8,
+            data,
+            ..self.clone()
+        }
+    }
+}
+
+fn decode_surface(format: DdsFormat, data: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let pixels = |channels: fn(&[u8]) -> [u8; 4]| {
+        let bytes_per_pixel = format.bytes_per_pixel().unwrap();
+
+        data.chunks_exact(bytes_per_pixel)
+            .flat_map(channels)
+            .collect::<Vec<_>>()
+    };
+
+    match format {
+        DdsFormat::Bc1
+        | DdsFormat::Bc2
+        | DdsFormat::Bc3
+        | DdsFormat::Bc4
+        | DdsFormat::Bc5
+        | DdsFormat::Bc7 => bc::decode(format, data, width, height),
+        DdsFormat::Rgba8 => data.to_vec(),
+        DdsFormat::Bgra8 => pixels(|p| [p[2], p[1], p[0], p[3]]),
+        DdsFormat::Bgrx8 => pixels(|p| [p[2], p[1], p[0], 255]),
+        DdsFormat::Rgbx8 => pixels(|p| [p[0], p[1], p[2], 255]),
+        DdsFormat::Bgr8 => pixels(|p| [p[2], p[1], p[0], 255]),
+        DdsFormat::L8 => pixels(|p| [p[0], p[0], p[0], 255]),
+        DdsFormat::A8 => pixels(|p| [0, 0, 0, p[0]]),
+        DdsFormat::A8L8 => pixels(|p| [p[0], p[0], p[0], p[1]]),
+        DdsFormat::Rgba16Float => pixels(|p| {
+            let channel = |i: usize| {
+                let value = half_to_f32(u16::from_le_bytes([p[i * 2], p[i * 2 + 1]]));
+                (value.clamp(0.0, 1.0) * 255.0).round() as u8
+            };
+
+            [channel(0), channel(1), channel(2), channel(3)]
+        }),
+    }
+}
+
+fn half_to_f32(half: u16) -> f32 {
+    let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((half >> 10) & 0x1F) as i32;
+    let mantissa = (half & 0x3FF) as f32;
+
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        0x1F if mantissa == 0.0 => f32::INFINITY,
+        0x1F => f32::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+fn d3d_format_of(four_cc: u32) -> Option<DdsFormat> {
+    Some(match &four_cc.to_le_bytes() {
+        b"DXT1" => DdsFormat::Bc1,
+        b"DXT2" | b"DXT3" => DdsFormat::Bc2,
+        b"DXT4" | b"DXT5" => DdsFormat::Bc3,
+        b"ATI1" | b"BC4U" => DdsFormat::Bc4,
+        b"ATI2" | b"BC5U" => DdsFormat::Bc5,
+        // D3DFMT_A16B16G16R16F
+        _ if four_cc == 113 => DdsFormat::Rgba16Float,
+        _ => return None,
+    })
+}
+
+fn masked_format_of(flags: u32, bit_count: u32, masks: [u32; 4]) -> Option<DdsFormat> {
+    let has_alpha = flags & DDPF_ALPHAPIXELS != 0;
+
+    Some(match (bit_count, masks) {
+        (32, [0xFF, 0xFF00, 0xFF_0000, _]) if has_alpha => DdsFormat::Rgba8,
+        (32, [0xFF, 0xFF00, 0xFF_0000, _]) => DdsFormat::Rgbx8,
+        (32, [0xFF_0000, 0xFF00, 0xFF, _]) if has_alpha => DdsFormat::Bgra8,
+        (32, [0xFF_0000, 0xFF00, 0xFF, _]) => DdsFormat::Bgrx8,
+        (24, [0xFF_0000, 0xFF00, 0xFF, _]) if flags & DDPF_RGB != 0 => DdsFormat::Bgr8,
+        (8, _) if flags & DDPF_LUMINANCE != 0 => DdsFormat::L8,
+        (8, _) if flags & DDPF_ALPHA != 0 => DdsFormat::A8,
+        (16, [0xFF, _, _, 0xFF00]) if flags & DDPF_LUMINANCE != 0 => DdsFormat::A8L8,
+        _ => return None,
+    })
+}
+
+fn dxgi_format_of(format: u32) -> Option<(DdsFormat, bool)> {
+    Some(match format {
+        10 => (DdsFormat::Rgba16Float, false),
+        28 => (DdsFormat::Rgba8, false),
+        29 => (DdsFormat::Rgba8, true),
+        71 => (DdsFormat::Bc1, false),
+        72 => (DdsFormat::Bc1, true),
+        74 => (DdsFormat::Bc2, false),
+        75 => (DdsFormat::Bc2, true),
+        77 => (DdsFormat::Bc3, false),
+        78 => (DdsFormat::Bc3, true),
+        80 => (DdsFormat::Bc4, false),
+        83 => (DdsFormat::Bc5, false),
+        87 => (DdsFormat::Bgra8, false),
+        88 => (DdsFormat::Bgrx8, false),
+        91 => (DdsFormat::Bgra8, true),
+        98 => (DdsFormat::Bc7, false),
+        99 => (DdsFormat::Bc7, true),
+        _ => return None,
+    })
+}
+
+#[derive(Debug, Error)]
+pub enum DdsError {
+    #[error("not a DDS file")]
+    InvalidMagic,
+    #[error("invalid DDS header")]
+    InvalidHeader,
+    #[error("unsupported DDS FourCC {0:#010x}")]
+    UnsupportedFourCc(u32),
+    #[error("unsupported DXGI format {0}")]
+    UnsupportedDxgiFormat(u32),
+    #[error("unsupported DDS pixel format")]
+    UnsupportedPixelFormat,
+    #[error("DDS data is truncated, expected {expected} bytes, found {found}")]
+    Truncated { expected: usize, found: usize },
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn dds(flags: u32, size: [u32; 3], mips: u32, pixel_format: [u32; 7], caps2: u32) -> Vec<u8> {
+        let mut header = [0u32; 32];
+        header[0] = DDS_MAGIC;
+        header[1] = DDS_HEADER_SIZE;
+        header[2] = flags;
+        [header[4], header[3], header[6]] = size;
+        header[7] = mips;
+        header[19] = 32;
+        header[20..27].copy_from_slice(&pixel_format);
+        header[28] = caps2;
+
+        header
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let dxt1 = [DDPF_FOURCC, u32::from_le_bytes(*b"DXT1"), 0, 0, 0, 0, 0];
+        let mut bytes = dds(
+            DDSD_MIPMAPCOUNT,
+            [8, 4, 0],
+            3,
+            dxt1,
+            DDSCAPS2_CUBEMAP | 0xFC00,
+        );
+        // 16 + 8 + 8 bytes of mips per face
+        bytes.extend((0..6 * 32).map(|i| i as u8));
+
+        let cube = DdsImage::parse(&bytes).unwrap();
+        assert_eq!((cube.format, cube.kind), (DdsFormat::Bc1, DdsKind::Cube));
+        assert_eq!((cube.layers, cube.mip_levels), (6, 3));
+        assert_eq!(cube.mip_size(2), (2, 1, 1));
+        assert_eq!(cube.level(1, 1), &bytes[128 + 48..128 + 56]);
+
+        let mut skipped = cube.clone();
+        skipped.skip_mips(5);
+        assert_eq!((skipped.width, skipped.height), (2, 1));
+        assert_eq!(skipped.mip_levels, 1);
+        assert_eq!(skipped.level(1, 0), cube.level(1, 2));
+
+        let bgrx = [DDPF_RGB, 0, 32, 0xFF_0000, 0xFF00, 0xFF, 0];
+        let mut bytes = dds(DDSD_DEPTH, [1, 1, 2], 0, bgrx, DDSCAPS2_VOLUME);
+        bytes.extend([1, 2, 3, 0, 4, 5, 6, 0]);
+
+        let volume = DdsImage::parse(&bytes).unwrap();
+        assert_eq!((volume.kind, volume.depth), (DdsKind::Volume, 2));
+        assert_eq!(volume.decode_rgba8().data, [3, 2, 1, 255, 6, 5, 4, 255]);
+
+        bytes.truncate(bytes.len() - 1);
+        assert_eq!(
+            DdsImage::parse(&bytes).unwrap_err().to_string(),
+            "DDS data is truncated, expected 8 bytes, found 7"
+        );
+    }
+}
@@ -0,0 +1,213 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{mpsc, Arc, Mutex},
+    thread,
+};
+
+use xray_oxide_core::filesystem::Filesystem;
+
+use super::{
+    dds::{DdsFormat, DdsImage, DdsKind},
+    thm::TextureDescription,
+    Texture,
+};
+
+/// Where texture names are resolved.
+pub const TEXTURES_ROOT: &str = "$game_textures$";
+
+/// Textures that `texture_lod` leaves at full size, like XRay does for the UI,
+/// light maps and editor textures.
+const FULL_SIZE_PREFIXES: &[&str] = &["ui\\", "ed\\", "lmap", "$user$"];
+
+const MAX_LOADER_THREADS: usize = 4;
+
+/// A texture read and parsed by a loader thread, waiting to be uploaded.
+struct LoadedTexture {
+    name: String,
+    image: anyhow::Result<DdsImage>,
+    description: Option<TextureDescription>,
+}
+
+/// Loads textures by their XRay name, `act\act_stalker`, and keeps them until
+/// the manager is dropped.
+///
+/// Files are read and parsed on loader threads. Until a texture is uploaded by
+/// [`TextureManager::update`], it's drawn with a placeholder.
+pub struct TextureManager {
+    placeholder: Arc<Texture>,
+    textures: HashMap<String, Arc<Texture>>,
+    descriptions: HashMap<String, Arc<TextureDescription>>,
+    pending: HashSet<String>,
+    jobs: mpsc::Sender<String>,
+    loaded: mpsc::Receiver<LoadedTexture>,
+}
+
+impl TextureManager {
+    /// Starts the loader threads. `lod` is how many of the largest mip levels
+    /// to skip, like XRay's `texture_lod`.
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        filesystem: Arc<Filesystem>,
+        lod: u32,
+    ) -> anyhow::Result<TextureManager> {
+        let (jobs, job_receiver) = mpsc::channel::<String>();
+        let (loaded_sender, loaded) = mpsc::channel();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+
+        let threads = thread::available_parallelism()
+            .map_or(1, |threads| threads.get())
+            .min(MAX_LOADER_THREADS);
+
+        for i in 0..threads {
+            let filesystem = filesystem.clone();
+            let job_receiver = job_receiver.clone();
+            let loaded_sender = loaded_sender.clone();
+
+            thread::Builder::new()
+                .name(format!("texture loader {i}"))
+                .spawn(move || loop {
+                    // Stops when the manager, and with it the job sender, is dropped
+                    let Ok(name) = job_receiver.lock().unwrap().recv() else {
+                        break;
+                    };
+
+                    let loaded = load(&filesystem, name, lod);
+                    if loaded_sender.send(loaded).is_err() {
+                        break;
+                    }
+                })?;
+        }
+
+        Ok(TextureManager {
+            placeholder: Arc::new(placeholder(device, queue)),
+            textures: HashMap::new(),
+            descriptions: HashMap::new(),
+            pending: HashSet::new(),
+            jobs,
+            loaded,
+        })
+    }
+
+    /// The texture drawn in place of ones that are loading or failed to load.
+    pub fn placeholder(&self) -> &Arc<Texture> {
+        &self.placeholder
+    }
+
+    /// Returns a texture, or the placeholder while it's still loading.
+    pub fn get(&mut self, name: &str) -> anyhow::Result<Arc<Texture>> {
+        let name = normalize_name(name);
+
+        if let Some(texture) = self.textures.get(&name) {
+            return Ok(texture.clone());
+        }
+
+        if !self.pending.contains(&name) {
+            self.jobs
+                .send(name.clone())
+                .map_err(|_| anyhow::anyhow!("The texture loader threads have stopped"))?;
+            self.pending.insert(name);
+        }
+
+        Ok(self.placeholder.clone())
+    }
+
+    /// Whether a texture is uploaded, or has failed and is drawn with the placeholder for good.
+    pub fn is_loaded(&self, name: &str) -> bool {
+        self.textures.contains_key(&normalize_name(name))
+    }
+
+    /// The `.thm` of a loaded texture, if it has one.
+    pub fn description(&self, name: &str) -> Option<Arc<TextureDescription>> {
+        self.descriptions.get(&normalize_name(name)).cloned()
+    }
+
+    /// Uploads the textures the loader threads have finished, returning their names.
+    ///
+    /// Bind groups made with the placeholder for one of them should be made again.
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) -> Vec<String> {
+        let mut names = Vec::new();
+
+        for loaded in self.loaded.try_iter() {
+            self.pending.remove(&loaded.name);
+
+            let texture = match loaded.image {
+                Ok(image) => {
+                    let texture = Texture::from_dds(device, queue, &image, Some(&loaded.name));
+                    Arc::new(texture)
+                }
+                Err(e) => {
+                    log::error!("Failed to load texture {}: {e}", loaded.name);
+                    self.placeholder.clone()
+                }
+            };
+
+            if let Some(description) = loaded.description {
+                self.descriptions
+                    .insert(loaded.name.clone(), Arc::new(description));
+            }
+
+            self.textures.insert(loaded.name.clone(), texture);
+            names.push(loaded.name);
+        }
+
+        names
+    }
+}
+
+/// Turns a texture name into the form it's cached by: lower case, with `\`
+/// separators and without the `.dds` extension.
+pub fn normalize_name(name: &str) -> String {
+    let name = name.trim().to_lowercase().replace('/', "\\");
+
+    match name.strip_suffix(".dds") {
+        Some(name) => name.to_owned(),
+        None => name,
+    }
+}
+
+fn load(filesystem: &Filesystem, name: String, lod: u32) -> LoadedTexture {
+    let image = filesystem
+        .update_path(&format!("{TEXTURES_ROOT}\\{name}.dds"))
+        .and_then(|path| DdsImage::load(filesystem, path))
+        .map(|mut image| {
+            let full_size = image.kind == DdsKind::Volume
+                || FULL_SIZE_PREFIXES
+                    .iter()
+                    .any(|prefix| name.starts_with(prefix));
+
+            if !full_size {
+                image.skip_mips(lod);
+            }
+
+            image
+        });
+
+    let description = TextureDescription::load(filesystem, &name)
+        .map_err(|e| log::warn!("Failed to read the .thm of {name}: {e}"))
+        .ok()
+        .flatten();
+
+    LoadedTexture {
+        name,
+        image,
+        description,
+    }
+}
+
+/// A 1x1 transparent black texture.
+fn placeholder(device: &wgpu::Device, queue: &wgpu::Queue) -> Texture {
+    let image = DdsImage {
+        format: DdsFormat::Rgba8,
+        srgb: true,
+        kind: DdsKind::Texture2d,
+        width: 1,
+        height: 1,
+        depth: 1,
+        mip_levels: 1,
+        layers: 1,
+        data: vec![0, 0, 0, 0],
+    };
+
+    Texture::from_dds(device, queue, &image, Some("placeholder"))
+}
@@ -0,0 +1,135 @@
+use std::path::Path;
+
+use wgpu::util::DeviceExt;
+use xray_oxide_core::filesystem::Filesystem;
+
+use dds::{DdsImage, DdsKind};
+
+mod assoc;
+mod bc;
+pub mod dds;
+mod manager;
+pub mod thm;
+
+pub use assoc::{DetailTexture, TextureAssociations, TextureInfo, TEXTURES_LTX};
+pub use manager::{normalize_name, TextureManager, TEXTURES_ROOT};
+
+/// A texture on the GPU, with a view of its whole mip chain.
+pub struct Texture {
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    pub view_dimension: wgpu::TextureViewDimension,
+}
+
+impl Texture {
+    /// Loads a DDS file and uploads it.
+    pub fn load<P: AsRef<Path>>(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        filesystem: &Filesystem,
+        path: P,
+    ) -> anyhow::Result<Texture> {
+        let path = path.as_ref();
+        let image = DdsImage::load(filesystem, path)?;
+
+        Ok(Texture::from_dds(device, queue, &image, path.to_str()))
+    }
+
+    /// Uploads an image as it is if the device can sample its format, or
+    /// decoded to RGBA8 if it can't.
+    ///
+    /// Block compressed formats need `TEXTURE_COMPRESSION_BC`, a size that's
+    /// a multiple of 4, and can't be volumes.
+    pub fn from_dds(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        image: &DdsImage,
+        label: Option<&str>,
+    ) -> Texture {
+        let native = image.format.wgpu_format(image.srgb).filter(|_| {
+            !image.format.is_compressed()
+                || (device
+                    .features()
+                    .contains(wgpu::Features::TEXTURE_COMPRESSION_BC)
+                    && image.kind != DdsKind::Volume
+                    && image.width.is_multiple_of(4)
+                    && image.height.is_multiple_of(4))
+        });
+
+        let decoded;
+        let (image, format) = match native {
+            Some(format) => (image, format),
+            None => {
+                log::debug!(
+                    "Decoding {} from {:?} on the CPU",
+                    label.unwrap_or("texture"),
+                    image.format
+                );
+
+                decoded = image.decode_rgba8();
+                let format = decoded.format.wgpu_format(image.srgb).unwrap();
+
+                (&decoded, format)
+            }
+        };
+
+        let (dimension, view_dimension, depth_or_array_layers) = match image.kind {
+            DdsKind::Volume => (
+                wgpu::TextureDimension::D3,
+                wgpu::TextureViewDimension::D3,
+                image.depth,
+            ),
+            DdsKind::Cube if image.layers > 6 => (
+                wgpu::TextureDimension::D2,
+                wgpu::TextureViewDimension::CubeArray,
+                image.layers,
+            ),
+            DdsKind::Cube => (
+                wgpu::TextureDimension::D2,
+                wgpu::TextureViewDimension::Cube,
+                image.layers,
+            ),
+            DdsKind::Texture2d if image.layers > 1 => (
+                wgpu::TextureDimension::D2,
+                wgpu::TextureViewDimension::D2Array,
+                image.layers,
+            ),
+            DdsKind::Texture2d => (
+                wgpu::TextureDimension::D2,
+                wgpu::TextureViewDimension::D2,
+                1,
+            ),
+        };
+
+        let texture = device.create_texture_with_data(
+            queue,
+            &wgpu::TextureDescriptor {
+                label,
+                size: wgpu::Extent3d {
+                    width: image.width,
+                    height: image.height,
+                    depth_or_array_layers,
+                },
+                mip_level_count: image.mip_levels,
+                sample_count: 1,
+                dimension,
+                format,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            },
+            &image.data,
+        );
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label,
+            dimension: Some(view_dimension),
+            ..Default::default()
+        });
+
+        Texture {
+            texture,
+            view,
+            view_dimension,
+        }
+    }
+}
@@ -0,0 +1,276 @@
+use xray_oxide_core::{
+    filesystem::Filesystem,
+    stream::{ReadError, Reader},
+};
+
+use super::TEXTURES_ROOT;
+
+const THM_CHUNK_VERSION: u32 = 0x0810;
+const THM_CHUNK_DATA: u32 = 0x0811;
+const THM_CHUNK_TEXTUREPARAM: u32 = 0x0812;
+const THM_CHUNK_TYPE: u32 = 0x0813;
+const THM_CHUNK_TEXTURE_TYPE: u32 = 0x0814;
+const THM_CHUNK_DETAIL_EXT: u32 = 0x0815;
+const THM_CHUNK_MATERIAL: u32 = 0x0816;
+const THM_CHUNK_BUMP: u32 = 0x0817;
+const THM_CHUNK_EXT_NORMALMAP: u32 = 0x0818;
+
+const FLAG_BINARY_ALPHA: u32 = 1 << 1;
+const FLAG_DIFFUSE_DETAIL: u32 = 1 << 23;
+const FLAG_IMPLICIT_LIGHTED: u32 = 1 << 24;
+const FLAG_HAS_ALPHA: u32 = 1 << 25;
+const FLAG_BUMP_DETAIL: u32 = 1 << 26;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TextureType {
+    #[default]
+    Image,
+    CubeMap,
+    BumpMap,
+    NormalMap,
+    Terrain,
+}
+
+/// The lighting model of a surface.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Material {
+    #[default]
+    OrenNayarBlinn,
+    BlinnPhong,
+    PhongMetal,
+    MetalOrenNayar,
+}
+
+impl Material {
+    /// The material of an index, as stored in `.thm` files and `textures.ltx`.
+    pub fn from_index(index: u32) -> Material {
+        match index {
+            1 => Material::BlinnPhong,
+            2 => Material::PhongMetal,
+            3 => Material::MetalOrenNayar,
+            _ => Material::OrenNayarBlinn,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BumpMode {
+    #[default]
+    None,
+    Use,
+    UseParallax,
+}
+
+/// The `.thm` file next to a texture, what the SDK's texture import settings
+/// say about it, like XRay's `STextureParams`.
+#[derive(Debug, Clone)]
+pub struct TextureDescription {
+    pub version: u16,
+    pub texture_type: TextureType,
+    pub flags: u32,
+    pub width: u32,
+    pub height: u32,
+    /// A detail texture drawn over this one up close, and how often it repeats.
+    pub detail_name: Option<String>,
+    pub detail_scale: f32,
+    pub material: Material,
+    pub material_weight: f32,
+    pub bump_mode: BumpMode,
+    pub bump_name: Option<String>,
+    pub bump_virtual_height: f32,
+    pub ext_normal_map_name: Option<String>,
+}
+
+impl Default for TextureDescription {
+    fn default() -> Self {
+        TextureDescription {
+            version: 0,
+            texture_type: TextureType::Image,
+            flags: 0,
+            width: 0,
+            height: 0,
+            detail_name: None,
+            detail_scale: 1.0,
+            material: Material::OrenNayarBlinn,
+            material_weight: 0.5,
+            bump_mode: BumpMode::None,
+            bump_name: None,
+            bump_virtual_height: 0.05,
+            ext_normal_map_name: None,
+        }
+    }
+}
+
+impl TextureDescription {
+    /// Reads the `.thm` of a texture under `$game_textures$`, `None` if it doesn't have one.
+    pub fn load(filesystem: &Filesystem, name: &str) -> anyhow::Result<Option<TextureDescription>> {
+        let path = filesystem.update_path(&format!("{TEXTURES_ROOT}\\{name}.thm"))?;
+        if !filesystem.exists(&path) {
+            return Ok(None);
+        }
+
+        Ok(Some(TextureDescription::parse(&filesystem.read(&path)?)?))
+    }
+
+    pub fn parse(data: &[u8]) -> Result<TextureDescription, ReadError> {
+        let mut description = TextureDescription::default();
+
+        for chunk in Reader::new(data).chunks() {
+            let mut chunk = chunk?;
+            description.read_chunk(chunk.id, &mut chunk.reader)?;
+        }
+
+        Ok(description)
+    }
+
+    fn read_chunk(&mut self, id: u32, chunk: &mut Reader) -> Result<(), ReadError> {
+        match id {
+            THM_CHUNK_VERSION => self.version = chunk.r_u16()?,
+            // The SDK's thumbnail image and whether it's an object or texture thumbnail
+            THM_CHUNK_DATA | THM_CHUNK_TYPE => {}
+            THM_CHUNK_TEXTUREPARAM => {
+                let _format = chunk.r_u32()?;
+                self.flags = chunk.r_u32()?;
+                // Border colour, fade colour, fade amount and mip filter
+                chunk.advance(16)?;
+                self.width = chunk.r_u32()?;
+                self.height = chunk.r_u32()?;
+            }
+            THM_CHUNK_TEXTURE_TYPE => {
+                self.texture_type = match chunk.r_u32()? {
+                    1 => TextureType::CubeMap,
+                    2 => TextureType::BumpMap,
+                    3 => TextureType::NormalMap,
+                    4 => TextureType::Terrain,
+                    _ => TextureType::Image,
+                }
+            }
+            THM_CHUNK_DETAIL_EXT => {
+                self.detail_name = read_name(chunk)?;
+                self.detail_scale = chunk.r_float()?;
+            }
+            THM_CHUNK_MATERIAL => {
+                self.material = Material::from_index(chunk.r_u32()?);
+                self.material_weight = chunk.r_float()?;
+            }
+            THM_CHUNK_BUMP => {
+                self.bump_virtual_height = chunk.r_float()?;
+                self.bump_mode = match chunk.r_u32()? {
+                    2 => BumpMode::Use,
+                    3 => BumpMode::UseParallax,
+                    _ => BumpMode::None,
+                };
+                self.bump_name = read_name(chunk)?;
+            }
+            THM_CHUNK_EXT_NORMALMAP => self.ext_normal_map_name = read_name(chunk)?,
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    pub fn has_alpha(&self) -> bool {
+        self.flags & FLAG_HAS_ALPHA != 0
+    }
+
+    /// Alpha is only used as a cutout, fully transparent or fully opaque.
+    pub fn has_binary_alpha(&self) -> bool {
+        self.flags & FLAG_BINARY_ALPHA != 0
+    }
+
+    pub fn has_diffuse_detail(&self) -> bool {
+        self.flags & FLAG_DIFFUSE_DETAIL != 0
+    }
+
+    pub fn has_bump_detail(&self) -> bool {
+        self.flags & FLAG_BUMP_DETAIL != 0
+    }
+
+    pub fn is_implicit_lighted(&self) -> bool {
+        self.flags & FLAG_IMPLICIT_LIGHTED != 0
+    }
+}
+
+/// Reads a zero terminated name, `None` if it's empty.
+fn read_name(chunk: &mut Reader) -> Result<Option<String>, ReadError> {
+    let name = chunk.r_stringz()?;
+
+    Ok((!name.is_empty()).then(|| name.into_owned()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A chunk as it's laid out on disk, id and size followed by the data.
+    fn chunk(id: u32, data: &[u8]) -> Vec<u8> {
+        let mut chunk = id.to_le_bytes().to_vec();
+        chunk.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(data);
+        chunk
+    }
+
+    #[test]
+    fn test_parse() {
+        let mut data = chunk(0x0810, &[0x12, 0x00]);
+        data.extend(chunk(0x0811, &[0xff; 8]));
+        data.extend(chunk(
+            0x0812,
+            &[
+                0x0c, 0x00, 0x00, 0x00, // DXT5
+                0x02, 0x00, 0x00, 0x02, // Binary alpha, has alpha
+                0x00, 0x00, 0x00, 0x00, // Border colour
+                0x80, 0x80, 0x80, 0x00, // Fade colour
+                0x00, 0x00, 0x00, 0x00, // Fade amount
+                0x00, 0x00, 0x00, 0x00, // Mip filter
+                0x00, 0x02, 0x00, 0x00, // Width
+                0x00, 0x01, 0x00, 0x00, // Height
+            ],
+        ));
+        data.extend(chunk(0x0813, &[0x01, 0x00, 0x00, 0x00]));
+        data.extend(chunk(0x0814, &[0x00, 0x00, 0x00, 0x00]));
+        data.extend(chunk(
+            0x0815,
+            b"detail\\detail_grnd_grass\0\x00\x00\x80\x40",
+        ));
+        data.extend(chunk(
+            0x0816,
+            &[0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x3f],
+        ));
+        data.extend(chunk(
+            0x0817,
+            b"\xcd\xcc\x4c\x3d\x02\x00\x00\x00act\\act_stalker_bump\0",
+        ));
+        data.extend(chunk(0x0818, b"\0"));
+
+        let description = TextureDescription::parse(&data).unwrap();
+        assert_eq!(description.version, 0x12);
+        assert_eq!(description.texture_type, TextureType::Image);
+        assert_eq!((description.width, description.height), (512, 256));
+        assert!(description.has_alpha() && description.has_binary_alpha());
+        assert!(!description.has_bump_detail());
+        assert_eq!(
+            description.detail_name.as_deref(),
+            Some("detail\\detail_grnd_grass")
+        );
+        assert_eq!(description.detail_scale, 4.0);
+        assert_eq!(description.material, Material::BlinnPhong);
+        assert_eq!(description.material_weight, 0.5);
+        assert_eq!(description.bump_mode, BumpMode::Use);
+        assert_eq!(description.bump_virtual_height, 0.05);
+        assert_eq!(
+            description.bump_name.as_deref(),
+            Some("act\\act_stalker_bump")
+        );
+        assert_eq!(description.ext_normal_map_name, None);
+
+        data.truncate(data.len() - 1);
+        assert!(matches!(
+            TextureDescription::parse(&data),
+            Err(ReadError::TruncatedChunk {
+                id: THM_CHUNK_EXT_NORMALMAP,
+                ..
+            })
+        ));
+    }
+}